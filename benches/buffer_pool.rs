@@ -0,0 +1,158 @@
+// per-packet Vec and copied frames vs pooled Bytes and shared frames, counting
+// heap allocations of the receive -> reorder -> fan-out path
+//
+//     cargo bench --bench buffer_pool
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/stream/utils/pool.rs"]
+mod pool;
+#[allow(dead_code, clippy::single_component_path_imports)]
+#[path = "../src/stream/utils/reorder.rs"]
+mod reorder;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use bytes::Bytes;
+use pool::BufferPool;
+use reorder::RtpPacketReOrder;
+use webrtc_util::{Marshal, Unmarshal};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const PAYLOAD_SIZE: usize = 1400;
+const PACKETS_PER_FRAME: usize = 20;
+const FRAMES: usize = 5000;
+// outputs every frame is handed to
+const VIEWERS: usize = 4;
+
+fn packets() -> Vec<Vec<u8>> {
+    let mut packets = Vec::with_capacity(FRAMES * PACKETS_PER_FRAME);
+    for i in 0..FRAMES * PACKETS_PER_FRAME {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number: i as u16,
+                timestamp: (i / PACKETS_PER_FRAME) as u32 * 3600,
+                ssrc: 200000001,
+                marker: i % PACKETS_PER_FRAME == PACKETS_PER_FRAME - 1,
+                ..Default::default()
+            },
+            payload: Bytes::from(vec![(i % 251) as u8; PAYLOAD_SIZE]),
+        };
+        packets.push(packet.marshal().unwrap().to_vec());
+    }
+    packets
+}
+
+// a new buffer per packet, the payload copied out by unmarshal and every
+// frame concatenated, then copied for each viewer
+fn legacy(packets: &[Vec<u8>]) -> usize {
+    let mut packets_reorder = RtpPacketReOrder::new(3, "");
+    let mut viewers: Vec<Vec<Vec<u8>>> = vec![vec![]; VIEWERS];
+    let mut frames = 0;
+    for datagram in packets {
+        let recv_buff = datagram.clone();
+        let mut b: &[u8] = &recv_buff;
+        let packet = rtp::packet::Packet::unmarshal(&mut b).unwrap();
+        if packets_reorder.feed_rtp(packet) {
+            let frame = packets_reorder.pop_frame().to_vec();
+            for viewer in viewers.iter_mut() {
+                viewer.push(frame.clone());
+                // a small window of frames in flight per viewer
+                if viewer.len() > 8 {
+                    viewer.remove(0);
+                }
+            }
+            frames += 1;
+        }
+    }
+    frames
+}
+
+// packets packed into pooled slabs, payloads and frames sharing their memory
+fn pooled(packets: &[Vec<u8>]) -> usize {
+    let mut buffer_pool = BufferPool::new(256 * 1024);
+    let mut packets_reorder = RtpPacketReOrder::new(3, "");
+    let mut viewers: Vec<Vec<reorder::Frame>> = vec![vec![]; VIEWERS];
+    let mut frames = 0;
+    for datagram in packets {
+        // the kernel writes into the slab, here a copy stands in for it
+        let slab = buffer_pool.slab(datagram.len());
+        slab.extend_from_slice(datagram);
+        let recv_buff = slab.split().freeze();
+        let mut b = recv_buff.clone();
+        let packet = rtp::packet::Packet::unmarshal(&mut b).unwrap();
+        if packets_reorder.feed_rtp(packet) {
+            let frame = packets_reorder.pop_frame();
+            for viewer in viewers.iter_mut() {
+                viewer.push(frame.clone());
+                if viewer.len() > 8 {
+                    viewer.remove(0);
+                }
+            }
+            frames += 1;
+        }
+    }
+    println!(
+        "{:<8} slabs allocated: {}, reused: {}",
+        "",
+        buffer_pool.allocated(),
+        buffer_pool.reused()
+    );
+    frames
+}
+
+fn bench(name: &str, packets: &[Vec<u8>], f: fn(&[Vec<u8>]) -> usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let started = Instant::now();
+    let frames = f(packets);
+    let elapsed = started.elapsed().as_secs_f64();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes;
+
+    println!(
+        "{:<8} {:>10.0} pkt/s {:>8.1} Mbit/s {:>6} frames {:>7.2} allocs/pkt {:>9.0} bytes/pkt",
+        name,
+        packets.len() as f64 / elapsed,
+        (packets.len() * PAYLOAD_SIZE) as f64 * 8.0 / elapsed / 1e6,
+        frames,
+        allocations as f64 / packets.len() as f64,
+        allocated_bytes as f64 / packets.len() as f64,
+    );
+}
+
+fn main() {
+    let packets = packets();
+    for _ in 0..2 {
+        bench("legacy", &packets, legacy);
+        bench("pooled", &packets, pooled);
+    }
+}
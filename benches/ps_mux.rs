@@ -0,0 +1,247 @@
+// ps muxer round trip against the demuxer: synthetic h264 and g711 frames,
+// then the *.output.ps dumps of the current directory or the given files are
+// demuxed, muxed again and demuxed again, any difference fails
+//
+//     cargo bench --bench ps_mux [-- udp.10000.output.ps ...]
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/stream/utils/ps.rs"]
+mod ps;
+#[allow(dead_code)]
+#[path = "../src/stream/utils/psmux.rs"]
+mod psmux;
+#[allow(dead_code, clippy::single_component_path_imports)]
+#[path = "../src/stream/utils/reorder.rs"]
+mod reorder;
+
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Instant;
+
+use psmux::{Codec, PsMuxer};
+
+const FRAMES: u64 = 2500;
+const GOP: u64 = 50;
+// 25 fps video and 20 ms audio, in 90 khz
+const VIDEO_DURATION: u64 = 3600;
+const AUDIO_DURATION: u64 = 1800;
+
+// elementary stream frame, payloads of its pes packets joined
+#[derive(Debug, PartialEq)]
+struct EsFrame {
+    stream_id: u8,
+    pts: u64,
+    dts: u64,
+    data: Vec<u8>,
+}
+
+fn es_frames(buff: &[u8]) -> Vec<EsFrame> {
+    let mut frames: Vec<EsFrame> = vec![];
+    for pes in ps::pes_packets(buff) {
+        match (pes.pts, pes.dts) {
+            (Some(pts), Some(dts)) => frames.push(EsFrame {
+                stream_id: pes.stream_id,
+                pts,
+                dts,
+                data: pes.payload.to_vec(),
+            }),
+            // continues the previous frame of its stream, a dump may start mid frame
+            _ => {
+                let previous = frames
+                    .iter_mut()
+                    .rev()
+                    .find(|f| f.stream_id == pes.stream_id);
+                if let Some(frame) = previous {
+                    frame.data.extend_from_slice(pes.payload);
+                }
+            }
+        }
+    }
+    frames
+}
+
+// pseudo random bytes without start codes
+fn fill(data: &mut Vec<u8>, len: usize, seed: u64) {
+    let mut x = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+    for _ in 0..len {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        data.push(((x >> 33) as u8) | 0x01);
+    }
+}
+
+fn synthetic() -> Vec<EsFrame> {
+    let mut frames = vec![];
+    for i in 0..FRAMES {
+        let mut video = vec![];
+        if i.is_multiple_of(GOP) {
+            // sps, pps and a 150 kb idr, more than two pes packets
+            video.extend_from_slice(&[0, 0, 0, 1, 0x67]);
+            fill(&mut video, 16, i);
+            video.extend_from_slice(&[0, 0, 0, 1, 0x68]);
+            fill(&mut video, 4, i);
+            video.extend_from_slice(&[0, 0, 0, 1, 0x65]);
+            fill(&mut video, 150 * 1024, i);
+        } else {
+            video.extend_from_slice(&[0, 0, 0, 1, 0x41]);
+            fill(&mut video, 2000 + (i as usize * 37) % 30000, i);
+        }
+        // b frames, dts behind pts, across the 33 bit wrap
+        let dts = (i * VIDEO_DURATION + (1 << 33) - 100 * VIDEO_DURATION) & 0x1_FFFF_FFFF;
+        let pts = (dts + VIDEO_DURATION * (i % 3)) & 0x1_FFFF_FFFF;
+        frames.push(EsFrame {
+            stream_id: 0xE0,
+            pts,
+            dts,
+            data: video,
+        });
+        for j in 0..2 {
+            let mut audio = vec![];
+            fill(&mut audio, 160, i * 2 + j);
+            let pts = ((i * 2 + j) * AUDIO_DURATION) & 0x1_FFFF_FFFF;
+            frames.push(EsFrame {
+                stream_id: 0xC0,
+                pts,
+                dts: pts,
+                data: audio,
+            });
+        }
+    }
+    frames
+}
+
+fn mux(muxer: &mut PsMuxer, frames: &[EsFrame]) -> (Vec<u8>, Vec<bool>) {
+    let mut buff = vec![];
+    let mut keyframes = vec![];
+    for frame in frames {
+        let ps_frame = if ps::is_video(frame.stream_id) {
+            muxer.mux_video(&frame.data, frame.pts, frame.dts)
+        } else {
+            muxer.mux_audio(&frame.data, frame.pts)
+        };
+        assert_eq!(ps_frame.keyframe, ps::is_keyframe(&ps_frame.payloads[0]));
+        keyframes.push(ps_frame.keyframe);
+        buff.extend_from_slice(&ps_frame.to_vec());
+    }
+    (buff, keyframes)
+}
+
+fn check(name: &str, expected: &[EsFrame], buff: &[u8]) -> bool {
+    let frames = es_frames(buff);
+    if frames.len() != expected.len() {
+        println!(
+            "{}: {} frames, expected {}",
+            name,
+            frames.len(),
+            expected.len()
+        );
+        return false;
+    }
+    match frames.iter().zip(expected).position(|(a, b)| a != b) {
+        None => true,
+        Some(i) => {
+            let (a, b) = (&frames[i], &expected[i]);
+            println!(
+                "{}: frame {} differs, stream_id: {:#x}/{:#x}, pts: {}/{}, dts: {}/{}, len: {}/{}",
+                name,
+                i,
+                a.stream_id,
+                b.stream_id,
+                a.pts,
+                b.pts,
+                a.dts,
+                b.dts,
+                a.data.len(),
+                b.data.len()
+            );
+            false
+        }
+    }
+}
+
+fn round_trip_synthetic() -> bool {
+    let frames = synthetic();
+    let mut muxer = PsMuxer::new(Some(Codec::H264), Some(Codec::G711A));
+    let started = Instant::now();
+    let (buff, keyframes) = mux(&mut muxer, &frames);
+    let elapsed = started.elapsed();
+
+    let headers = ps::headers(&bytes::Bytes::copy_from_slice(&buff)).unwrap_or_default();
+    let codecs = ps::codecs(&headers);
+    let keyframes = keyframes.iter().filter(|k| **k).count() as u64;
+    println!(
+        "synthetic: {} frames, {} bytes, keyframes: {}, codecs: {:?}, mux: {:?} ({:.0} MB/s)",
+        frames.len(),
+        buff.len(),
+        keyframes,
+        codecs,
+        elapsed,
+        buff.len() as f64 / elapsed.as_secs_f64() / 1e6
+    );
+    codecs == ("h264", "g711a") && keyframes == FRAMES / GOP && check("synthetic", &frames, &buff)
+}
+
+fn round_trip_dump(path: &PathBuf) -> bool {
+    let name = path.display().to_string();
+    let buff = match std::fs::read(path) {
+        Err(e) => {
+            println!("{}: read error, e: {:?}", name, e);
+            return false;
+        }
+        Ok(buff) => buff,
+    };
+    let frames = es_frames(&buff);
+
+    // codecs of the first psm, the dumps hold whole packs
+    let shared = bytes::Bytes::from(buff.clone());
+    let mut codecs = ("", "");
+    let mut offset = 0;
+    while let Some(at) = buff[offset..]
+        .windows(4)
+        .position(|w| w == [0, 0, 1, ps::PACK_HEADER])
+    {
+        if let Some(headers) = ps::headers(&shared.slice(offset + at..)) {
+            codecs = ps::codecs(&headers);
+            break;
+        }
+        offset += at + 4;
+    }
+    let mut muxer = PsMuxer::new(Codec::from_name(codecs.0), Codec::from_name(codecs.1));
+    let (remuxed, _) = mux(&mut muxer, &frames);
+    let ok = check(&name, &frames, &remuxed);
+    println!(
+        "{}: {} frames, codecs: {:?}, {} -> {} bytes, {}",
+        name,
+        frames.len(),
+        codecs,
+        buff.len(),
+        remuxed.len(),
+        if ok { "ok" } else { "failed" }
+    );
+    ok
+}
+
+fn main() {
+    let mut paths: Vec<PathBuf> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(PathBuf::from)
+        .collect();
+    if paths.is_empty() {
+        if let Ok(entries) = std::fs::read_dir(".") {
+            paths = entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.to_string_lossy().ends_with(".output.ps"))
+                .collect();
+        }
+    }
+
+    let mut ok = round_trip_synthetic();
+    for path in &paths {
+        ok &= round_trip_dump(path);
+    }
+    if !ok {
+        exit(1);
+    }
+}
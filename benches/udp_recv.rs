@@ -0,0 +1,94 @@
+// tokio recv_from per datagram vs recv_batch, over loopback with a blasting sender
+//
+//     cargo bench --bench udp_recv
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/stream/utils/socket.rs"]
+mod socket;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use socket::{RecvBatch, SocketOptions};
+
+const PACKET_SIZE: usize = 1400;
+const MAX_DATAGRAM_SIZE: usize = 65535;
+const SLAB_SIZE: usize = 256 * 1024;
+const DURATION: Duration = Duration::from_secs(3);
+
+fn start_sender(to: SocketAddr, stop: Arc<AtomicBool>) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let packet = vec![0x80u8; PACKET_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let _ = socket.send_to(&packet, to);
+        }
+    })
+}
+
+async fn bench(name: &str, batch_size: usize) {
+    let options = SocketOptions {
+        recv_buffer_size: 8 * 1024 * 1024,
+        ..Default::default()
+    };
+    let socket = socket::bind_udp(&"127.0.0.1:0".parse().unwrap(), &options).unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let sender = start_sender(socket.local_addr().unwrap(), stop.clone());
+
+    let mut packets = 0u64;
+    let mut wakeups = 0u64;
+    let mut drops = 0u32;
+    let started = Instant::now();
+    if batch_size == 0 {
+        // the baseline, one datagram per wakeup, the drop counter is not read
+        let mut buff = vec![0u8; MAX_DATAGRAM_SIZE];
+        while started.elapsed() < DURATION {
+            socket.recv_from(&mut buff).await.unwrap();
+            packets += 1;
+            wakeups += 1;
+        }
+    } else {
+        let mut batch = RecvBatch::new(batch_size, MAX_DATAGRAM_SIZE);
+        let mut slab = BytesMut::new();
+        while started.elapsed() < DURATION {
+            // a fresh slab once the packets took its room, as the pool does
+            if slab.capacity() < batch.slab_size() {
+                slab = BytesMut::with_capacity(SLAB_SIZE.max(batch.slab_size()));
+            }
+            packets += socket::recv_batch(&socket, &mut batch, &mut slab)
+                .await
+                .unwrap() as u64;
+            wakeups += 1;
+            drops = batch.kernel_drops().unwrap_or(drops);
+            batch.drain().for_each(drop);
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    stop.store(true, Ordering::Relaxed);
+    sender.join().unwrap();
+    println!(
+        "{:<16} {:>10.0} pkt/s {:>8.1} Mbit/s {:>6.2} pkt/wakeup  kernel drops: {}",
+        name,
+        packets as f64 / elapsed,
+        packets as f64 * PACKET_SIZE as f64 * 8.0 / elapsed / 1e6,
+        packets as f64 / wakeups as f64,
+        drops
+    );
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        bench("recv_from", 0).await;
+        for batch_size in [1, 8, 16, 32, 64] {
+            bench(&format!("recv_batch({})", batch_size), batch_size).await;
+        }
+    });
+}
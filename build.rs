@@ -8,8 +8,12 @@ fn main() -> io::Result<()> {
     replace_version_in_rs()?;
 
     println!("cargo:rerun-if-changed=src/proto/gss.proto");
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
     tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("gss_descriptor.bin"))
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        // omitted json fields take the proto3 defaults, like on the wire
+        .message_attribute(".", "#[serde(default)]")
         .compile_well_known_types(true)
         .compile_protos(&["src/proto/gss.proto"], &["proto"])
         .unwrap();
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::rpc::server::MyGbtStreamService;
+use crate::utils::auth::{AuthConfig, HEADER_KEY_ID};
+
+// api bodies are small json messages
+const MAX_SIGNED_BODY_SIZE: usize = 1024 * 1024;
+
+// same credentials as the grpc api, see utils::auth
+pub async fn require_auth(
+    State(service): State<Arc<MyGbtStreamService>>,
+    ConnectInfo(remote): ConnectInfo<std::net::SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let auth = service.config().auth.clone();
+
+    // the body digest is signed, so it is read before the handler gets it
+    let (parts, body) = request.into_parts();
+    let (signed, body) = if auth.is_enabled() && parts.headers.contains_key(HEADER_KEY_ID) {
+        let bytes = match axum::body::to_bytes(body, MAX_SIGNED_BODY_SIZE).await {
+            Err(e) => {
+                tracing::warn!("http body read error, uri: {}, e: {:?}", &parts.uri, e);
+                return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response();
+            }
+            Ok(bytes) => bytes,
+        };
+        let path_and_query = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or_else(|| parts.uri.path());
+        let signed = AuthConfig::http_request(parts.method.as_str(), path_and_query, &bytes);
+        (signed, Body::from(bytes))
+    } else {
+        (String::new(), body)
+    };
+
+    match auth.check(
+        |key| parts.headers.get(key).and_then(|v| v.to_str().ok()),
+        &signed,
+    ) {
+        Ok(()) => next.run(Request::from_parts(parts, body)).await,
+        Err(reason) => {
+            tracing::warn!(
+                "unauthenticated http call, remote: {}, uri: {}, reason: {}",
+                remote,
+                &parts.uri,
+                &reason
+            );
+            (StatusCode::UNAUTHORIZED, reason).into_response()
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tonic::Request;
+
+use super::streams::{http_status, status_response};
+use crate::gss::{
+    gbt_stream_service_server::GbtStreamService, ReloadConfigRequest, SetLogLevelRequest,
+};
+use crate::rpc::server::MyGbtStreamService;
+
+pub async fn reload_config(State(service): State<Arc<MyGbtStreamService>>) -> Response {
+    match GbtStreamService::reload_config(service.as_ref(), Request::new(ReloadConfigRequest {}))
+        .await
+    {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+pub async fn set_log_level(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Json(req): Json<SetLogLevelRequest>,
+) -> Response {
+    match GbtStreamService::set_log_level(service.as_ref(), Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::BytesMut;
+use tokio::io::AsyncReadExt;
+
+use crate::rpc::server::MyGbtStreamService;
+use crate::stream::handler::download;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+// complete download files, a file still being written is .part and not found
+pub async fn get_download(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Path(name): Path<String>,
+) -> Response {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            format!("download not found: {}", &name),
+        )
+    };
+    if !download::is_file_name(&name) {
+        return not_found().into_response();
+    }
+    let path = std::path::Path::new(&service.config().download_dir).join(&name);
+    let file = match tokio::fs::File::open(&path).await {
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::error!("File::open({}) error, e: {:?}", path.display(), e);
+            }
+            return not_found().into_response();
+        }
+        Ok(file) => file,
+    };
+    let len = match file.metadata().await {
+        Err(e) => {
+            tracing::error!("File::metadata({}) error, e: {:?}", path.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Ok(metadata) => metadata.len(),
+    };
+
+    let chunks = futures::stream::unfold(file, |mut file| async move {
+        let mut buff = BytesMut::with_capacity(READ_CHUNK_SIZE);
+        match file.read_buf(&mut buff).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(buff.freeze()), file)),
+            Err(e) => {
+                tracing::error!("File::read error, e: {:?}", e);
+                Some((Err(e), file))
+            }
+        }
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "video/mp4".to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", &name),
+            ),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response()
+}
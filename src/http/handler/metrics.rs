@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use prometheus::{
+    proto::MetricFamily, Encoder, GaugeVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, TextEncoder,
+};
+
+use crate::rpc::server::MyGbtStreamService;
+use crate::utils::metrics::metrics;
+
+// per-session and pool metrics are built at scrape time, so freed sessions disappear
+fn service_metric_families(service: &MyGbtStreamService) -> prometheus::Result<Vec<MetricFamily>> {
+    let mut families = vec![];
+
+    let usage = service.port_pool_usage();
+    let ports = IntGaugeVec::new(
+        Opts::new("msprs_stream_ports", "stream port pool by state"),
+        &["state"],
+    )?;
+    ports.with_label_values(&["free"]).set(usage.free as i64);
+    ports.with_label_values(&["used"]).set(usage.used as i64);
+    ports
+        .with_label_values(&["cooling"])
+        .set(usage.cooling as i64);
+    ports
+        .with_label_values(&["quarantined"])
+        .set(usage.quarantined as i64);
+    families.extend(prometheus::core::Collector::collect(&ports));
+
+    let labels = &["gb_code", "stream_id", "port"];
+    let sessions = IntGauge::new("msprs_active_sessions", "active stream sessions")?;
+    let packets = IntCounterVec::new(
+        Opts::new("msprs_session_rtp_packets_total", "rtp packets received"),
+        labels,
+    )?;
+    let bytes = IntCounterVec::new(
+        Opts::new("msprs_session_rtp_bytes_total", "rtp bytes received"),
+        labels,
+    )?;
+    let lost = IntGaugeVec::new(
+        Opts::new("msprs_session_packets_lost", "cumulative rtp packets lost"),
+        labels,
+    )?;
+    let jitter = GaugeVec::new(
+        Opts::new("msprs_session_jitter_seconds", "rtp interarrival jitter"),
+        labels,
+    )?;
+    let kernel_drops = IntCounterVec::new(
+        Opts::new(
+            "msprs_session_kernel_drops_total",
+            "rtp packets dropped by a full socket receive buffer",
+        ),
+        labels,
+    )?;
+    let fps = IntGaugeVec::new(Opts::new("msprs_session_fps", "frames per second"), labels)?;
+    let reorder_drops = IntCounterVec::new(
+        Opts::new(
+            "msprs_session_reorder_drops_total",
+            "rtp packets dropped by the reorder buffer as expired",
+        ),
+        labels,
+    )?;
+
+    if let Ok(join_handlers) = service.join_handlers.lock() {
+        sessions.set(join_handlers.len() as i64);
+        for (port, task) in join_handlers.iter() {
+            let info = &task.stream_handler.info;
+            let stream_id = info.stream_id.to_string();
+            let port = port.to_string();
+            let values = [info.gb_code.as_str(), stream_id.as_str(), port.as_str()];
+            let stats = task.stream_handler.stats.snapshot();
+
+            packets.with_label_values(&values).inc_by(stats.rtp_packets);
+            bytes.with_label_values(&values).inc_by(stats.rtp_bytes);
+            lost.with_label_values(&values).set(stats.packets_lost);
+            jitter
+                .with_label_values(&values)
+                .set(stats.jitter as f64 / crate::stream::handler::rtcp::RTP_CLOCK_RATE as f64);
+            kernel_drops
+                .with_label_values(&values)
+                .inc_by(stats.kernel_drops);
+            fps.with_label_values(&values).set(stats.fps as i64);
+            reorder_drops
+                .with_label_values(&values)
+                .inc_by(stats.reorder_drops);
+        }
+    }
+
+    families.extend(prometheus::core::Collector::collect(&sessions));
+    families.extend(prometheus::core::Collector::collect(&packets));
+    families.extend(prometheus::core::Collector::collect(&bytes));
+    families.extend(prometheus::core::Collector::collect(&lost));
+    families.extend(prometheus::core::Collector::collect(&jitter));
+    families.extend(prometheus::core::Collector::collect(&kernel_drops));
+    families.extend(prometheus::core::Collector::collect(&fps));
+    families.extend(prometheus::core::Collector::collect(&reorder_drops));
+    Ok(families)
+}
+
+pub async fn get_metrics(State(service): State<Arc<MyGbtStreamService>>) -> impl IntoResponse {
+    let mut families = metrics().registry.gather();
+    match service_metric_families(&service) {
+        Err(e) => tracing::error!("service_metric_families error, e: {:?}", e),
+        Ok(f) => families.extend(f),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buff = vec![];
+    if let Err(e) = encoder.encode(&families, &mut buff) {
+        tracing::error!("prometheus encode error, e: {:?}", e);
+    }
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buff,
+    )
+}
@@ -0,0 +1,5 @@
+pub mod config;
+pub mod downloads;
+pub mod metrics;
+pub mod streams;
+pub mod talk;
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use tonic::Request;
+
+use crate::gss::{
+    gbt_stream_service_server::GbtStreamService, BindStreamPortRequest, FreeStreamPortRequest,
+    FreeStreamPortResponse, ListStreamsRequest, ResponseCode, StartSendRtpRequest,
+    StopSendRtpRequest,
+};
+use crate::rpc::server::MyGbtStreamService;
+
+// the body carries the grpc response code, the http status only summarizes it
+pub fn http_status(code: ResponseCode) -> StatusCode {
+    match code {
+        ResponseCode::Ok => StatusCode::OK,
+        ResponseCode::InvalidSsrc
+        | ResponseCode::InvalidDeviceIp
+        | ResponseCode::InvalidLogLevel
+        | ResponseCode::AddressFamilyUnavailable
+        | ResponseCode::MediaInterfaceNotFound
+        | ResponseCode::InvalidDestination
+        | ResponseCode::InvalidAudioFile => StatusCode::BAD_REQUEST,
+        ResponseCode::StreamNotFound
+        | ResponseCode::SendRtpNotFound
+        | ResponseCode::TalkNotFound => StatusCode::NOT_FOUND,
+        ResponseCode::NoPortsFree | ResponseCode::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        ResponseCode::BindPortError
+        | ResponseCode::RunStreamServiceError
+        | ResponseCode::ConfigError
+        | ResponseCode::SendRtpError
+        | ResponseCode::TalkError
+        | ResponseCode::DownloadError => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+pub fn status_response(status: tonic::Status) -> Response {
+    tracing::error!("http api error, status: {:?}", &status);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        status.message().to_string(),
+    )
+        .into_response()
+}
+
+pub async fn bind_stream(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Json(req): Json<BindStreamPortRequest>,
+) -> Response {
+    match service.bind_stream_port(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+pub async fn free_stream(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Path((gb_code, stream_id)): Path<(String, u32)>,
+) -> Response {
+    let port = match service.find_port(&gb_code, stream_id) {
+        None => {
+            let reply = FreeStreamPortResponse {
+                code: ResponseCode::StreamNotFound.into(),
+                message: format!("stream not found: {}/{}", &gb_code, stream_id),
+            };
+            return (StatusCode::NOT_FOUND, Json(reply)).into_response();
+        }
+        Some(port) => port,
+    };
+
+    let req = FreeStreamPortRequest {
+        gb_code,
+        stream_id,
+        media_server_ip: service.config().my_ip.clone(),
+        media_server_port: port as u32,
+    };
+    match service.free_stream_port(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+pub async fn list_streams(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Query(req): Query<ListStreamsRequest>,
+) -> Response {
+    match service.list_streams(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+pub async fn start_send_rtp(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Json(req): Json<StartSendRtpRequest>,
+) -> Response {
+    match service.start_send_rtp(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+pub async fn stop_send_rtp(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Query(req): Query<StopSendRtpRequest>,
+) -> Response {
+    match service.stop_send_rtp(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+// the session's program stream as is, from its latest keyframe on
+pub async fn play_ps(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Path((gb_code, stream_id)): Path<(String, u32)>,
+) -> Response {
+    let subscription = match service.find_handler(&gb_code, stream_id) {
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("stream not found: {}/{}", &gb_code, stream_id),
+            )
+                .into_response();
+        }
+        Some(handler) => handler.hub.subscribe(),
+    };
+
+    let frames = futures::stream::unfold(subscription, |mut subscription| async move {
+        let frame = subscription.recv().await?;
+        Some((frame, subscription))
+    });
+    let body = frames.flat_map(|frame| {
+        futures::stream::iter(
+            frame
+                .payloads
+                .into_iter()
+                .map(Ok::<_, std::convert::Infallible>),
+        )
+    });
+    (
+        [(header::CONTENT_TYPE, "video/mp2p")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bytes::Bytes;
+use serde::Deserialize;
+use tonic::Request;
+
+use super::streams::{http_status, status_response};
+use crate::gss::{gbt_stream_service_server::GbtStreamService, StartTalkRequest, StopTalkRequest};
+use crate::rpc::server::MyGbtStreamService;
+
+pub async fn start_talk(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Json(req): Json<StartTalkRequest>,
+) -> Response {
+    match service.start_talk(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+pub async fn stop_talk(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Query(req): Query<StopTalkRequest>,
+) -> Response {
+    match service.stop_talk(Request::new(req)).await {
+        Err(status) => status_response(status),
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            (http_status(reply.code()), Json(reply)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    token: String,
+}
+
+// binary messages of the talk's input_format, browsers can't set auth
+// headers, the talk's token stands in for them
+pub async fn talk_ws(
+    State(service): State<Arc<MyGbtStreamService>>,
+    Path(gb_code): Path<String>,
+    Query(query): Query<FeedQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let feed_tx = service
+        .talks
+        .lock()
+        .unwrap()
+        .get(&gb_code)
+        .and_then(|talk| talk.take_feed(&query.token));
+    let Some(feed_tx) = feed_tx else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("no talk to feed: {}", &gb_code),
+        )
+            .into_response();
+    };
+
+    ws.on_upgrade(move |mut socket| async move {
+        tracing::info!("talk websocket open, gb_code: {}", &gb_code);
+        while let Some(message) = socket.recv().await {
+            match message {
+                Err(e) => {
+                    tracing::error!("talk websocket recv error, e: {:?}", e);
+                    break;
+                }
+                Ok(Message::Binary(data)) => {
+                    // the talk is gone
+                    if feed_tx.send(Bytes::from(data)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Message::Close(_)) => break,
+                Ok(_) => {}
+            }
+        }
+        tracing::info!("talk websocket closed, gb_code: {}", &gb_code);
+    })
+}
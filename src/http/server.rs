@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use axum::{
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+
+use super::{auth, handler};
+use crate::rpc::server::MyGbtStreamService;
+use crate::utils::net;
+
+pub fn router(service: Arc<MyGbtStreamService>) -> Router {
+    // rest mirror of GbtStreamService, json bodies are the grpc messages
+    let api = Router::new()
+        .route(
+            "/api/streams",
+            get(handler::streams::list_streams).post(handler::streams::bind_stream),
+        )
+        .route(
+            "/api/streams/:gb_code/:stream_id",
+            delete(handler::streams::free_stream),
+        )
+        .route(
+            "/api/streams/:gb_code/:stream_id/ps",
+            get(handler::streams::play_ps),
+        )
+        .route(
+            "/api/send_rtp",
+            post(handler::streams::start_send_rtp).delete(handler::streams::stop_send_rtp),
+        )
+        .route(
+            "/api/talk",
+            post(handler::talk::start_talk).delete(handler::talk::stop_talk),
+        )
+        .route(
+            "/api/downloads/:name",
+            get(handler::downloads::get_download),
+        )
+        .route("/api/config/reload", post(handler::config::reload_config))
+        .route("/api/log/level", put(handler::config::set_log_level))
+        .route_layer(middleware::from_fn_with_state(
+            service.clone(),
+            auth::require_auth,
+        ));
+
+    Router::new()
+        .route("/metrics", get(handler::metrics::get_metrics))
+        // authorized by the talk's token
+        .route("/api/talk/:gb_code/ws", get(handler::talk::talk_ws))
+        .merge(api)
+        .with_state(service)
+}
+
+pub async fn run_forever(
+    host: &str,
+    port: u16,
+    service: Arc<MyGbtStreamService>,
+) -> Result<tokio::task::JoinHandle<()>, std::io::Error> {
+    let local_addr = net::host_port(host, port);
+    let listener = match tokio::net::TcpListener::bind(&local_addr).await {
+        Err(e) => {
+            tracing::error!("http TcpListener::bind({}) error, e: {:?}", &local_addr, e);
+            return Err(e);
+        }
+        Ok(listener) => listener,
+    };
+    tracing::info!("http TcpListener::bind({}) ok", &local_addr);
+
+    let app = router(service.clone());
+    Ok(tokio::spawn(async move {
+        if let Err(e) = axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move { service.wait_closed().await })
+        .await
+        {
+            tracing::error!("http serve error, e: {:?}", e);
+        }
+    }))
+}
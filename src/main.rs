@@ -1,3 +1,4 @@
+pub mod http;
 pub mod rpc;
 pub mod stream;
 pub mod utils;
@@ -8,8 +9,11 @@ use std::{path::PathBuf, process::exit};
 use tracing::{self, error};
 use utils::config::Config;
 
+#[allow(non_camel_case_types)]
 pub mod gss {
     tonic::include_proto!("gss");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("gss_descriptor");
 }
 
 #[derive(Parser, Debug)]
@@ -28,16 +32,98 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = Config::load_from_file(&args.config).unwrap();
 
-    // open daily log
-    let _log = utils::log::init(&config);
+    // open log
+    let _log = match utils::log::init(&config) {
+        Err(e) => {
+            eprintln!("utils::log::init error, e: {:?}", e);
+            exit(1);
+        }
+        Ok(guards) => guards,
+    };
     // serve grpc
-    let rpc_addr = format!("{}:{}", &config.host, &config.grpc_port);
-    let rpc_service = rpc::server::MyGbtStreamService::new(config.clone());
-    match tonic::transport::Server::builder()
-        .add_service(gss::gbt_stream_service_server::GbtStreamServiceServer::new(
-            rpc_service,
+    let rpc_addr = utils::net::host_port(&config.host, config.grpc_port);
+    let rpc_service = std::sync::Arc::new(rpc::server::MyGbtStreamService::new(
+        args.config.clone(),
+        config.clone(),
+    ));
+    utils::otel::start_metrics(&rpc_service);
+    let _watchdog = rpc_service.start_watchdog();
+    let _reload = rpc_service.start_reload();
+    let webhook = utils::webhook::start(rpc_service.watch_config(), &rpc_service.event_tx);
+    // serve http
+    let mut http_join_handle = None;
+    if config.http_port != 0 {
+        match http::server::run_forever(&config.host, config.http_port, rpc_service.clone()).await {
+            Err(e) => tracing::error!("http::server::run_forever error, e: {:?}", e),
+            Ok(h) => http_join_handle = Some(h),
+        }
+    }
+
+    // on SIGTERM/SIGINT: refuse binds, drain sessions, deliver final events, then stop serving
+    let shutdown_service = rpc_service.clone();
+    let shutdown = async move {
+        utils::signal::wait_for_shutdown().await;
+        let shutdown_timeout =
+            std::time::Duration::from_secs(shutdown_service.config().shutdown_timeout);
+        let deadline = tokio::time::Instant::now() + shutdown_timeout;
+        shutdown_service.shutdown(deadline).await;
+        if tokio::time::timeout_at(deadline, webhook.flush())
+            .await
+            .is_err()
+        {
+            tracing::error!("shutdown deadline exceeded, webhooks not delivered");
+        }
+        shutdown_service.close();
+    };
+    // health and reflection
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let _health = rpc_service.start_health(health_reporter);
+    let reflection_builder = || {
+        tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(gss::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+    };
+    // v1alpha for older grpcurl
+    let reflection_service = reflection_builder().build_v1().unwrap();
+    let reflection_service_v1alpha = reflection_builder().build_v1alpha().unwrap();
+
+    // tls, and mtls with a client ca
+    let mut server_builder = tonic::transport::Server::builder();
+    match config.auth.server_tls_config() {
+        Err(e) => {
+            error!("load grpc tls config error, e: {:?}", e);
+            exit(1);
+        }
+        Ok(None) => {}
+        Ok(Some(tls_config)) => {
+            tracing::info!(
+                "grpc tls enabled, mtls: {}",
+                !config.auth.tls_client_ca.is_empty()
+            );
+            server_builder = match server_builder.tls_config(tls_config) {
+                Err(e) => {
+                    error!("grpc tls config error, e: {:?}", e);
+                    exit(1);
+                }
+                Ok(b) => b,
+            };
+        }
+    }
+    if !config.auth.is_enabled() {
+        tracing::warn!("grpc control api authentication is disabled");
+    }
+
+    let auth_interceptor = rpc::auth::AuthInterceptor::new(rpc_service.watch_config());
+    match server_builder
+        .layer(rpc::auth::grpc_path_layer())
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(reflection_service_v1alpha)
+        .add_service(tonic::service::interceptor::InterceptedService::new(
+            gss::gbt_stream_service_server::GbtStreamServiceServer::from_arc(rpc_service),
+            auth_interceptor,
         ))
-        .serve(rpc_addr.parse().unwrap())
+        .serve_with_shutdown(rpc_addr.parse().unwrap(), shutdown)
         .await
     {
         Ok(_) => {}
@@ -45,6 +131,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             tracing::error!("grpc serve error, e: {:?}", e);
         }
     };
+    if let Some(h) = http_join_handle {
+        let _ = h.await;
+    }
+    tracing::info!("stop services");
+    utils::otel::shutdown();
 
     Ok(())
 }
@@ -1,78 +1,297 @@
 use tokio;
 use tonic::{Request, Response, Status};
 
-use crate::gss::{BindStreamPortRequest, BindStreamPortResponse, ResponseCode};
-use crate::rpc::server::MyGbtStreamService;
+use crate::gss::{
+    AddressFamily, BindStreamPortRequest, BindStreamPortResponse, ResponseCode, SessionType,
+};
+use crate::rpc::server::{MyGbtStreamService, PushTaskError, StreamTask};
 use crate::stream;
+use crate::stream::handler::download::{self, Download};
+use crate::stream::utils::ssrc;
 
 impl MyGbtStreamService {
     pub async fn rpc_bind_stream_port(
         &self,
-        _request: Request<BindStreamPortRequest>,
+        request: Request<BindStreamPortRequest>,
     ) -> Result<Response<BindStreamPortResponse>, Status> {
+        let req = request.into_inner();
         let mut reply = BindStreamPortResponse::default();
+        let config = self.config();
 
-        // stub for mediaserver
-        if true {
-            reply.code = ResponseCode::Ok.into();
-            reply.message = String::new();
-            reply.media_server_ip = String::from("192.168.31.164");
-            reply.media_server_port = 10000;
+        if self.is_shutting_down() {
+            reply.code = ResponseCode::ShuttingDown.into();
+            reply.message = ResponseCode::ShuttingDown.as_str_name().to_string();
             return Ok(Response::new(reply));
         }
 
-        // alloc port
-        let port = self.pop_port();
-        if port == 0 {
-            reply.code = ResponseCode::NoPortsFree.into();
-            reply.message = ResponseCode::NoPortsFree.as_str_name().to_string();
+        // ssrc, from caller or generated, playback and download take the playback flag
+        let session_type = req.session_type();
+        let ssrc_str = if req.ssrc.is_empty() {
+            self.alloc_ssrc(
+                &req.gb_code,
+                req.playback || session_type != SessionType::Realtime,
+            )
+        } else {
+            req.ssrc.clone()
+        };
+        let ssrc_value = match ssrc::parse(&ssrc_str) {
+            None => {
+                reply.code = ResponseCode::InvalidSsrc.into();
+                reply.message = format!("invalid ssrc: {}", &ssrc_str);
+                return Ok(Response::new(reply));
+            }
+            Some(v) => v,
+        };
+        // a second session would take the first one's packets
+        if self.ssrc_in_use(ssrc_value) {
+            reply.code = ResponseCode::InvalidSsrc.into();
+            reply.message = format!("ssrc in use: {}", &ssrc_str);
             return Ok(Response::new(reply));
         }
 
-        // bind
-        match stream::server::bind(&self.config.host, port).await {
-            Err(e) => {
-                tracing::error!("stream::server::bind error, e: {:?}", &e);
-                reply.code = ResponseCode::BindPortError.into();
-                reply.message = e.to_string();
+        // media source
+        let expected_ip = if req.device_ip.is_empty() {
+            None
+        } else {
+            match req.device_ip.parse::<std::net::IpAddr>() {
+                Err(e) => {
+                    reply.code = ResponseCode::InvalidDeviceIp.into();
+                    reply.message = format!("invalid device_ip: {}, e: {}", &req.device_ip, e);
+                    return Ok(Response::new(reply));
+                }
+                Ok(ip) => Some(ip.to_canonical()),
+            }
+        };
+
+        // media interface, by name or by the device's subnet
+        let interface = match config.media_interface(&req.media_interface, expected_ip) {
+            None => {
+                reply.code = ResponseCode::MediaInterfaceNotFound.into();
+                reply.message = format!("media interface not found: {}", &req.media_interface);
+                return Ok(Response::new(reply));
+            }
+            Some(interface) => interface,
+        };
+
+        // advertised address, in the family asked for
+        let family = req.address_family();
+        let media_server_ip = match interface.media_server_ip(family, config.ipv6_only) {
+            None => {
+                reply.code = ResponseCode::AddressFamilyUnavailable.into();
+                reply.message = format!(
+                    "address family {} is not available on media interface: {}, bind: {}",
+                    family.as_str_name(),
+                    &interface.name,
+                    &interface.bind
+                );
+                return Ok(Response::new(reply));
+            }
+            Some(ip) => ip,
+        };
+
+        // alloc and bind port, a port failing to bind is quarantined and the next one is tried
+        let mut bound = None;
+        let mut bind_error = None;
+        for _ in 0..=config.stream_port_bind_retries {
+            let port = self.pop_port();
+            if port == 0 {
+                break;
+            }
+            match self
+                .bind_stream_sockets(&interface.bind, port, family)
+                .await
+            {
+                Err(e) => {
+                    tracing::error!("stream::server::bind error, port: {}, e: {:?}", port, &e);
+                    self.quarantine_port(port);
+                    bind_error = Some(e);
+                }
+                Ok(sockets) => {
+                    bound = Some((port, sockets));
+                    break;
+                }
+            }
+        }
+
+        match bound {
+            None => {
+                match bind_error {
+                    None => {
+                        reply.code = ResponseCode::NoPortsFree.into();
+                        reply.message = ResponseCode::NoPortsFree.as_str_name().to_string();
+                    }
+                    Some(e) => {
+                        reply.code = ResponseCode::BindPortError.into();
+                        reply.message = e.to_string();
+                    }
+                }
                 Ok(Response::new(reply))
             }
-            Ok((stream_udp_socket, stream_tcp_listener)) => {
+            Some((port, (stream_udp_socket, stream_rtcp_socket, stream_tcp_listener))) => {
                 // serve
                 let stream_handler = stream::handler::StreamHandler::new(
-                    self.config.my_ip.clone(),
+                    media_server_ip.clone(),
                     port,
+                    stream::handler::StreamInfo {
+                        gb_code: req.gb_code.clone(),
+                        stream_id: req.stream_id,
+                        setup_type: req.setup_type,
+                        ssrc: ssrc_value,
+                        ssrc_check: config.ssrc_check,
+                        source_filter: stream::handler::source::SourceFilter {
+                            expected_ip,
+                            lock_first: req.lock_source,
+                        },
+                        idle_timeout: stream::handler::idle::IdleTimeout {
+                            no_data_on_start: match req.no_data_on_start_timeout {
+                                0 => config.no_data_on_start_timeout,
+                                n => n,
+                            },
+                            no_data: match req.no_data_timeout {
+                                0 => config.no_data_timeout,
+                                n => n,
+                            },
+                            no_reader: match (req.on_demand, req.no_reader_timeout) {
+                                (false, _) => 0,
+                                (true, 0) => config.no_reader_timeout,
+                                (true, n) => n,
+                            },
+                        },
+                        on_demand: req.on_demand,
+                        media_interface: interface.name.clone(),
+                        hub_options: config.hub_options(),
+                        session_type,
+                        download_speed: match (session_type, req.download_speed) {
+                            (SessionType::Realtime, _) | (_, 0) => 1,
+                            (_, n) => n,
+                        },
+                    },
                     stream_udp_socket,
+                    stream_rtcp_socket,
                     stream_tcp_listener,
+                    self.event_tx.clone(),
                 );
+                stream_handler.set_allowlists(config.source_allowlists(&req.gb_code));
+                let mut download_url = String::new();
+                if session_type == SessionType::Download {
+                    let name =
+                        download::file_name(&req.gb_code, req.start_time, req.end_time, &ssrc_str);
+                    match Download::create(
+                        &config.download_dir,
+                        name,
+                        req.start_time,
+                        req.end_time,
+                        stream_handler.info.download_speed,
+                    ) {
+                        Err(e) => {
+                            tracing::error!("Download::create error, e: {:?}", &e);
+                            self.push_port(port);
+                            reply.code = ResponseCode::DownloadError.into();
+                            reply.message = e.to_string();
+                            return Ok(Response::new(reply));
+                        }
+                        Ok(download) => {
+                            download_url = download::download_url(&download.name);
+                            stream_handler.set_download(download);
+                        }
+                    }
+                }
 
                 let (udp_tcp_cancel_tx, _) = tokio::sync::broadcast::channel(1);
                 let arc_stream_handler: std::sync::Arc<stream::handler::StreamHandler> =
                     std::sync::Arc::new(stream_handler);
                 match stream::server::run_forever(
                     udp_tcp_cancel_tx.clone(),
-                    self.config.socket_recv_buffer_size,
-                    arc_stream_handler,
+                    config.max_datagram_size,
+                    config.udp_recv_batch,
+                    config.rtcp_interval,
+                    arc_stream_handler.clone(),
                 )
                 .await
                 {
                     Err(e) => {
                         tracing::error!("stream::server::run_forever error, e: {:?}", &e);
+                        self.push_port(port);
                         reply.code = ResponseCode::RunStreamServiceError.into();
                         reply.message = e.to_string();
                         Ok(Response::new(reply))
                     }
                     Ok((udp_join_handle, tcp_join_handle)) => {
-                        self.push_task(port, udp_tcp_cancel_tx, udp_join_handle, tcp_join_handle);
+                        let task = StreamTask {
+                            cancel_tx: udp_tcp_cancel_tx,
+                            udp_join_handle,
+                            tcp_join_handle,
+                            stream_handler: arc_stream_handler,
+                        };
+                        match self.push_task(port, task) {
+                            Ok(()) => {}
+                            Err(PushTaskError::ShuttingDown) => {
+                                if self.pop_task(port).await {
+                                    self.push_port(port);
+                                }
+                                reply.code = ResponseCode::ShuttingDown.into();
+                                reply.message =
+                                    ResponseCode::ShuttingDown.as_str_name().to_string();
+                                return Ok(Response::new(reply));
+                            }
+                            Err(PushTaskError::SsrcInUse(task)) => {
+                                // another bind took the ssrc meanwhile
+                                task.cancel().await;
+                                self.push_port(port);
+                                reply.code = ResponseCode::InvalidSsrc.into();
+                                reply.message = format!("ssrc in use: {}", &ssrc_str);
+                                return Ok(Response::new(reply));
+                            }
+                        }
 
                         reply.code = ResponseCode::Ok.into();
                         reply.message = String::new();
-                        reply.media_server_ip = self.config.my_ip.clone();
+                        reply.media_server_ip = media_server_ip;
                         reply.media_server_port = port as u32;
+                        reply.ssrc = ssrc_str;
+                        reply.media_interface = interface.name;
+                        reply.download_url = download_url;
                         Ok(Response::new(reply))
                     }
                 }
             }
         }
     }
+
+    async fn bind_stream_sockets(
+        &self,
+        host: &str,
+        port: u16,
+        family: AddressFamily,
+    ) -> Result<
+        (
+            tokio::net::UdpSocket,
+            Option<tokio::net::UdpSocket>,
+            tokio::net::TcpListener,
+        ),
+        std::io::Error,
+    > {
+        let config = self.config();
+        let socket_options = config.socket_options();
+        let (stream_udp_socket, stream_tcp_listener) =
+            stream::server::bind(host, port, family, &socket_options).await?;
+
+        // rtcp port + 1 is reserved with port pairs, so it has to bind too, without
+        // pairs it is taken from the pool when free, rtcp-mux otherwise
+        let stream_rtcp_socket = if config.rtcp_on_next_port && self.reserve_rtcp_port(port) {
+            match stream::server::bind_rtcp(host, port, family, &socket_options).await {
+                None if config.stream_port_pairs => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AddrInUse,
+                        format!("rtcp port {} is not available", port as u32 + 1),
+                    ));
+                }
+                rtcp_socket => rtcp_socket,
+            }
+        } else {
+            None
+        };
+
+        Ok((stream_udp_socket, stream_rtcp_socket, stream_tcp_listener))
+    }
 }
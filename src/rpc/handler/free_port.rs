@@ -11,9 +11,8 @@ impl MyGbtStreamService {
         let req = request.into_inner();
         let port = req.media_server_port as u16;
 
-        // stub for mediaserver
-        if false {
-            self.pop_task(port).await;
+        // only ports with a running task go back to the pool
+        if self.pop_task(port).await {
             self.push_port(port);
         }
 
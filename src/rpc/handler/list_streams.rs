@@ -0,0 +1,59 @@
+use tonic::{Request, Response, Status};
+
+use crate::gss::{ListStreamsRequest, ListStreamsResponse, ResponseCode, StreamSession};
+use crate::rpc::server::MyGbtStreamService;
+use crate::stream::utils::{ps, socket, ssrc};
+
+impl MyGbtStreamService {
+    pub async fn rpc_list_streams(
+        &self,
+        request: Request<ListStreamsRequest>,
+    ) -> Result<Response<ListStreamsResponse>, Status> {
+        let gb_code = request.into_inner().gb_code;
+        let mut reply = ListStreamsResponse {
+            code: ResponseCode::Ok.into(),
+            ..Default::default()
+        };
+
+        if let Ok(join_handlers) = self.join_handlers.lock() {
+            for (port, task) in join_handlers.iter() {
+                let handler = &task.stream_handler;
+                if !gb_code.is_empty() && handler.info.gb_code != gb_code {
+                    continue;
+                }
+                let (rcvbuf, sndbuf) = socket::buffer_sizes(&handler.stream_udp_socket);
+                let (video_codec, audio_codec) = match handler.hub.headers() {
+                    None => ("", ""),
+                    Some(headers) => ps::codecs(&headers),
+                };
+                let (progress, download_url) = handler.download_state().unwrap_or_default();
+                reply.streams.push(StreamSession {
+                    gb_code: handler.info.gb_code.clone(),
+                    stream_id: handler.info.stream_id,
+                    setup_type: handler.info.setup_type,
+                    media_server_ip: handler.ip.clone(),
+                    media_server_port: *port as u32,
+                    ssrc: ssrc::to_string(handler.info.ssrc),
+                    stats: Some(handler.stats.snapshot()),
+                    uptime: handler.created_at.elapsed().as_secs(),
+                    socket_recv_buffer_size: rcvbuf as u32,
+                    socket_send_buffer_size: sndbuf as u32,
+                    media_interface: handler.info.media_interface.clone(),
+                    subscribers: handler.hub.subscribers() as u32,
+                    skipped_frames: handler.hub.skipped(),
+                    video_codec: video_codec.to_string(),
+                    audio_codec: audio_codec.to_string(),
+                    on_demand: handler.info.on_demand,
+                    send_rtp: handler.send_targets(),
+                    session_type: handler.info.session_type.into(),
+                    download_speed: handler.info.download_speed,
+                    progress,
+                    download_url,
+                });
+            }
+        }
+        reply.streams.sort_by_key(|s| s.media_server_port);
+
+        Ok(Response::new(reply))
+    }
+}
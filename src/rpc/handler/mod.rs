@@ -1,2 +1,8 @@
 pub mod bind_port;
-pub mod free_port;
\ No newline at end of file
+pub mod free_port;
+pub mod list_streams;
+pub mod reload_config;
+pub mod send_rtp;
+pub mod set_log_level;
+pub mod subscribe_events;
+pub mod talk;
@@ -0,0 +1,26 @@
+use tonic::{Request, Response, Status};
+
+use crate::gss::{ReloadConfigRequest, ReloadConfigResponse, ResponseCode};
+use crate::rpc::server::MyGbtStreamService;
+
+impl MyGbtStreamService {
+    pub async fn rpc_reload_config(
+        &self,
+        _request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        let mut reply = ReloadConfigResponse::default();
+        match self.reload_config_file() {
+            Err(e) => {
+                tracing::error!("config reload error, e: {}", &e);
+                reply.code = ResponseCode::ConfigError.into();
+                reply.message = e;
+            }
+            Ok((applied, rejected)) => {
+                reply.code = ResponseCode::Ok.into();
+                reply.applied = applied;
+                reply.rejected = rejected;
+            }
+        }
+        Ok(Response::new(reply))
+    }
+}
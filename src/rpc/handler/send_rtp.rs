@@ -0,0 +1,226 @@
+use std::net::{IpAddr, SocketAddr};
+
+use tonic::{Request, Response, Status};
+
+use crate::gss::{
+    AddressFamily, ResponseCode, StartSendRtpRequest, StartSendRtpResponse, StopSendRtpRequest,
+    StopSendRtpResponse, StreamSetupType,
+};
+use crate::rpc::server::MyGbtStreamService;
+use crate::stream;
+use crate::stream::sender::{self, Bound, RtpSender, SendTarget};
+use crate::stream::utils::ssrc;
+
+// None for passive, the target connects to us
+pub fn destination(
+    setup_type: StreamSetupType,
+    dst_ip: &str,
+    dst_port: u32,
+) -> Result<Option<SocketAddr>, String> {
+    match (setup_type, dst_ip.parse::<IpAddr>()) {
+        (StreamSetupType::NoMansLandC3916a6, _) => Err("setup_type is required".to_string()),
+        (StreamSetupType::Passive, _) => Ok(None),
+        (_, Ok(ip)) if dst_port > 0 && dst_port <= u16::MAX as u32 => {
+            Ok(Some(SocketAddr::new(ip, dst_port as u16)))
+        }
+        _ => Err(format!("invalid destination: {}:{}", dst_ip, dst_port)),
+    }
+}
+
+// family of the local socket toward dst
+pub fn destination_family(dst: Option<SocketAddr>) -> AddressFamily {
+    match dst {
+        Some(SocketAddr::V4(_)) => AddressFamily::Ipv4,
+        Some(SocketAddr::V6(_)) => AddressFamily::Ipv6,
+        None => AddressFamily::Any,
+    }
+}
+
+impl MyGbtStreamService {
+    // a port from the pool bound for target, a port failing to bind is
+    // quarantined and the next one is tried, None when no ports are free
+    pub async fn bind_send_target(
+        &self,
+        host: &str,
+        family: AddressFamily,
+        target: impl Fn(SocketAddr) -> SendTarget,
+    ) -> std::io::Result<Option<(SendTarget, Bound)>> {
+        let config = self.config();
+        let socket_options = config.socket_options();
+        let mut bind_error = None;
+        for _ in 0..=config.stream_port_bind_retries {
+            let port = self.pop_port();
+            if port == 0 {
+                break;
+            }
+            let result = match stream::server::resolve(host, port, family, &socket_options).await {
+                Err(e) => Err(e),
+                Ok(local_addr) => {
+                    let target = target(local_addr);
+                    sender::bind(&target, &socket_options).map(|b| (target, b))
+                }
+            };
+            match result {
+                Err(e) => {
+                    tracing::error!("sender::bind error, port: {}, e: {:?}", port, &e);
+                    self.quarantine_port(port);
+                    bind_error = Some(e);
+                }
+                Ok(target_bound) => return Ok(Some(target_bound)),
+            }
+        }
+        match bind_error {
+            None => Ok(None),
+            Some(e) => Err(e),
+        }
+    }
+
+    pub async fn rpc_start_send_rtp(
+        &self,
+        request: Request<StartSendRtpRequest>,
+    ) -> Result<Response<StartSendRtpResponse>, Status> {
+        let req = request.into_inner();
+        let mut reply = StartSendRtpResponse::default();
+        let config = self.config();
+
+        if self.is_shutting_down() {
+            reply.code = ResponseCode::ShuttingDown.into();
+            reply.message = ResponseCode::ShuttingDown.as_str_name().to_string();
+            return Ok(Response::new(reply));
+        }
+
+        let stream_handler = match self.find_handler(&req.gb_code, req.stream_id) {
+            None => {
+                reply.code = ResponseCode::StreamNotFound.into();
+                reply.message = format!("stream not found: {}/{}", &req.gb_code, req.stream_id);
+                return Ok(Response::new(reply));
+            }
+            Some(h) => h,
+        };
+        let ssrc_value = match ssrc::parse(&req.ssrc) {
+            None => {
+                reply.code = ResponseCode::InvalidSsrc.into();
+                reply.message = format!("invalid ssrc: {}", &req.ssrc);
+                return Ok(Response::new(reply));
+            }
+            Some(v) => v,
+        };
+        if stream_handler.has_sender(ssrc_value) {
+            reply.code = ResponseCode::SendRtpError.into();
+            reply.message = format!("already sending ssrc: {}", &req.ssrc);
+            return Ok(Response::new(reply));
+        }
+
+        let setup_type = req.setup_type();
+        let dst = match destination(setup_type, &req.dst_ip, req.dst_port) {
+            Err(message) => {
+                reply.code = ResponseCode::InvalidDestination.into();
+                reply.message = message;
+                return Ok(Response::new(reply));
+            }
+            Ok(dst) => dst,
+        };
+        let payload_type = match req.payload_type {
+            0 => 96,
+            n if n < 128 => n as u8,
+            n => {
+                reply.code = ResponseCode::InvalidDestination.into();
+                reply.message = format!("invalid payload_type: {}", n);
+                return Ok(Response::new(reply));
+            }
+        };
+
+        // from the session's media interface, in the destination's family
+        let interface = config
+            .media_interface(&stream_handler.info.media_interface, None)
+            .unwrap_or_else(|| config.default_interface());
+        let family = destination_family(dst);
+        let local_ip = interface
+            .media_server_ip(family, config.ipv6_only)
+            .unwrap_or_default();
+        let bound = self
+            .bind_send_target(&interface.bind, family, |local_addr| SendTarget {
+                setup_type,
+                local_addr,
+                dst,
+                ssrc: ssrc_value,
+                payload_type,
+            })
+            .await;
+
+        match bound {
+            Err(e) => {
+                reply.code = ResponseCode::SendRtpError.into();
+                reply.message = e.to_string();
+                Ok(Response::new(reply))
+            }
+            Ok(None) => {
+                reply.code = ResponseCode::NoPortsFree.into();
+                reply.message = ResponseCode::NoPortsFree.as_str_name().to_string();
+                Ok(Response::new(reply))
+            }
+            Ok(Some((target, bound))) => {
+                tracing::info!(
+                    "send rtp start, ssrc: {}, setup_type: {}, local: {}, dst: {:?}",
+                    &req.ssrc,
+                    setup_type.as_str_name(),
+                    target.local_addr,
+                    target.dst
+                );
+                reply.code = ResponseCode::Ok.into();
+                reply.local_ip = local_ip;
+                reply.local_port = target.local_addr.port() as u32;
+                let subscription = stream_handler.hub.subscribe();
+                stream_handler.add_sender(RtpSender::start(
+                    target,
+                    bound,
+                    subscription,
+                    &stream_handler,
+                ));
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    pub async fn rpc_stop_send_rtp(
+        &self,
+        request: Request<StopSendRtpRequest>,
+    ) -> Result<Response<StopSendRtpResponse>, Status> {
+        let req = request.into_inner();
+        let mut reply = StopSendRtpResponse::default();
+
+        let stream_handler = match self.find_handler(&req.gb_code, req.stream_id) {
+            None => {
+                reply.code = ResponseCode::StreamNotFound.into();
+                reply.message = format!("stream not found: {}/{}", &req.gb_code, req.stream_id);
+                return Ok(Response::new(reply));
+            }
+            Some(h) => h,
+        };
+        let ssrc_value = if req.ssrc.is_empty() {
+            None
+        } else {
+            match ssrc::parse(&req.ssrc) {
+                None => {
+                    reply.code = ResponseCode::InvalidSsrc.into();
+                    reply.message = format!("invalid ssrc: {}", &req.ssrc);
+                    return Ok(Response::new(reply));
+                }
+                v => v,
+            }
+        };
+
+        let ports = stream_handler.stop_senders(ssrc_value);
+        if ports.is_empty() {
+            reply.code = ResponseCode::SendRtpNotFound.into();
+            reply.message = format!("not sending ssrc: {}", &req.ssrc);
+            return Ok(Response::new(reply));
+        }
+        tracing::info!("send rtp stop, ssrc: {}, ports: {:?}", &req.ssrc, &ports);
+        for port in ports {
+            self.push_port(port);
+        }
+        reply.code = ResponseCode::Ok.into();
+        Ok(Response::new(reply))
+    }
+}
@@ -0,0 +1,29 @@
+use tonic::{Request, Response, Status};
+
+use crate::gss::{ResponseCode, SetLogLevelRequest, SetLogLevelResponse};
+use crate::rpc::server::MyGbtStreamService;
+use crate::utils::log;
+
+impl MyGbtStreamService {
+    // runtime only, the config file is left as is
+    pub async fn rpc_set_log_level(
+        &self,
+        request: Request<SetLogLevelRequest>,
+    ) -> Result<Response<SetLogLevelResponse>, Status> {
+        let req = request.into_inner();
+        let mut reply = SetLogLevelResponse::default();
+        match log::set_level(&req.output, &req.level) {
+            Err(e) => {
+                tracing::error!("log::set_level error, e: {}", &e);
+                reply.code = ResponseCode::InvalidLogLevel.into();
+                reply.message = e;
+            }
+            Ok((stdout_level, file_level)) => {
+                reply.code = ResponseCode::Ok.into();
+                reply.stdout_level = stdout_level;
+                reply.file_level = file_level;
+            }
+        }
+        Ok(Response::new(reply))
+    }
+}
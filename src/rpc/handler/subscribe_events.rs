@@ -0,0 +1,41 @@
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Request, Response, Status};
+
+use crate::gss::SubscribeStreamEventsRequest;
+use crate::rpc::server::{MyGbtStreamService, StreamEventStream};
+
+impl MyGbtStreamService {
+    pub async fn rpc_subscribe_stream_events(
+        &self,
+        request: Request<SubscribeStreamEventsRequest>,
+    ) -> Result<Response<StreamEventStream>, Status> {
+        let gb_code = request.into_inner().gb_code;
+        let event_rx = self.event_tx.subscribe();
+        let closed_rx = self.closed_tx.subscribe();
+
+        let stream = futures::stream::unfold(
+            (event_rx, closed_rx, gb_code),
+            |(mut event_rx, mut closed_rx, gb_code)| async move {
+                loop {
+                    let result = tokio::select! {
+                        result = event_rx.recv() => result,
+                        _ = closed_rx.wait_for(|closed| *closed) => return None,
+                    };
+                    match result {
+                        Ok(event) => {
+                            if gb_code.is_empty() || event.gb_code == gb_code {
+                                return Some((Ok(event), (event_rx, closed_rx, gb_code)));
+                            }
+                        }
+                        Err(RecvError::Lagged(n)) => {
+                            tracing::warn!("stream events subscriber lagged, skipped: {}", n);
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::hash_map::Entry;
+use std::path::{Component, Path};
+
+use tonic::{Request, Response, Status};
+
+use crate::gss::{
+    ResponseCode, StartTalkRequest, StartTalkResponse, StopTalkRequest, StopTalkResponse,
+};
+use crate::rpc::handler::send_rtp::{destination, destination_family};
+use crate::rpc::server::MyGbtStreamService;
+use crate::stream::sender::SendTarget;
+use crate::stream::talk::{self, Talk, TalkSource};
+use crate::stream::utils::ssrc;
+
+// relative and inside the directory, no way out with ".."
+fn is_inside(file: &str) -> bool {
+    let path = Path::new(file);
+    !file.is_empty() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+impl MyGbtStreamService {
+    pub async fn rpc_start_talk(
+        &self,
+        request: Request<StartTalkRequest>,
+    ) -> Result<Response<StartTalkResponse>, Status> {
+        let req = request.into_inner();
+        let mut reply = StartTalkResponse::default();
+        let config = self.config();
+
+        if self.is_shutting_down() {
+            reply.code = ResponseCode::ShuttingDown.into();
+            reply.message = ResponseCode::ShuttingDown.as_str_name().to_string();
+            return Ok(Response::new(reply));
+        }
+
+        if self.talks.lock().unwrap().contains_key(&req.gb_code) {
+            reply.code = ResponseCode::TalkError.into();
+            reply.message = format!("already talking to: {}", &req.gb_code);
+            return Ok(Response::new(reply));
+        }
+        let ssrc_value = match ssrc::parse(&req.ssrc) {
+            None => {
+                reply.code = ResponseCode::InvalidSsrc.into();
+                reply.message = format!("invalid ssrc: {}", &req.ssrc);
+                return Ok(Response::new(reply));
+            }
+            Some(v) => v,
+        };
+        let setup_type = req.setup_type();
+        let dst = match destination(setup_type, &req.dst_ip, req.dst_port) {
+            Err(message) => {
+                reply.code = ResponseCode::InvalidDestination.into();
+                reply.message = message;
+                return Ok(Response::new(reply));
+            }
+            Ok(dst) => dst,
+        };
+
+        // read up front, a bad file fails the rpc and not the talk
+        let payload = req.payload();
+        let source = if req.file.is_empty() {
+            TalkSource::Feed(req.input_format())
+        } else {
+            if !is_inside(&req.file) {
+                reply.code = ResponseCode::InvalidAudioFile.into();
+                reply.message = format!("file outside talk_file_dir: {}", &req.file);
+                return Ok(Response::new(reply));
+            }
+            let path = Path::new(&config.talk_file_dir).join(&req.file);
+            match talk::load_file(&path, req.input_format()) {
+                Err(e) => {
+                    tracing::error!("talk::load_file({}) error, e: {:?}", path.display(), e);
+                    reply.code = ResponseCode::InvalidAudioFile.into();
+                    reply.message = format!("{}: {}", &req.file, e);
+                    return Ok(Response::new(reply));
+                }
+                Ok((format, data)) => TalkSource::File(format, data),
+            }
+        };
+
+        let interface = match config.media_interface(&req.media_interface, dst.map(|d| d.ip())) {
+            None => {
+                reply.code = ResponseCode::MediaInterfaceNotFound.into();
+                reply.message = format!("media interface not found: {}", &req.media_interface);
+                return Ok(Response::new(reply));
+            }
+            Some(interface) => interface,
+        };
+        let family = destination_family(dst);
+        let local_ip = interface
+            .media_server_ip(family, config.ipv6_only)
+            .unwrap_or_default();
+        let bound = self
+            .bind_send_target(&interface.bind, family, |local_addr| SendTarget {
+                setup_type,
+                local_addr,
+                dst,
+                ssrc: ssrc_value,
+                payload_type: payload.payload_type(),
+            })
+            .await;
+
+        match bound {
+            Err(e) => {
+                reply.code = ResponseCode::TalkError.into();
+                reply.message = e.to_string();
+                Ok(Response::new(reply))
+            }
+            Ok(None) => {
+                reply.code = ResponseCode::NoPortsFree.into();
+                reply.message = ResponseCode::NoPortsFree.as_str_name().to_string();
+                Ok(Response::new(reply))
+            }
+            Ok(Some((target, bound))) => {
+                tracing::info!(
+                    "talk start, gb_code: {}, ssrc: {}, setup_type: {}, payload: {}, local: {}, dst: {:?}, file: {}",
+                    &req.gb_code,
+                    &req.ssrc,
+                    setup_type.as_str_name(),
+                    payload.as_str_name(),
+                    target.local_addr,
+                    target.dst,
+                    &req.file
+                );
+                let talk = Talk::start(
+                    &req.gb_code,
+                    target,
+                    bound,
+                    payload,
+                    source,
+                    self.event_tx.clone(),
+                );
+                reply.code = ResponseCode::Ok.into();
+                reply.local_ip = local_ip;
+                reply.local_port = talk.target.local_addr.port() as u32;
+                if !talk.token.is_empty() {
+                    reply.ws_path = format!("/api/talk/{}/ws?token={}", &req.gb_code, &talk.token);
+                }
+
+                // raced by another start for the device, the later one loses
+                let port = talk.target.local_addr.port();
+                let lost = match self.talks.lock().unwrap().entry(req.gb_code.clone()) {
+                    Entry::Occupied(_) => Some(talk),
+                    Entry::Vacant(entry) => {
+                        entry.insert(talk);
+                        None
+                    }
+                };
+                if let Some(talk) = lost {
+                    talk.stop();
+                    self.push_port(port);
+                    reply = StartTalkResponse {
+                        code: ResponseCode::TalkError.into(),
+                        message: format!("already talking to: {}", &req.gb_code),
+                        ..Default::default()
+                    };
+                }
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    pub async fn rpc_stop_talk(
+        &self,
+        request: Request<StopTalkRequest>,
+    ) -> Result<Response<StopTalkResponse>, Status> {
+        let req = request.into_inner();
+        let mut reply = StopTalkResponse::default();
+
+        let talk = self.talks.lock().unwrap().remove(&req.gb_code);
+        match talk {
+            None => {
+                reply.code = ResponseCode::TalkNotFound.into();
+                reply.message = format!("not talking to: {}", &req.gb_code);
+            }
+            Some(talk) => {
+                let port = talk.target.local_addr.port();
+                tracing::info!("talk stop, gb_code: {}, port: {}", &req.gb_code, port);
+                talk.stop();
+                self.push_port(port);
+                reply.code = ResponseCode::Ok.into();
+            }
+        }
+        Ok(Response::new(reply))
+    }
+
+    // talks that ended on their own, their ports go back to the pool
+    pub fn reap_talks(&self) -> Vec<u16> {
+        let mut talks = self.talks.lock().unwrap();
+        let mut ports = vec![];
+        talks.retain(|_, talk| {
+            if talk.is_finished() {
+                ports.push(talk.target.local_addr.port());
+                return false;
+            }
+            true
+        });
+        ports
+    }
+}
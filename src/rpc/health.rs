@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use tonic_health::server::HealthReporter;
+
+use crate::gss::gbt_stream_service_server::GbtStreamServiceServer;
+use crate::rpc::server::MyGbtStreamService;
+
+const HEALTH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+impl MyGbtStreamService {
+    // NOT_SERVING while no port is free or a shutdown is in progress,
+    // so load balancers send binds elsewhere
+    pub fn is_serving(&self) -> bool {
+        !self.is_shutting_down() && self.port_pool_usage().free > 0
+    }
+
+    pub fn start_health(
+        self: &Arc<Self>,
+        mut reporter: HealthReporter,
+    ) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut serving = None;
+            let mut interval = tokio::time::interval(HEALTH_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let now_serving = service.is_serving();
+                if serving == Some(now_serving) {
+                    continue;
+                }
+                serving = Some(now_serving);
+
+                if now_serving {
+                    tracing::info!("health: SERVING");
+                    reporter
+                        .set_serving::<GbtStreamServiceServer<MyGbtStreamService>>()
+                        .await;
+                    reporter
+                        .set_service_status("", tonic_health::ServingStatus::Serving)
+                        .await;
+                } else {
+                    tracing::warn!(
+                        "health: NOT_SERVING, shutting down: {}, ports: {:?}",
+                        service.is_shutting_down(),
+                        service.port_pool_usage()
+                    );
+                    reporter
+                        .set_not_serving::<GbtStreamServiceServer<MyGbtStreamService>>()
+                        .await;
+                    reporter
+                        .set_service_status("", tonic_health::ServingStatus::NotServing)
+                        .await;
+                }
+            }
+        })
+    }
+}
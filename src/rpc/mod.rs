@@ -1,5 +1,9 @@
+pub mod auth;
 pub mod handler;
+pub mod health;
+pub mod port_pool;
+pub mod reload;
 pub mod request;
 pub mod server;
-
-
+pub mod shutdown;
+pub mod watchdog;
@@ -0,0 +1,300 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PortPoolUsage {
+    pub free: usize,
+    pub cooling: usize,
+    pub quarantined: usize,
+    pub used: usize,
+}
+
+pub struct PortPool {
+    free: VecDeque<u16>,
+    // freed ports, reusable after cooldown so late packets of the old session die out
+    cooling: VecDeque<(u16, Instant)>,
+    // ports that failed to bind, retried after quarantine
+    quarantined: VecDeque<(u16, Instant)>,
+    used: HashSet<u16>,
+    // ports whose port + 1 is held for rtcp, without pairs
+    rtcp_reserved: HashSet<u16>,
+    pairs: bool,
+    cooldown: Duration,
+    quarantine: Duration,
+}
+
+impl PortPool {
+    // with pairs, only even ports are handed out, port + 1 is kept for rtcp
+    pub fn new(
+        start: u16,
+        stop: u16,
+        pairs: bool,
+        cooldown: Duration,
+        quarantine: Duration,
+    ) -> Self {
+        PortPool {
+            free: Self::range(start, stop, pairs).collect(),
+            cooling: VecDeque::new(),
+            quarantined: VecDeque::new(),
+            used: HashSet::new(),
+            rtcp_reserved: HashSet::new(),
+            pairs,
+            cooldown,
+            quarantine,
+        }
+    }
+
+    fn range(start: u16, stop: u16, pairs: bool) -> Box<dyn Iterator<Item = u16>> {
+        if pairs {
+            Box::new((start..stop).filter(|port| port % 2 == 0))
+        } else {
+            Box::new(start..=stop)
+        }
+    }
+
+    // ports in use have to stay in range, cooling and quarantined ones outside are dropped
+    pub fn resize(&mut self, start: u16, stop: u16) -> Result<(), String> {
+        let ports = Self::range(start, stop, self.pairs).collect::<HashSet<u16>>();
+        let mut outside = self
+            .used
+            .iter()
+            .filter(|port| !ports.contains(port))
+            .cloned()
+            .collect::<Vec<u16>>();
+        if !outside.is_empty() {
+            outside.sort();
+            return Err(format!(
+                "ports in use outside {}..{}: {:?}",
+                start, stop, outside
+            ));
+        }
+
+        self.cooling.retain(|(port, _)| ports.contains(port));
+        self.quarantined.retain(|(port, _)| ports.contains(port));
+        self.free.retain(|port| ports.contains(port));
+        let known = self
+            .free
+            .iter()
+            .chain(self.cooling.iter().map(|(port, _)| port))
+            .chain(self.quarantined.iter().map(|(port, _)| port))
+            .chain(self.used.iter())
+            .cloned()
+            .collect::<HashSet<u16>>();
+        self.free
+            .extend(Self::range(start, stop, self.pairs).filter(|port| !known.contains(port)));
+        Ok(())
+    }
+
+    pub fn set_timeouts(&mut self, cooldown: Duration, quarantine: Duration) {
+        self.cooldown = cooldown;
+        self.quarantine = quarantine;
+    }
+
+    fn refresh(&mut self, now: Instant) {
+        while let Some((port, until)) = self.cooling.front() {
+            if *until > now {
+                break;
+            }
+            self.free.push_back(*port);
+            self.cooling.pop_front();
+        }
+        while let Some((port, until)) = self.quarantined.front() {
+            if *until > now {
+                break;
+            }
+            tracing::info!("port quarantine over, port: {}", port);
+            self.free.push_back(*port);
+            self.quarantined.pop_front();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<u16> {
+        self.refresh(Instant::now());
+        let port = self.free.pop_front()?;
+        self.used.insert(port);
+        Some(port)
+    }
+
+    pub fn push(&mut self, port: u16) {
+        if !self.used.remove(&port) {
+            tracing::warn!("PortPool::push, port is not in use: {}", port);
+            return;
+        }
+        self.cooling
+            .push_back((port, Instant::now() + self.cooldown));
+        self.release_rtcp(port);
+    }
+
+    // holds port + 1 of a port in use for rtcp, false when it is not free,
+    // released with the port; with pairs it is never handed out anyway
+    pub fn reserve_rtcp(&mut self, port: u16) -> bool {
+        if self.pairs {
+            return true;
+        }
+        let Some(next) = port.checked_add(1) else {
+            return false;
+        };
+        self.refresh(Instant::now());
+        let Some(index) = self.free.iter().position(|p| *p == next) else {
+            return false;
+        };
+        self.free.remove(index);
+        self.used.insert(next);
+        self.rtcp_reserved.insert(port);
+        true
+    }
+
+    fn release_rtcp(&mut self, port: u16) {
+        if self.rtcp_reserved.remove(&port) {
+            self.push(port + 1);
+        }
+    }
+
+    pub fn quarantine(&mut self, port: u16) {
+        if !self.used.remove(&port) {
+            tracing::warn!("PortPool::quarantine, port is not in use: {}", port);
+            return;
+        }
+        tracing::warn!(
+            "port quarantined, port: {}, seconds: {}",
+            port,
+            self.quarantine.as_secs()
+        );
+        self.quarantined
+            .push_back((port, Instant::now() + self.quarantine));
+        self.release_rtcp(port);
+    }
+
+    pub fn usage(&mut self) -> PortPoolUsage {
+        self.refresh(Instant::now());
+        PortPoolUsage {
+            free: self.free.len(),
+            cooling: self.cooling.len(),
+            quarantined: self.quarantined.len(),
+            used: self.used.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(3600);
+
+    fn drain(pool: &mut PortPool) -> Vec<u16> {
+        std::iter::from_fn(|| pool.pop()).collect()
+    }
+
+    #[test]
+    fn hands_out_the_range_in_order() {
+        let mut pool = PortPool::new(10000, 10003, false, LONG, LONG);
+        assert_eq!(drain(&mut pool), vec![10000, 10001, 10002, 10003]);
+        assert_eq!(pool.usage().used, 4);
+    }
+
+    #[test]
+    fn pairs_hand_out_even_ports_only() {
+        let mut pool = PortPool::new(10001, 10008, true, LONG, LONG);
+        assert_eq!(drain(&mut pool), vec![10002, 10004, 10006]);
+        // port + 1 is kept for rtcp without taking it from the pool
+        assert!(pool.reserve_rtcp(10002));
+        assert_eq!(pool.usage().used, 3);
+    }
+
+    #[test]
+    fn freed_ports_cool_down_before_reuse() {
+        let mut pool = PortPool::new(10000, 10001, false, LONG, LONG);
+        assert_eq!(drain(&mut pool), vec![10000, 10001]);
+        pool.push(10000);
+        assert_eq!(pool.pop(), None);
+        let usage = pool.usage();
+        assert_eq!((usage.free, usage.cooling, usage.used), (0, 1, 1));
+
+        let mut pool = PortPool::new(10000, 10001, false, Duration::ZERO, LONG);
+        assert_eq!(drain(&mut pool), vec![10000, 10001]);
+        pool.push(10001);
+        assert_eq!(pool.pop(), Some(10001));
+    }
+
+    #[test]
+    fn pushing_a_port_not_in_use_is_ignored() {
+        let mut pool = PortPool::new(10000, 10001, false, Duration::ZERO, LONG);
+        pool.push(10000);
+        pool.push(20000);
+        assert_eq!(drain(&mut pool), vec![10000, 10001]);
+    }
+
+    #[test]
+    fn quarantined_ports_come_back_after_the_quarantine() {
+        let mut pool = PortPool::new(10000, 10001, false, Duration::ZERO, LONG);
+        assert_eq!(pool.pop(), Some(10000));
+        pool.quarantine(10000);
+        assert_eq!(drain(&mut pool), vec![10001]);
+        assert_eq!(pool.usage().quarantined, 1);
+
+        let mut pool = PortPool::new(10000, 10001, false, LONG, Duration::ZERO);
+        assert_eq!(pool.pop(), Some(10000));
+        pool.quarantine(10000);
+        assert_eq!(drain(&mut pool), vec![10001, 10000]);
+    }
+
+    #[test]
+    fn rtcp_port_is_reserved_and_released_with_the_port() {
+        let mut pool = PortPool::new(10000, 10003, false, Duration::ZERO, LONG);
+        assert_eq!(pool.pop(), Some(10000));
+        assert!(pool.reserve_rtcp(10000));
+        assert_eq!(pool.pop(), Some(10002));
+        // 10003 is free, 10001 is not
+        assert!(pool.reserve_rtcp(10002));
+        assert_eq!(pool.pop(), None);
+
+        pool.push(10000);
+        assert_eq!(drain(&mut pool), vec![10000, 10001]);
+    }
+
+    #[test]
+    fn rtcp_port_in_use_is_not_reserved() {
+        let mut pool = PortPool::new(10000, 10001, false, Duration::ZERO, LONG);
+        assert_eq!(drain(&mut pool), vec![10000, 10001]);
+        assert!(!pool.reserve_rtcp(10000));
+        assert!(!pool.reserve_rtcp(u16::MAX));
+        // releasing 10000 leaves 10001 in use by its own session
+        pool.push(10000);
+        assert_eq!(drain(&mut pool), vec![10000]);
+    }
+
+    #[test]
+    fn resize_grows_and_shrinks_around_used_ports() {
+        let mut pool = PortPool::new(10000, 10003, false, LONG, LONG);
+        assert_eq!(pool.pop(), Some(10000));
+        assert_eq!(pool.pop(), Some(10001));
+        pool.push(10001);
+
+        let e = pool.resize(10001, 10005).unwrap_err();
+        assert!(e.contains("10000"), "{}", e);
+
+        pool.resize(10000, 10005).unwrap();
+        let usage = pool.usage();
+        assert_eq!((usage.free, usage.cooling, usage.used), (4, 1, 1));
+        // the cooling port is not handed out again by the resize
+        assert_eq!(drain(&mut pool), vec![10002, 10003, 10004, 10005]);
+
+        // cooling ports outside the new range are dropped
+        pool.resize(10000, 10000).unwrap_err();
+        pool.push(10002);
+        pool.push(10003);
+        pool.push(10004);
+        pool.push(10005);
+        pool.resize(10000, 10000).unwrap();
+        let usage = pool.usage();
+        assert_eq!((usage.free, usage.cooling, usage.used), (0, 0, 1));
+    }
+
+    #[test]
+    fn resize_keeps_pairs() {
+        let mut pool = PortPool::new(10000, 10003, true, LONG, LONG);
+        pool.resize(10000, 10007).unwrap();
+        assert_eq!(drain(&mut pool), vec![10000, 10002, 10004, 10006]);
+    }
+}
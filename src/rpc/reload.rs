@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use crate::gss::ConfigChange;
+use crate::rpc::server::MyGbtStreamService;
+use crate::utils::config::Config;
+use crate::utils::log;
+
+fn is_under(key: &str, prefix: &str) -> bool {
+    key == prefix || key.starts_with(&format!("{}.", prefix))
+}
+
+impl MyGbtStreamService {
+    // re-read the config file and apply what is safe without dropping sessions,
+    // new values apply to new sessions except allowlists, which are pushed to running ones
+    pub fn reload_config_file(&self) -> Result<(Vec<ConfigChange>, Vec<ConfigChange>), String> {
+        let mut config = Config::load_from_file(&self.config_path)
+            .map_err(|e| format!("load {} error, e: {}", self.config_path.display(), e))?;
+        let current = self.config();
+        let changes = current.diff(&config);
+
+        let mut rejected: Vec<(&str, String)> = config
+            .keep_restart_keys(&current)
+            .into_iter()
+            .map(|key| (key, "restart required".to_string()))
+            .collect();
+
+        {
+            let mut ports = self.ports.lock().unwrap();
+            if config.stream_port_start != current.stream_port_start
+                || config.stream_port_stop != current.stream_port_stop
+            {
+                if let Err(e) = ports.resize(config.stream_port_start, config.stream_port_stop) {
+                    config.stream_port_start = current.stream_port_start;
+                    config.stream_port_stop = current.stream_port_stop;
+                    rejected.push(("stream_port_start", e.clone()));
+                    rejected.push(("stream_port_stop", e));
+                }
+            }
+            ports.set_timeouts(
+                std::time::Duration::from_secs(config.stream_port_cooldown),
+                std::time::Duration::from_secs(config.stream_port_quarantine),
+            );
+        }
+
+        // levels go through the reload handles, a bad directive keeps the current one
+        for (key, output, new_level, current_level) in [
+            (
+                "log.stdout_level",
+                log::LOG_OUTPUT_STDOUT,
+                &mut config.log.stdout_level,
+                &current.log.stdout_level,
+            ),
+            (
+                "log.file_level",
+                log::LOG_OUTPUT_FILE,
+                &mut config.log.file_level,
+                &current.log.file_level,
+            ),
+        ] {
+            if new_level == current_level {
+                continue;
+            }
+            if let Err(e) = log::set_level(output, new_level) {
+                *new_level = current_level.clone();
+                rejected.push((key, e));
+            }
+        }
+
+        let mut applied = vec![];
+        let mut not_applied = vec![];
+        for (key, old_value, new_value) in changes {
+            match rejected.iter().find(|(prefix, _)| is_under(&key, prefix)) {
+                Some((_, reason)) => {
+                    tracing::error!(
+                        "config reload rejected, key: {}, old: {}, new: {}, reason: {}",
+                        &key,
+                        &old_value,
+                        &new_value,
+                        reason
+                    );
+                    not_applied.push(ConfigChange {
+                        key,
+                        old_value,
+                        new_value,
+                        reason: reason.clone(),
+                    });
+                }
+                None => {
+                    tracing::info!(
+                        "config reload applied, key: {}, old: {}, new: {}",
+                        &key,
+                        &old_value,
+                        &new_value
+                    );
+                    applied.push(ConfigChange {
+                        key,
+                        old_value,
+                        new_value,
+                        reason: String::new(),
+                    });
+                }
+            }
+        }
+
+        if let Ok(join_handlers) = self.join_handlers.lock() {
+            for task in join_handlers.values() {
+                let handler = &task.stream_handler;
+                handler.set_allowlists(config.source_allowlists(&handler.info.gb_code));
+            }
+        }
+        self.config_tx.send_replace(Arc::new(config));
+
+        Ok((applied, not_applied))
+    }
+
+    // SIGHUP reloads the config file
+    pub fn start_reload(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            {
+                let mut hangup =
+                    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                        Err(e) => {
+                            tracing::error!("tokio::signal::unix::signal error, e: {:?}", e);
+                            return;
+                        }
+                        Ok(s) => s,
+                    };
+                while hangup.recv().await.is_some() {
+                    tracing::warn!("SIGHUP received, reload config");
+                    if let Err(e) = service.reload_config_file() {
+                        tracing::error!("config reload error, e: {}", e);
+                    }
+                }
+            }
+            #[cfg(not(unix))]
+            let _ = service;
+        })
+    }
+}
@@ -1,39 +1,100 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use futures::Stream;
 use tonic::{Request, Response, Status};
+use tracing::Instrument;
 
+use crate::rpc::port_pool::{PortPool, PortPoolUsage};
+use crate::stream::handler::StreamHandler;
+use crate::stream::talk::Talk;
+use crate::stream::utils::ssrc;
 use crate::utils::config::Config;
+use crate::utils::metrics::metrics;
 
 use crate::gss::{
     gbt_stream_service_server::GbtStreamService, BindStreamPortRequest, BindStreamPortResponse,
-    FreeStreamPortRequest, FreeStreamPortResponse,
+    FreeStreamPortRequest, FreeStreamPortResponse, ListStreamsRequest, ListStreamsResponse,
+    ReloadConfigRequest, ReloadConfigResponse, ResponseCode, SetLogLevelRequest,
+    SetLogLevelResponse, StartSendRtpRequest, StartSendRtpResponse, StartTalkRequest,
+    StartTalkResponse, StopSendRtpRequest, StopSendRtpResponse, StopTalkRequest, StopTalkResponse,
+    StreamEvent, StreamEventType, SubscribeStreamEventsRequest,
 };
 
+pub type StreamEventStream =
+    Pin<Box<dyn Stream<Item = Result<StreamEvent, Status>> + Send + 'static>>;
+
 pub struct StreamTask {
     pub cancel_tx: tokio::sync::broadcast::Sender<()>,
     pub udp_join_handle: tokio::task::JoinHandle<()>,
     pub tcp_join_handle: tokio::task::JoinHandle<()>,
+    pub stream_handler: std::sync::Arc<StreamHandler>,
+}
+
+impl StreamTask {
+    // stops the receive tasks of a session that was not pushed
+    pub async fn cancel(self) {
+        let _ = self.cancel_tx.send(());
+        let _ = tokio::join!(self.udp_join_handle, self.tcp_join_handle);
+        self.stream_handler.hub.close();
+    }
+}
+
+// why push_task did not keep a session
+pub enum PushTaskError {
+    // the session missed the shutdown drain and has to be popped by the caller
+    ShuttingDown,
+    // a live session has the ssrc, the task is handed back to be cancelled
+    SsrcInUse(StreamTask),
 }
 
 pub struct MyGbtStreamService {
-    pub config: Config,
-    ports: std::sync::Mutex<std::collections::LinkedList<u16>>,
+    pub(crate) config_path: std::path::PathBuf,
+    // swapped on reload, sessions keep what they were bound with
+    pub(crate) config_tx: tokio::sync::watch::Sender<std::sync::Arc<Config>>,
+    pub(crate) ports: std::sync::Mutex<PortPool>,
     pub join_handlers: std::sync::Mutex<std::collections::HashMap<u16, StreamTask>>,
+    // by gb_code
+    pub talks: std::sync::Mutex<std::collections::HashMap<String, Talk>>,
+    pub event_tx: tokio::sync::broadcast::Sender<StreamEvent>,
+    ssrc_sequence: AtomicU32,
+    pub(crate) shutting_down: AtomicBool,
+    pub(crate) closed_tx: tokio::sync::watch::Sender<bool>,
 }
 
 impl MyGbtStreamService {
-    pub fn new(config: Config) -> Self {
-        let start = config.stream_port_start;
-        let stop = config.stream_port_stop;
+    pub fn new(config_path: std::path::PathBuf, config: Config) -> Self {
+        let ports = PortPool::new(
+            config.stream_port_start,
+            config.stream_port_stop,
+            config.stream_port_pairs,
+            std::time::Duration::from_secs(config.stream_port_cooldown),
+            std::time::Duration::from_secs(config.stream_port_quarantine),
+        );
+        let (event_tx, _) = tokio::sync::broadcast::channel(1024);
         MyGbtStreamService {
-            config,
-            ports: (start..=stop)
-                .collect::<std::collections::LinkedList<u16>>()
-                .into(),
+            config_path,
+            config_tx: tokio::sync::watch::Sender::new(std::sync::Arc::new(config)),
+            ports: ports.into(),
             join_handlers: std::collections::HashMap::<u16, StreamTask>::new().into(),
+            talks: std::collections::HashMap::new().into(),
+            event_tx,
+            ssrc_sequence: AtomicU32::new(1),
+            shutting_down: AtomicBool::new(false),
+            closed_tx: tokio::sync::watch::Sender::new(false),
         }
     }
 
+    pub fn config(&self) -> std::sync::Arc<Config> {
+        self.config_tx.borrow().clone()
+    }
+
+    pub fn watch_config(&self) -> tokio::sync::watch::Receiver<std::sync::Arc<Config>> {
+        self.config_tx.subscribe()
+    }
+
     pub fn pop_port(&self) -> u16 {
-        match self.ports.lock().unwrap().pop_front() {
+        match self.ports.lock().unwrap().pop() {
             None => {
                 tracing::error!("No ports are free");
                 0
@@ -43,59 +104,334 @@ impl MyGbtStreamService {
     }
 
     pub fn push_port(&self, port: u16) {
-        self.ports.lock().unwrap().push_back(port);
+        self.ports.lock().unwrap().push(port);
     }
 
-    pub fn push_task(
+    pub fn reserve_rtcp_port(&self, port: u16) -> bool {
+        self.ports.lock().unwrap().reserve_rtcp(port)
+    }
+
+    pub fn quarantine_port(&self, port: u16) {
+        self.ports.lock().unwrap().quarantine(port);
+    }
+
+    pub fn port_pool_usage(&self) -> PortPoolUsage {
+        self.ports.lock().unwrap().usage()
+    }
+
+    // the next sequence not used by a live session, push_task has the final say
+    pub fn alloc_ssrc(&self, gb_code: &str, playback: bool) -> String {
+        let domain = ssrc::domain(&self.config().ssrc_domain, gb_code);
+        let mut ssrc_str = String::new();
+        for _ in 0..ssrc::SSRC_SEQUENCE_MAX {
+            let sequence =
+                self.ssrc_sequence.fetch_add(1, Ordering::Relaxed) % ssrc::SSRC_SEQUENCE_MAX;
+            ssrc_str = ssrc::make(playback, &domain, sequence);
+            if !ssrc::parse(&ssrc_str).is_some_and(|v| self.ssrc_in_use(v)) {
+                break;
+            }
+        }
+        ssrc_str
+    }
+
+    pub fn ssrc_in_use(&self, ssrc: u32) -> bool {
+        self.join_handlers
+            .lock()
+            .map(|join_handlers| {
+                join_handlers
+                    .values()
+                    .any(|task| task.stream_handler.info.ssrc == ssrc)
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn find_port(&self, gb_code: &str, stream_id: u32) -> Option<u16> {
+        let join_handlers = self.join_handlers.lock().ok()?;
+        join_handlers
+            .iter()
+            .find(|(_, task)| {
+                task.stream_handler.info.gb_code == gb_code
+                    && task.stream_handler.info.stream_id == stream_id
+            })
+            .map(|(port, _)| *port)
+    }
+
+    pub fn find_handler(
         &self,
-        port: u16,
-        cancel_tx: tokio::sync::broadcast::Sender<()>,
-        udp_join_handle: tokio::task::JoinHandle<()>,
-        tcp_join_handle: tokio::task::JoinHandle<()>,
-    ) {
-        if let Ok(mut join_handlers) = self.join_handlers.lock() {
-            join_handlers.insert(
-                port,
-                StreamTask {
-                    cancel_tx,
-                    udp_join_handle,
-                    tcp_join_handle,
-                },
-            );
+        gb_code: &str,
+        stream_id: u32,
+    ) -> Option<std::sync::Arc<StreamHandler>> {
+        let join_handlers = self.join_handlers.lock().ok()?;
+        join_handlers
+            .values()
+            .find(|task| {
+                task.stream_handler.info.gb_code == gb_code
+                    && task.stream_handler.info.stream_id == stream_id
+            })
+            .map(|task| task.stream_handler.clone())
+    }
+
+    // the ssrc is checked under the lock it is inserted with, ssrc_in_use
+    // before binding does not stop two binds racing for one ssrc
+    pub fn push_task(&self, port: u16, task: StreamTask) -> Result<(), PushTaskError> {
+        let mut join_handlers = self.join_handlers.lock().unwrap();
+        let ssrc = task.stream_handler.info.ssrc;
+        if join_handlers
+            .values()
+            .any(|t| t.stream_handler.info.ssrc == ssrc)
+        {
+            return Err(PushTaskError::SsrcInUse(task));
+        }
+        join_handlers.insert(port, task);
+        if self.is_shutting_down() {
+            return Err(PushTaskError::ShuttingDown);
         }
+        Ok(())
     }
 
-    pub async fn pop_task(&self, port: u16) {
+    pub async fn pop_task(&self, port: u16) -> bool {
         let mut udp_handle: Option<tokio::task::JoinHandle<()>> = None;
         let mut tcp_handle: Option<tokio::task::JoinHandle<()>> = None;
+        let mut stream_handler: Option<std::sync::Arc<StreamHandler>> = None;
 
         if let Ok(mut join_handlers) = self.join_handlers.lock() {
             if let Some(task) = join_handlers.remove(&port) {
                 let _ = task.cancel_tx.send(());
                 udp_handle = Some(task.udp_join_handle);
                 tcp_handle = Some(task.tcp_join_handle);
+                stream_handler = Some(task.stream_handler);
             }
         }
 
-        if let (Some(u), Some(t)) = (udp_handle, tcp_handle) {
+        if let (Some(u), Some(t), Some(h)) = (udp_handle, tcp_handle, stream_handler) {
+            tracing::Span::current().follows_from(&h.span);
             let _ = tokio::join!(u, t);
+            h.hub.close();
+            // a partial file is still kept playable
+            h.complete_download("session freed".to_string(), false);
+            for port in h.stop_senders(None) {
+                self.push_port(port);
+            }
+            h.emit_event(StreamEventType::StreamFreed, String::new());
+            return true;
         }
+        false
     }
 }
 
+fn rpc_span(method: &'static str) -> tracing::Span {
+    tracing::info_span!("rpc", method)
+}
+
+fn rpc_code(result: Result<ResponseCode, &Status>) -> String {
+    match result {
+        Ok(code) => code.as_str_name().to_string(),
+        Err(status) => format!("{:?}", status.code()),
+    }
+}
+
+// replies carrying a response code, for the rpc metrics
+trait HasCode {
+    fn response_code(&self) -> ResponseCode;
+}
+
+macro_rules! has_code {
+    ($($reply:ty),+) => {
+        $(impl HasCode for $reply {
+            fn response_code(&self) -> ResponseCode {
+                self.code()
+            }
+        })+
+    };
+}
+
+has_code!(
+    BindStreamPortResponse,
+    FreeStreamPortResponse,
+    ListStreamsResponse,
+    ReloadConfigResponse,
+    SetLogLevelResponse,
+    StartSendRtpResponse,
+    StopSendRtpResponse,
+    StartTalkResponse,
+    StopTalkResponse
+);
+
+// runs a handler in its span and records its duration and response code
+async fn observed<R: HasCode>(
+    method: &'static str,
+    fut: impl std::future::Future<Output = Result<Response<R>, Status>>,
+) -> Result<Response<R>, Status> {
+    let started = std::time::Instant::now();
+    let result = fut.instrument(rpc_span(method)).await;
+    metrics().observe_rpc(
+        method,
+        &rpc_code(result.as_ref().map(|r| r.get_ref().response_code())),
+        started,
+    );
+    result
+}
+
 #[tonic::async_trait]
 impl GbtStreamService for MyGbtStreamService {
     async fn bind_stream_port(
         &self,
         request: Request<BindStreamPortRequest>,
     ) -> Result<Response<BindStreamPortResponse>, Status> {
-        self.rpc_bind_stream_port(request).await
+        observed("bind_stream_port", self.rpc_bind_stream_port(request)).await
     }
 
     async fn free_stream_port(
         &self,
         request: Request<FreeStreamPortRequest>,
     ) -> Result<Response<FreeStreamPortResponse>, Status> {
-        self.rpc_free_stream_port(request).await
+        observed("free_stream_port", self.rpc_free_stream_port(request)).await
+    }
+
+    type subscribe_stream_eventsStream = StreamEventStream;
+
+    async fn subscribe_stream_events(
+        &self,
+        request: Request<SubscribeStreamEventsRequest>,
+    ) -> Result<Response<Self::subscribe_stream_eventsStream>, Status> {
+        self.rpc_subscribe_stream_events(request)
+            .instrument(rpc_span("subscribe_stream_events"))
+            .await
+    }
+
+    async fn list_streams(
+        &self,
+        request: Request<ListStreamsRequest>,
+    ) -> Result<Response<ListStreamsResponse>, Status> {
+        observed("list_streams", self.rpc_list_streams(request)).await
+    }
+
+    async fn reload_config(
+        &self,
+        request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        observed("reload_config", self.rpc_reload_config(request)).await
+    }
+
+    async fn set_log_level(
+        &self,
+        request: Request<SetLogLevelRequest>,
+    ) -> Result<Response<SetLogLevelResponse>, Status> {
+        observed("set_log_level", self.rpc_set_log_level(request)).await
+    }
+
+    async fn start_send_rtp(
+        &self,
+        request: Request<StartSendRtpRequest>,
+    ) -> Result<Response<StartSendRtpResponse>, Status> {
+        observed("start_send_rtp", self.rpc_start_send_rtp(request)).await
+    }
+
+    async fn stop_send_rtp(
+        &self,
+        request: Request<StopSendRtpRequest>,
+    ) -> Result<Response<StopSendRtpResponse>, Status> {
+        observed("stop_send_rtp", self.rpc_stop_send_rtp(request)).await
+    }
+
+    async fn start_talk(
+        &self,
+        request: Request<StartTalkRequest>,
+    ) -> Result<Response<StartTalkResponse>, Status> {
+        observed("start_talk", self.rpc_start_talk(request)).await
+    }
+
+    async fn stop_talk(
+        &self,
+        request: Request<StopTalkRequest>,
+    ) -> Result<Response<StopTalkResponse>, Status> {
+        observed("stop_talk", self.rpc_stop_talk(request)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::handler::StreamInfo;
+
+    fn service() -> MyGbtStreamService {
+        let config: Config = serde_yaml::from_str("{}").unwrap();
+        MyGbtStreamService::new(std::path::PathBuf::new(), config)
+    }
+
+    async fn task(ssrc: u32) -> StreamTask {
+        let local = "127.0.0.1:0";
+        let (cancel_tx, _) = tokio::sync::broadcast::channel(1);
+        let mut cancel_rx = cancel_tx.subscribe();
+        let stream_handler = StreamHandler::new(
+            "127.0.0.1".to_string(),
+            10002,
+            StreamInfo {
+                gb_code: "34020000001320000001".to_string(),
+                stream_id: 1,
+                setup_type: 0,
+                ssrc,
+                ssrc_check: true,
+                source_filter: Default::default(),
+                idle_timeout: Default::default(),
+                on_demand: false,
+                media_interface: String::new(),
+                hub_options: crate::stream::hub::HubOptions {
+                    queue_frames: 16,
+                    gop_frames: 16,
+                },
+                session_type: crate::gss::SessionType::Realtime,
+                download_speed: 1,
+            },
+            tokio::net::UdpSocket::bind(local).await.unwrap(),
+            None,
+            tokio::net::TcpListener::bind(local).await.unwrap(),
+            tokio::sync::broadcast::channel(16).0,
+        );
+        StreamTask {
+            cancel_tx,
+            udp_join_handle: tokio::spawn(async move {
+                let _ = cancel_rx.recv().await;
+            }),
+            tcp_join_handle: tokio::spawn(async {}),
+            stream_handler: std::sync::Arc::new(stream_handler),
+        }
+    }
+
+    #[tokio::test]
+    async fn push_task_rejects_an_ssrc_in_use() {
+        let service = service();
+        assert!(service.push_task(10002, task(200000001).await).is_ok());
+        assert!(service.ssrc_in_use(200000001));
+
+        match service.push_task(10004, task(200000001).await) {
+            Err(PushTaskError::SsrcInUse(task)) => task.cancel().await,
+            _ => panic!("a second session with the ssrc was pushed"),
+        }
+        assert!(service.push_task(10004, task(200000002).await).is_ok());
+        let join_handlers = service.join_handlers.lock().unwrap();
+        assert_eq!(join_handlers.len(), 2);
+        assert_eq!(join_handlers[&10002].stream_handler.info.ssrc, 200000001);
+    }
+
+    #[tokio::test]
+    async fn push_task_after_shutdown_keeps_the_task_to_pop() {
+        let service = service();
+        service.shutdown(tokio::time::Instant::now()).await;
+        assert!(matches!(
+            service.push_task(10002, task(200000001).await),
+            Err(PushTaskError::ShuttingDown)
+        ));
+        assert!(service.pop_task(10002).await);
+    }
+
+    #[tokio::test]
+    async fn alloc_ssrc_skips_live_sessions() {
+        let service = service();
+        assert!(service.push_task(10002, task(200000002).await).is_ok());
+        let gb_code = "34020000001320000001";
+        assert_eq!(service.alloc_ssrc(gb_code, false), "0200000001");
+        assert_eq!(service.alloc_ssrc(gb_code, false), "0200000003");
+        assert_eq!(service.alloc_ssrc(gb_code, true), "1200000004");
     }
 }
@@ -0,0 +1,53 @@
+use std::sync::atomic::Ordering;
+
+use crate::rpc::server::MyGbtStreamService;
+
+impl MyGbtStreamService {
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    // resolves once event streams and http should close
+    pub async fn wait_closed(&self) {
+        let mut closed_rx = self.closed_tx.subscribe();
+        let _ = closed_rx.wait_for(|closed| *closed).await;
+    }
+
+    // refuse new binds, then cancel every session so outputs get flushed and
+    // final events emitted
+    pub async fn shutdown(&self, deadline: tokio::time::Instant) {
+        // set under the lock, a session pushed after is seen by push_task
+        let ports: Vec<u16> = {
+            let join_handlers = self.join_handlers.lock().unwrap();
+            self.shutting_down.store(true, Ordering::Relaxed);
+            join_handlers.keys().cloned().collect()
+        };
+        tracing::warn!("shutdown, draining sessions: {}", ports.len());
+
+        // talks have nothing to flush
+        let talks: Vec<_> = self.talks.lock().unwrap().drain().collect();
+        for (_, talk) in talks {
+            talk.stop();
+            self.push_port(talk.target.local_addr.port());
+        }
+
+        let drain = futures::future::join_all(ports.iter().map(|port| async move {
+            if self.pop_task(*port).await {
+                self.push_port(*port);
+            }
+        }));
+        if tokio::time::timeout_at(deadline, drain).await.is_err() {
+            let remain = self.join_handlers.lock().map(|j| j.len()).unwrap_or(0);
+            tracing::error!(
+                "shutdown deadline exceeded, sessions not drained: {}",
+                remain
+            );
+        } else {
+            tracing::warn!("shutdown, sessions drained");
+        }
+    }
+
+    pub fn close(&self) {
+        let _ = self.closed_tx.send(true);
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::gss::StreamEventType;
+use crate::rpc::server::MyGbtStreamService;
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+impl MyGbtStreamService {
+    // tears down sessions that never sent or stopped sending, and playback and
+    // download sessions whose media ended, reports on-demand sessions nobody
+    // reads and frees them when configured to, reports download progress
+    pub fn start_watchdog(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let mut expired = vec![];
+                let mut ended = vec![];
+                let mut unread = vec![];
+                let mut sender_ports = vec![];
+                let mut progress = vec![];
+                let progress_interval =
+                    Duration::from_secs(service.config().download_progress_interval);
+                if let Ok(join_handlers) = service.join_handlers.lock() {
+                    for (port, task) in join_handlers.iter() {
+                        sender_ports.extend(task.stream_handler.reap_senders());
+                        if let Some(event) = task
+                            .stream_handler
+                            .download_progress_event(progress_interval)
+                        {
+                            progress.push((task.stream_handler.clone(), event));
+                        }
+                        if task.stream_handler.media_ended() {
+                            ended.push(*port);
+                        } else if let Some(reason) = task.stream_handler.idle_reason() {
+                            expired.push((*port, task.stream_handler.clone(), reason));
+                        } else if let Some(reason) = task.stream_handler.no_reader_reason() {
+                            unread.push((*port, task.stream_handler.clone(), reason));
+                        }
+                    }
+                }
+
+                sender_ports.extend(service.reap_talks());
+                for port in sender_ports {
+                    service.push_port(port);
+                }
+
+                for (stream_handler, event) in progress {
+                    stream_handler
+                        .span
+                        .in_scope(|| stream_handler.send_event(event));
+                }
+
+                let no_reader_free = service.config().no_reader_free;
+                for (port, stream_handler, reason) in unread {
+                    stream_handler.span.in_scope(|| {
+                        tracing::warn!("stream no reader, port: {}, reason: {}", port, &reason);
+                        stream_handler.emit_event(StreamEventType::NoReader, reason);
+                    });
+                    if no_reader_free && service.pop_task(port).await {
+                        service.push_port(port);
+                    }
+                }
+
+                for port in ended {
+                    tracing::info!("stream media ended, port: {}", port);
+                    if service.pop_task(port).await {
+                        service.push_port(port);
+                    }
+                }
+
+                for (port, stream_handler, reason) in expired {
+                    stream_handler.span.in_scope(|| {
+                        tracing::warn!("stream timeout, port: {}, reason: {}", port, &reason);
+                        stream_handler.emit_event(StreamEventType::StreamTimeout, reason);
+                    });
+                    if service.pop_task(port).await {
+                        service.push_port(port);
+                    }
+                }
+            }
+        })
+    }
+}
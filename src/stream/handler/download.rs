@@ -0,0 +1,319 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::StreamHandler;
+
+use crate::gss::{StreamEvent, StreamEventType};
+use crate::stream::utils::mp4::Mp4Writer;
+use crate::stream::utils::ps::{self, Demuxer};
+use crate::stream::utils::psmux::Codec;
+use crate::stream::utils::reorder::Frame;
+
+// ps over rtp, 90 khz
+const RTP_CLOCK_RATE: f64 = 90000.0;
+
+pub fn download_url(name: &str) -> String {
+    format!("/api/downloads/{}", name)
+}
+
+// <gb_code>-<start_time>-<end_time>-<ssrc>.mp4, the gb_code kept to safe characters
+pub fn file_name(gb_code: &str, start_time: i64, end_time: i64, ssrc: &str) -> String {
+    let gb_code: String = gb_code
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}-{}-{}-{}.mp4", gb_code, start_time, end_time, ssrc)
+}
+
+// what file_name makes, nothing that leads out of the download directory
+pub fn is_file_name(name: &str) -> bool {
+    name.ends_with(".mp4")
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+// a session written to an mp4 file, progress is measured with the rtp
+// timestamps against the requested range
+pub struct Download {
+    pub name: String,
+    // unix seconds
+    pub start_time: i64,
+    pub end_time: i64,
+    pub speed: u32,
+    // None once complete
+    writer: Option<Mp4Writer>,
+    // complete and under its final name
+    saved: bool,
+    demuxer: Demuxer,
+    first_timestamp: Option<u32>,
+    last_timestamp: u32,
+    // rtp timestamp units since the first frame
+    elapsed: u64,
+    first_frame_at: Option<Instant>,
+    last_progress_at: Instant,
+    progress: f32,
+}
+
+impl Download {
+    // <dir>/<name>, written as <name>.part until complete
+    pub fn create(
+        dir: &str,
+        name: String,
+        start_time: i64,
+        end_time: i64,
+        speed: u32,
+    ) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let writer = Mp4Writer::create(&Path::new(dir).join(&name))?;
+        Ok(Download {
+            name,
+            start_time,
+            end_time,
+            speed,
+            writer: Some(writer),
+            saved: false,
+            demuxer: Demuxer::default(),
+            first_timestamp: None,
+            last_timestamp: 0,
+            elapsed: 0,
+            first_frame_at: None,
+            last_progress_at: Instant::now(),
+            progress: 0.0,
+        })
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.writer.is_none()
+    }
+
+    fn on_timestamp(&mut self, timestamp: u32) {
+        if self.first_timestamp.is_none() {
+            self.first_timestamp = Some(timestamp);
+            self.last_timestamp = timestamp;
+            self.first_frame_at = Some(Instant::now());
+        }
+        // forward only, a step back is reordering or a device restarting
+        let delta = timestamp.wrapping_sub(self.last_timestamp);
+        if delta < 0x8000_0000 {
+            self.elapsed += delta as u64;
+            self.last_timestamp = timestamp;
+        }
+        let range = self.end_time - self.start_time;
+        if range > 0 {
+            self.progress = (self.media_seconds() / range as f64).min(1.0) as f32;
+        }
+    }
+
+    fn media_seconds(&self) -> f64 {
+        self.elapsed as f64 / RTP_CLOCK_RATE
+    }
+
+    // media seconds per wall clock second since the first frame
+    fn measured_speed(&self) -> f64 {
+        match self.first_frame_at {
+            None => 0.0,
+            Some(at) => self.media_seconds() / at.elapsed().as_secs_f64().max(0.001),
+        }
+    }
+
+    fn write_frames(&mut self, frames: Vec<ps::EsFrame>) -> std::io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        let (video, audio) = self.demuxer.codecs();
+        for frame in frames {
+            if ps::is_video(frame.stream_id) {
+                if let Some(codec) = Codec::from_name(video) {
+                    writer.write_video(codec, &frame.data, frame.pts, frame.dts)?;
+                }
+            } else if let Some(codec) = Codec::from_name(audio) {
+                writer.write_audio(codec, &frame.data, frame.pts)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn url(&self) -> Option<String> {
+        self.saved.then(|| download_url(&self.name))
+    }
+
+    fn finish(&mut self) {
+        let frames = self.demuxer.flush();
+        if let Err(e) = self.write_frames(frames) {
+            tracing::error!("Mp4Writer::write error, e: {:?}", e);
+        }
+        let Some(writer) = self.writer.take() else {
+            return;
+        };
+        match writer.finish() {
+            Err(e) => tracing::error!("Mp4Writer::finish error, e: {:?}", e),
+            Ok(path) => {
+                tracing::info!("download saved, path: {}", path.display());
+                self.saved = true;
+            }
+        }
+    }
+}
+
+impl StreamHandler {
+    pub fn set_download(&self, download: Download) {
+        *self.download.lock().unwrap() = Some(download);
+    }
+
+    pub fn on_download_frame(&self, frame: &Frame) {
+        let mut download = self.download.lock().unwrap();
+        let Some(download) = download.as_mut().filter(|d| !d.is_complete()) else {
+            return;
+        };
+        download.on_timestamp(frame.timestamp);
+        let frames = download.demuxer.push(&frame.to_vec());
+        if let Err(e) = download.write_frames(frames) {
+            tracing::error!("Mp4Writer::write error, e: {:?}", e);
+            // the file is of no use, left as .part
+            download.writer = None;
+            self.emit_event(StreamEventType::DownloadComplete, e.to_string());
+        }
+    }
+
+    // on a bye or the tcp connection closing, sent_all, or the session being
+    // freed, once
+    pub fn complete_download(&self, reason: String, sent_all: bool) {
+        let mut download = self.download.lock().unwrap();
+        let Some(download) = download.as_mut().filter(|d| !d.is_complete()) else {
+            return;
+        };
+        download.finish();
+        if let Some(url) = download.url() {
+            let mut event =
+                self.make_event(StreamEventType::RecordSegmentDone, download.name.clone());
+            event.download_url = url;
+            self.send_event(event);
+        }
+        // the device sent what it had, whatever the timestamps say
+        if sent_all && download.saved {
+            download.progress = 1.0;
+        }
+        let mut event = self.make_event(StreamEventType::DownloadComplete, reason);
+        event.progress = download.progress;
+        event.download_url = download.url().unwrap_or_default();
+        self.send_event(event);
+    }
+
+    // every interval while downloading, with the speed the device keeps
+    pub fn download_progress_event(&self, interval: Duration) -> Option<StreamEvent> {
+        let mut download = self.download.lock().unwrap();
+        let download = download.as_mut().filter(|d| !d.is_complete())?;
+        if interval.is_zero()
+            || download.first_frame_at.is_none()
+            || download.last_progress_at.elapsed() < interval
+        {
+            return None;
+        }
+        download.last_progress_at = Instant::now();
+        let message = format!(
+            "media: {:.1}s, speed: {:.1}x, requested: {}x",
+            download.media_seconds(),
+            download.measured_speed(),
+            download.speed
+        );
+        let mut event = self.make_event(StreamEventType::DownloadProgress, message);
+        event.progress = download.progress;
+        Some(event)
+    }
+
+    // (progress, download_url) for list_streams
+    pub fn download_state(&self) -> Option<(f32, String)> {
+        let download = self.download.lock().unwrap();
+        let download = download.as_ref()?;
+        Some((download.progress, download.url().unwrap_or_default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name_is_a_valid_file_name() {
+        let name = file_name(
+            "3402000000/../1320000001",
+            1700000000,
+            1700000600,
+            "1100000001",
+        );
+        assert_eq!(
+            name,
+            "3402000000____1320000001-1700000000-1700000600-1100000001.mp4"
+        );
+        assert!(is_file_name(&name));
+        assert_eq!(download_url(&name), format!("/api/downloads/{}", name));
+    }
+
+    #[tokio::test]
+    async fn complete_download_reports_the_saved_file() {
+        let local = "127.0.0.1:0";
+        let (event_tx, mut event_rx) = tokio::sync::broadcast::channel(16);
+        let handler = StreamHandler::new(
+            "127.0.0.1".to_string(),
+            10002,
+            crate::stream::handler::StreamInfo {
+                gb_code: "34020000001320000001".to_string(),
+                stream_id: 1,
+                setup_type: 0,
+                ssrc: 1100000001,
+                ssrc_check: true,
+                source_filter: Default::default(),
+                idle_timeout: Default::default(),
+                on_demand: false,
+                media_interface: String::new(),
+                hub_options: crate::stream::hub::HubOptions {
+                    queue_frames: 16,
+                    gop_frames: 16,
+                },
+                session_type: crate::gss::SessionType::Download,
+                download_speed: 4,
+            },
+            tokio::net::UdpSocket::bind(local).await.unwrap(),
+            None,
+            tokio::net::TcpListener::bind(local).await.unwrap(),
+            event_tx,
+        );
+        let dir = std::env::temp_dir().join(format!("downloads.{}", std::process::id()));
+        let name = file_name("34020000001320000001", 0, 60, "1100000001");
+        let download = Download::create(dir.to_str().unwrap(), name.clone(), 0, 60, 4).unwrap();
+        handler.set_download(download);
+
+        handler.complete_download("bye".to_string(), true);
+        handler.complete_download("freed".to_string(), false);
+        assert!(dir.join(&name).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let done = event_rx.try_recv().unwrap();
+        assert_eq!(done.event_type(), StreamEventType::RecordSegmentDone);
+        assert_eq!(done.message, name);
+        assert_eq!(done.download_url, download_url(&name));
+        let complete = event_rx.try_recv().unwrap();
+        assert_eq!(complete.event_type(), StreamEventType::DownloadComplete);
+        assert_eq!(complete.progress, 1.0);
+        assert_eq!(complete.download_url, download_url(&name));
+        // once
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn rejects_names_leading_out_of_the_directory() {
+        for name in [
+            "../secret.mp4",
+            "dir/a.mp4",
+            "a\\b.mp4",
+            ".hidden.mp4",
+            "a.mp4.part",
+            "a.txt",
+            "",
+        ] {
+            assert!(!is_file_name(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use super::StreamHandler;
+
+use crate::gss::{StreamEvent, StreamEventType};
+use crate::stream::utils::ssrc;
+
+impl StreamHandler {
+    pub fn make_event(&self, event_type: StreamEventType, message: String) -> StreamEvent {
+        StreamEvent {
+            event_type: event_type.into(),
+            gb_code: self.info.gb_code.clone(),
+            stream_id: self.info.stream_id,
+            media_server_port: self.port as u32,
+            ssrc: ssrc::to_string(self.info.ssrc),
+            message,
+            stats: Some(self.stats.snapshot()),
+            timestamp: chrono::Local::now().timestamp_millis(),
+            ..Default::default()
+        }
+    }
+
+    pub fn emit_event(&self, event_type: StreamEventType, message: String) {
+        self.send_event(self.make_event(event_type, message));
+    }
+
+    pub fn send_event(&self, event: StreamEvent) {
+        tracing::info!(
+            "stream event, port: {}, type: {}, message: {}",
+            self.port,
+            event.event_type().as_str_name(),
+            &event.message
+        );
+        // no subscribers is not an error
+        let _ = self.event_tx.send(event);
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use super::StreamHandler;
+
+#[derive(Default, Clone, Copy)]
+pub struct IdleTimeout {
+    // seconds, 0 disables
+    pub no_data_on_start: u32,
+    pub no_data: u32,
+    // seconds without readers, set for on-demand sessions only
+    pub no_reader: u32,
+}
+
+impl StreamHandler {
+    // true on the first data of the session
+    pub fn touch(&self) -> bool {
+        // 0 is reserved for "no data yet"
+        let elapsed = self.created_at.elapsed().as_millis() as u64 + 1;
+        self.last_data_ms.swap(elapsed, Ordering::Relaxed) == 0
+    }
+
+    pub fn idle_reason(&self) -> Option<String> {
+        let timeout = self.info.idle_timeout;
+        let elapsed = self.created_at.elapsed();
+        match self.last_data_ms.load(Ordering::Relaxed) {
+            0 => {
+                if timeout.no_data_on_start > 0
+                    && elapsed >= Duration::from_secs(timeout.no_data_on_start as u64)
+                {
+                    return Some(format!(
+                        "no data since start for {}s",
+                        timeout.no_data_on_start
+                    ));
+                }
+            }
+            last_data_ms => {
+                let idle = elapsed.saturating_sub(Duration::from_millis(last_data_ms - 1));
+                if timeout.no_data > 0 && idle >= Duration::from_secs(timeout.no_data as u64) {
+                    return Some(format!("no data for {}s", timeout.no_data));
+                }
+            }
+        }
+        None
+    }
+
+    // once per stretch without readers, the count is taken on every check
+    pub fn no_reader_reason(&self) -> Option<String> {
+        let no_reader = self.info.idle_timeout.no_reader;
+        if no_reader == 0 {
+            return None;
+        }
+        let elapsed = self.created_at.elapsed();
+        if self.hub.subscribers() > 0 {
+            self.last_reader_ms
+                .store(elapsed.as_millis() as u64, Ordering::Relaxed);
+            self.no_reader_notified.store(false, Ordering::Relaxed);
+            return None;
+        }
+        let idle = elapsed.saturating_sub(Duration::from_millis(
+            self.last_reader_ms.load(Ordering::Relaxed),
+        ));
+        if idle < Duration::from_secs(no_reader as u64)
+            || self.no_reader_notified.swap(true, Ordering::Relaxed)
+        {
+            return None;
+        }
+        Some(format!("no reader for {}s", no_reader))
+    }
+}
@@ -1,24 +1,112 @@
+pub mod download;
+pub mod event;
+pub mod idle;
+pub mod rtcp;
 pub mod rtp;
+pub mod send;
+pub mod source;
+
+use std::sync::atomic::{AtomicBool, AtomicU64};
+
+use crate::gss::{SessionType, StreamEvent, StreamSetupType};
+use crate::stream::hub::{Hub, HubOptions};
+use crate::stream::sender::RtpSender;
+use crate::stream::utils::stats::StreamStats;
+
+pub struct StreamInfo {
+    pub gb_code: String,
+    pub stream_id: u32,
+    pub setup_type: i32,
+    pub ssrc: u32,
+    pub ssrc_check: bool,
+    pub source_filter: source::SourceFilter,
+    pub idle_timeout: idle::IdleTimeout,
+    // closed by the watchdog when nobody reads, or always-on
+    pub on_demand: bool,
+    pub media_interface: String,
+    pub hub_options: HubOptions,
+    pub session_type: SessionType,
+    // requested from the device, 1 for realtime
+    pub download_speed: u32,
+}
 
 pub struct StreamHandler {
     pub ip: String,
     pub port: u16,
+    pub info: StreamInfo,
     pub stream_udp_socket: tokio::net::UdpSocket,
+    pub stream_rtcp_socket: Option<tokio::net::UdpSocket>,
     pub stream_tcp_listener: tokio::net::TcpListener,
+    pub tcp_writer: tokio::sync::Mutex<Option<tokio::net::tcp::OwnedWriteHalf>>,
+    pub stats: StreamStats,
+    // frames to every output
+    pub hub: Hub,
+    // targets the frames are sent on to
+    pub senders: std::sync::Mutex<Vec<RtpSender>>,
+    pub rtcp: std::sync::Mutex<rtcp::RtcpSession>,
+    pub source: std::sync::Mutex<source::SourceState>,
+    // download sessions only
+    pub download: std::sync::Mutex<Option<download::Download>>,
+    pub event_tx: tokio::sync::broadcast::Sender<StreamEvent>,
+    pub created_at: std::time::Instant,
+    // root span of the session tasks, linked from the rpcs that touch it
+    pub span: tracing::Span,
+    last_data_ms: AtomicU64,
+    // since session start, 0 for no reader yet
+    last_reader_ms: AtomicU64,
+    no_reader_notified: AtomicBool,
+    local_ssrc: u32,
+    // since session start, 0 for no mismatch reported yet
+    last_mismatch_ms: AtomicU64,
 }
 
 impl StreamHandler {
     pub fn new(
         ip: String,
         port: u16,
+        info: StreamInfo,
         stream_udp_socket: tokio::net::UdpSocket,
+        stream_rtcp_socket: Option<tokio::net::UdpSocket>,
         stream_tcp_listener: tokio::net::TcpListener,
+        event_tx: tokio::sync::broadcast::Sender<StreamEvent>,
     ) -> Self {
+        // our own ssrc in receiver reports, only has to differ from the sender's
+        let local_ssrc = !info.ssrc ^ port as u32;
+        // outlives the bind rpc, so a new root that follows from it
+        let span = tracing::info_span!(
+            parent: None,
+            "session",
+            gb_code = %info.gb_code,
+            stream_id = info.stream_id,
+            port,
+            setup_type = StreamSetupType::try_from(info.setup_type)
+                .map(|t| t.as_str_name())
+                .unwrap_or("unknown"),
+        );
+        span.follows_from(tracing::Span::current());
+        let hub = Hub::new(info.hub_options);
         StreamHandler {
             ip,
             port,
+            info,
             stream_udp_socket,
+            stream_rtcp_socket,
             stream_tcp_listener,
+            tcp_writer: tokio::sync::Mutex::new(None),
+            stats: StreamStats::default(),
+            hub,
+            senders: std::sync::Mutex::new(vec![]),
+            rtcp: std::sync::Mutex::new(rtcp::RtcpSession::default()),
+            source: std::sync::Mutex::new(source::SourceState::default()),
+            download: std::sync::Mutex::new(None),
+            event_tx,
+            created_at: std::time::Instant::now(),
+            span,
+            last_data_ms: AtomicU64::new(0),
+            last_reader_ms: AtomicU64::new(0),
+            no_reader_notified: AtomicBool::new(false),
+            local_ssrc,
+            last_mismatch_ms: AtomicU64::new(0),
         }
     }
 }
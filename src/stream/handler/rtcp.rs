@@ -0,0 +1,238 @@
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+
+use tokio::io::AsyncWriteExt;
+
+use super::StreamHandler;
+
+use crate::gss::{SessionType, StreamEventType};
+use crate::stream::utils::rtcp::{self, ReceiverStatistics, ReportBlock, RtcpPacket};
+
+// gb28181 ps over rtp always uses a 90kHz clock
+pub const RTP_CLOCK_RATE: u64 = 90000;
+
+const TCP_REPORT_WRITE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+pub enum RtcpRoute {
+    // rtcp-mux, back through the rtp socket
+    Udp(SocketAddr),
+    // through the dedicated rtcp socket (rtp port + 1)
+    UdpRtcpPort(SocketAddr),
+    // interleaved on the tcp connection
+    Tcp,
+}
+
+#[derive(Default)]
+pub struct RtcpSession {
+    pub statistics: ReceiverStatistics,
+    pub rtp_remote: Option<SocketAddr>,
+    pub rtcp_remote: Option<(SocketAddr, bool)>, // (addr, arrived on rtcp port)
+    pub last_sr_ntp: u64,
+    pub last_sr_rtp: u32,
+    pub last_sr_at: Option<std::time::Instant>,
+    pub bye: bool,
+}
+
+impl RtcpSession {
+    // wallclock (ntp) of a rtp timestamp, from the latest sender report
+    pub fn rtp_to_ntp(&self, rtp_timestamp: u32) -> Option<u64> {
+        self.last_sr_at?;
+        let delta = rtp_timestamp.wrapping_sub(self.last_sr_rtp) as i32 as i64;
+        let delta_ntp = (delta << 32) / RTP_CLOCK_RATE as i64;
+        Some((self.last_sr_ntp as i64 + delta_ntp) as u64)
+    }
+}
+
+impl StreamHandler {
+    pub fn on_rtcp(&self, addr: SocketAddr, buff: &[u8], from_rtcp_port: bool) -> bool {
+        self.stats.rtcp_packets.fetch_add(1, Ordering::Relaxed);
+
+        let packets = match rtcp::parse(buff) {
+            Err(e) => {
+                tracing::error!("rtcp::parse error, port: {}, e: {}", self.port, e);
+                return false;
+            }
+            Ok(packets) => packets,
+        };
+
+        let mut bye_message = None;
+        let mut rtcp_session = self.rtcp.lock().unwrap();
+        rtcp_session.rtcp_remote = Some((addr, from_rtcp_port));
+        for packet in packets {
+            match packet {
+                RtcpPacket::SenderReport(sr) => {
+                    if self.info.ssrc_check && sr.ssrc != self.info.ssrc {
+                        tracing::warn!(
+                            "port: {}, ignore sender report of ssrc: {}",
+                            self.port,
+                            sr.ssrc
+                        );
+                        continue;
+                    }
+                    tracing::debug!(
+                        "port: {}, sender report, ntp: {}, rtp: {}, packets: {}, octets: {}",
+                        self.port,
+                        sr.ntp_timestamp,
+                        sr.rtp_timestamp,
+                        sr.packet_count,
+                        sr.octet_count
+                    );
+                    rtcp_session.last_sr_ntp = sr.ntp_timestamp;
+                    rtcp_session.last_sr_rtp = sr.rtp_timestamp;
+                    rtcp_session.last_sr_at = Some(std::time::Instant::now());
+                    self.stats
+                        .sr_ntp_timestamp
+                        .store(sr.ntp_timestamp, Ordering::Relaxed);
+                    self.stats
+                        .sr_rtp_timestamp
+                        .store(sr.rtp_timestamp, Ordering::Relaxed);
+                }
+                RtcpPacket::Bye(bye) => {
+                    if self.info.ssrc_check && !bye.ssrcs.contains(&self.info.ssrc) {
+                        continue;
+                    }
+                    bye_message = Some(format!("rtcp bye from: {}, reason: {}", addr, bye.reason));
+                }
+                RtcpPacket::Other(_) => {}
+            }
+        }
+        drop(rtcp_session);
+        if let Some(message) = bye_message {
+            self.on_media_end(message);
+        }
+        true
+    }
+
+    // a bye, or playback and download closing their tcp connection, once
+    pub fn on_media_end(&self, message: String) {
+        if std::mem::replace(&mut self.rtcp.lock().unwrap().bye, true) {
+            return;
+        }
+        self.emit_event(StreamEventType::StreamBye, message.clone());
+        self.complete_download(message, true);
+    }
+
+    // playback and download sessions are over once the media ended, the
+    // watchdog frees them
+    pub fn media_ended(&self) -> bool {
+        self.info.session_type != SessionType::Realtime && self.rtcp.lock().unwrap().bye
+    }
+
+    pub fn update_receiver_statistics(
+        &self,
+        addr: SocketAddr,
+        sequence_number: u16,
+        rtp_timestamp: u32,
+    ) {
+        let arrival =
+            (self.created_at.elapsed().as_micros() as u64 * RTP_CLOCK_RATE / 1_000_000) as u32;
+
+        let mut rtcp_session = self.rtcp.lock().unwrap();
+        rtcp_session.rtp_remote = Some(addr);
+        rtcp_session
+            .statistics
+            .update(sequence_number, rtp_timestamp, arrival);
+        self.stats
+            .packets_lost
+            .store(rtcp_session.statistics.lost(), Ordering::Relaxed);
+        self.stats
+            .jitter
+            .store(rtcp_session.statistics.jitter(), Ordering::Relaxed);
+    }
+
+    fn rtcp_route(&self, rtcp_session: &RtcpSession, tcp_connected: bool) -> Option<RtcpRoute> {
+        if tcp_connected {
+            return Some(RtcpRoute::Tcp);
+        }
+        match (rtcp_session.rtcp_remote, rtcp_session.rtp_remote) {
+            (Some((addr, true)), _) => Some(RtcpRoute::UdpRtcpPort(addr)),
+            (Some((addr, false)), _) => Some(RtcpRoute::Udp(addr)),
+            (None, Some(addr)) if self.stream_rtcp_socket.is_some() => Some(
+                RtcpRoute::UdpRtcpPort(SocketAddr::new(addr.ip(), addr.port().wrapping_add(1))),
+            ),
+            (None, Some(addr)) => Some(RtcpRoute::Udp(addr)),
+            (None, None) => None,
+        }
+    }
+
+    // never waits on a peer that does not read, a report is skipped instead
+    pub async fn send_receiver_report(&self) {
+        let Ok(mut tcp_writer) = self.tcp_writer.try_lock() else {
+            return;
+        };
+
+        let (route, report) = {
+            let mut rtcp_session = self.rtcp.lock().unwrap();
+            if !rtcp_session.statistics.has_data() || rtcp_session.bye {
+                return;
+            }
+            let route = match self.rtcp_route(&rtcp_session, tcp_writer.is_some()) {
+                None => return,
+                Some(route) => route,
+            };
+
+            let delay = match rtcp_session.last_sr_at {
+                None => 0,
+                Some(at) => (at.elapsed().as_micros() as u64 * 65536 / 1_000_000) as u32,
+            };
+            let block = ReportBlock {
+                ssrc: self.info.ssrc,
+                fraction_lost: rtcp_session.statistics.take_fraction_lost(),
+                total_lost: rtcp_session.statistics.lost(),
+                last_sequence_number: rtcp_session.statistics.extended_max_seq(),
+                jitter: rtcp_session.statistics.jitter(),
+                last_sender_report: rtcp::ntp_middle(rtcp_session.last_sr_ntp),
+                delay,
+            };
+            (
+                route,
+                rtcp::make_receiver_report(self.local_ssrc, &block, &self.ip),
+            )
+        };
+
+        let result = match route {
+            RtcpRoute::Udp(addr) => self
+                .stream_udp_socket
+                .send_to(&report, addr)
+                .await
+                .map(|_| ()),
+            RtcpRoute::UdpRtcpPort(addr) => match &self.stream_rtcp_socket {
+                None => Ok(()),
+                Some(socket) => socket.send_to(&report, addr).await.map(|_| ()),
+            },
+            RtcpRoute::Tcp => match tcp_writer.as_mut() {
+                None => Ok(()),
+                Some(writer) => {
+                    let mut framed = Vec::with_capacity(2 + report.len());
+                    framed.extend_from_slice(&(report.len() as u16).to_be_bytes());
+                    framed.extend_from_slice(&report);
+                    match writer.try_write(&framed) {
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+                        Err(e) => Err(e),
+                        Ok(n) if n == framed.len() => Ok(()),
+                        // the rest of a started frame has to follow, or the framing breaks
+                        Ok(n) => {
+                            let rest = writer.write_all(&framed[n..]);
+                            match tokio::time::timeout(TCP_REPORT_WRITE_TIMEOUT, rest).await {
+                                Ok(result) => result,
+                                Err(e) => {
+                                    // no more reports on this connection
+                                    *tcp_writer = None;
+                                    Err(e.into())
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+        };
+
+        if let Err(e) = result {
+            tracing::warn!(
+                "send receiver report error, port: {}, e: {:?}",
+                self.port,
+                e
+            );
+        }
+    }
+}
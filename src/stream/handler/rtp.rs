@@ -1,33 +1,124 @@
 use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
 
 use super::StreamHandler;
 
+use crate::gss::StreamEventType;
 use crate::stream::utils::reorder::RtpPacketReOrder;
+use crate::stream::utils::socket::RecvBatch;
+use crate::stream::utils::{rtcp, ssrc};
+use crate::utils::metrics::metrics;
 
+use bytes::Bytes;
 use rtp;
 
 use webrtc_util::Unmarshal;
 
+// at most one ssrc_mismatch event per session in this time
+const SSRC_MISMATCH_EVENT_INTERVAL_MS: u64 = 10_000;
+
 impl StreamHandler {
+    // datagrams of one recv_batch in arrival order, already in the pool,
+    // returns how many were accepted
+    pub fn on_rtp_batch(
+        &self,
+        batch: &mut RecvBatch,
+        packets_reorder: &mut RtpPacketReOrder,
+    ) -> usize {
+        batch
+            .drain()
+            .map(|(addr, buff)| self.on_rtp(addr, buff, packets_reorder))
+            .filter(|accepted| *accepted)
+            .count()
+    }
+
+    // the payload and the frames it ends up in share the packet's memory
     pub fn on_rtp(
         &self,
-        _addr: SocketAddr,
-        buff: &[u8],
+        addr: SocketAddr,
+        buff: Bytes,
         packets_reorder: &mut RtpPacketReOrder,
     ) -> bool {
-        let mut b = buff;
+        if !self.check_source(addr) {
+            return false;
+        }
+
+        // rtcp-mux
+        if rtcp::is_rtcp(&buff) {
+            return self.on_rtcp(addr, &buff, false);
+        }
+
+        let mut b = buff.clone();
         match rtp::packet::Packet::unmarshal(&mut b) {
             Err(e) => {
                 tracing::error!("rtp::packet::Packet::unmarshal error, e: {:?}", e);
                 false
             }
             Ok(rtp_packet) => {
+                if !self.check_ssrc(addr, rtp_packet.header.ssrc) {
+                    return false;
+                }
+
+                let arrived = self.touch();
+                self.stats.on_rtp(buff.len());
+                if arrived {
+                    self.emit_event(
+                        StreamEventType::StreamArrive,
+                        format!("first rtp packet from: {}", addr),
+                    );
+                }
+                self.update_receiver_statistics(
+                    addr,
+                    rtp_packet.header.sequence_number,
+                    rtp_packet.header.timestamp,
+                );
+                let dropped = packets_reorder.dropped();
                 if packets_reorder.feed_rtp(rtp_packet) {
-                    let (ts, frame) = packets_reorder.pop_frame();
-                    tracing::info!("ts: {}, frame size: {}", ts, frame.len());
+                    let frame = packets_reorder.pop_frame();
+                    tracing::info!("ts: {}, frame size: {}", frame.timestamp, frame.len());
+                    self.stats
+                        .on_frame(self.created_at.elapsed().as_millis() as u64);
+                    self.on_download_frame(&frame);
+                    self.hub.publish(frame);
+                }
+                if packets_reorder.dropped() != dropped {
+                    self.stats.reorder_drops.fetch_add(1, Ordering::Relaxed);
+                    metrics().reorder_drops.inc();
                 }
                 true
             }
         }
     }
+
+    fn check_ssrc(&self, addr: SocketAddr, packet_ssrc: u32) -> bool {
+        if !self.info.ssrc_check || packet_ssrc == self.info.ssrc {
+            return true;
+        }
+
+        self.stats
+            .ssrc_mismatch_packets
+            .fetch_add(1, Ordering::Relaxed);
+
+        // one report per interval, however many foreign ssrcs there are
+        let now = self.created_at.elapsed().as_millis() as u64 + 1;
+        let last = self.last_mismatch_ms.load(Ordering::Relaxed);
+        let due = last == 0 || now - last >= SSRC_MISMATCH_EVENT_INTERVAL_MS;
+        if due
+            && self
+                .last_mismatch_ms
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let message = format!(
+                "ssrc mismatch, expected: {}, got: {}, from: {}, mismatched packets: {}",
+                ssrc::to_string(self.info.ssrc),
+                ssrc::to_string(packet_ssrc),
+                addr,
+                self.stats.ssrc_mismatch_packets.load(Ordering::Relaxed)
+            );
+            tracing::warn!("port: {}, {}", self.port, &message);
+            self.emit_event(StreamEventType::SsrcMismatch, message);
+        }
+        false
+    }
 }
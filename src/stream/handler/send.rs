@@ -0,0 +1,69 @@
+use std::sync::atomic::Ordering;
+
+use super::StreamHandler;
+
+use crate::gss::SendRtpTarget;
+use crate::stream::sender::RtpSender;
+use crate::stream::utils::ssrc;
+
+impl StreamHandler {
+    pub fn has_sender(&self, ssrc: u32) -> bool {
+        self.senders
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| s.target.ssrc == ssrc)
+    }
+
+    pub fn add_sender(&self, sender: RtpSender) {
+        self.senders.lock().unwrap().push(sender);
+    }
+
+    // stops the target of ssrc or all of them, returns their local ports
+    pub fn stop_senders(&self, ssrc: Option<u32>) -> Vec<u16> {
+        let mut ports = vec![];
+        self.senders.lock().unwrap().retain(|sender| {
+            if ssrc.is_some_and(|ssrc| ssrc != sender.target.ssrc) {
+                return true;
+            }
+            sender.stop();
+            ports.push(sender.target.local_addr.port());
+            false
+        });
+        ports
+    }
+
+    pub fn send_targets(&self) -> Vec<SendRtpTarget> {
+        self.senders
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|sender| SendRtpTarget {
+                ssrc: ssrc::to_string(sender.target.ssrc),
+                setup_type: sender.target.setup_type.into(),
+                dst_ip: sender
+                    .target
+                    .dst
+                    .map(|dst| dst.ip().to_string())
+                    .unwrap_or_default(),
+                dst_port: sender.target.dst.map(|dst| dst.port()).unwrap_or(0) as u32,
+                local_port: sender.target.local_addr.port() as u32,
+                rtp_packets: sender.packets.load(Ordering::Relaxed),
+                rtp_bytes: sender.bytes.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // drops targets that ended by themselves, returns their local ports
+    pub fn reap_senders(&self) -> Vec<u16> {
+        let mut ports = vec![];
+        self.senders.lock().unwrap().retain(|sender| {
+            if !sender.is_finished() {
+                return true;
+            }
+            ports.push(sender.target.local_addr.port());
+            false
+        });
+        ports
+    }
+}
@@ -0,0 +1,159 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use super::StreamHandler;
+
+// at most one warning per interval and session, the rest are counted
+const REJECT_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+pub struct SourceFilter {
+    // only this device ip may send
+    pub expected_ip: Option<IpAddr>,
+    // lock to the ip of the first accepted packet
+    pub lock_first: bool,
+}
+
+impl SourceFilter {
+    pub fn is_allowed(&self, ip: &IpAddr, allowlists: &[Vec<ipnet::IpNet>]) -> bool {
+        if let Some(expected_ip) = &self.expected_ip {
+            if expected_ip != ip {
+                return false;
+            }
+        }
+        allowlists
+            .iter()
+            .all(|allowlist| allowlist.is_empty() || allowlist.iter().any(|net| net.contains(ip)))
+    }
+}
+
+#[derive(Default)]
+pub struct SourceState {
+    // every non-empty list must contain the source ip, replaced on config reload
+    pub allowlists: Vec<Vec<ipnet::IpNet>>,
+    pub locked_ip: Option<IpAddr>,
+    last_warn: Option<Instant>,
+    suppressed: u64,
+}
+
+impl StreamHandler {
+    pub fn set_allowlists(&self, allowlists: Vec<Vec<ipnet::IpNet>>) {
+        self.source.lock().unwrap().allowlists = allowlists;
+    }
+
+    pub fn check_source(&self, addr: SocketAddr) -> bool {
+        let ip = addr.ip().to_canonical();
+        let filter = &self.info.source_filter;
+
+        let mut source = self.source.lock().unwrap();
+        let allowed = filter.is_allowed(&ip, &source.allowlists)
+            && match source.locked_ip {
+                Some(locked_ip) => locked_ip == ip,
+                None => true,
+            };
+
+        if allowed {
+            if filter.lock_first && source.locked_ip.is_none() {
+                tracing::info!("port: {}, source locked to: {}", self.port, ip);
+                source.locked_ip = Some(ip);
+            }
+            return true;
+        }
+
+        self.stats.rejected_packets.fetch_add(1, Ordering::Relaxed);
+
+        let now = Instant::now();
+        match source.last_warn {
+            Some(at) if now.duration_since(at) < REJECT_WARN_INTERVAL => {
+                source.suppressed += 1;
+            }
+            _ => {
+                tracing::warn!(
+                    "port: {}, rejected packet from: {}, locked: {:?}, suppressed since last warning: {}",
+                    self.port,
+                    addr,
+                    source.locked_ip,
+                    source.suppressed
+                );
+                source.last_warn = Some(now);
+                source.suppressed = 0;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn nets(nets: &[&str]) -> Vec<ipnet::IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn empty_filter_allows_any_source() {
+        let filter = SourceFilter::default();
+        assert!(filter.is_allowed(&ip("192.168.1.10"), &[]));
+        assert!(filter.is_allowed(&ip("2001:db8::1"), &[vec![], vec![]]));
+    }
+
+    #[test]
+    fn expected_ip_allows_only_the_device() {
+        let filter = SourceFilter {
+            expected_ip: Some(ip("192.168.1.10")),
+            lock_first: false,
+        };
+        assert!(filter.is_allowed(&ip("192.168.1.10"), &[]));
+        assert!(!filter.is_allowed(&ip("192.168.1.11"), &[]));
+        // the allowlists still apply to the device
+        assert!(!filter.is_allowed(&ip("192.168.1.10"), &[nets(&["10.0.0.0/8"])]));
+    }
+
+    #[test]
+    fn allowlists_match_cidr_ranges() {
+        let filter = SourceFilter::default();
+        let allowlists = [nets(&["10.0.0.0/8", "192.168.1.0/24", "2001:db8::/32"])];
+        assert!(filter.is_allowed(&ip("10.255.0.1"), &allowlists));
+        assert!(filter.is_allowed(&ip("192.168.1.255"), &allowlists));
+        assert!(!filter.is_allowed(&ip("192.168.2.1"), &allowlists));
+        assert!(!filter.is_allowed(&ip("11.0.0.1"), &allowlists));
+        assert!(filter.is_allowed(&ip("2001:db8:1::5"), &allowlists));
+        assert!(!filter.is_allowed(&ip("2001:db9::5"), &allowlists));
+    }
+
+    #[test]
+    fn allowlists_match_single_hosts() {
+        let filter = SourceFilter::default();
+        let allowlists = [nets(&["172.16.0.7/32"])];
+        assert!(filter.is_allowed(&ip("172.16.0.7"), &allowlists));
+        assert!(!filter.is_allowed(&ip("172.16.0.8"), &allowlists));
+    }
+
+    #[test]
+    fn every_non_empty_allowlist_must_match() {
+        let filter = SourceFilter::default();
+        // global list, then the one of the gb code
+        let allowlists = [nets(&["10.0.0.0/8"]), nets(&["10.1.0.0/16"])];
+        assert!(filter.is_allowed(&ip("10.1.2.3"), &allowlists));
+        assert!(!filter.is_allowed(&ip("10.2.2.3"), &allowlists));
+        let allowlists = [vec![], nets(&["10.1.0.0/16"])];
+        assert!(filter.is_allowed(&ip("10.1.2.3"), &allowlists));
+        assert!(!filter.is_allowed(&ip("192.168.1.1"), &allowlists));
+    }
+
+    #[test]
+    fn mapped_ipv4_matches_once_canonical() {
+        // check_source takes the canonical form of a dual stack peer
+        let filter = SourceFilter::default();
+        let allowlists = [nets(&["10.0.0.0/8"])];
+        let mapped = ip("::ffff:10.0.0.1");
+        assert!(!filter.is_allowed(&mapped, &allowlists));
+        assert!(filter.is_allowed(&mapped.to_canonical(), &allowlists));
+    }
+}
@@ -0,0 +1,305 @@
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use super::utils::ps;
+use super::utils::reorder::Frame;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HubOptions {
+    // frames queued per subscriber, a full queue skips to the next keyframe
+    pub queue_frames: usize,
+    // longest gop cached, longer ones are not
+    pub gop_frames: usize,
+}
+
+#[derive(PartialEq)]
+enum SubscriberState {
+    // joined without a cached gop, starts on the next keyframe
+    Waiting,
+    Live,
+    // queue was full, frames are dropped up to the next keyframe
+    Lagging,
+}
+
+struct Subscriber {
+    id: u64,
+    tx: mpsc::Sender<Frame>,
+    state: SubscriberState,
+}
+
+impl Subscriber {
+    // false once the subscription is dropped
+    fn send(&mut self, frame: &Frame, skipped: &mut u64) -> bool {
+        if self.state != SubscriberState::Live {
+            if !frame.keyframe {
+                if self.state == SubscriberState::Lagging {
+                    *skipped += 1;
+                }
+                return true;
+            }
+            self.state = SubscriberState::Live;
+        }
+        match self.tx.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::warn!(
+                    "hub subscriber: {} lagged, skipping to the next keyframe",
+                    self.id
+                );
+                self.state = SubscriberState::Lagging;
+                *skipped += 1;
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::info!("hub subscriber: {} left", self.id);
+                false
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct HubState {
+    // system header and psm of the latest keyframe
+    headers: Option<Bytes>,
+    // latest keyframe and the frames after it
+    gop: Vec<Frame>,
+    subscribers: Vec<Subscriber>,
+    next_id: u64,
+    // frames dropped for lagging subscribers
+    skipped: u64,
+    closed: bool,
+}
+
+// frames of one subscriber, ends when the session is freed
+pub struct Subscription {
+    frames: mpsc::Receiver<Frame>,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Option<Frame> {
+        self.frames.recv().await
+    }
+}
+
+// hands every frame of a session to all of its outputs, the frames share
+// their memory, a new subscriber starts on the cached keyframe
+pub struct Hub {
+    options: HubOptions,
+    state: Mutex<HubState>,
+}
+
+impl Hub {
+    pub fn new(options: HubOptions) -> Self {
+        Hub {
+            options,
+            state: Mutex::new(HubState::default()),
+        }
+    }
+
+    pub fn publish(&self, mut frame: Frame) {
+        frame.keyframe = frame.payloads.first().is_some_and(|p| ps::is_keyframe(p));
+
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        if frame.keyframe {
+            if let Some(headers) = ps::headers(&frame.payloads[0]) {
+                state.headers = Some(headers);
+            }
+            state.gop.clear();
+        }
+        if frame.keyframe || !state.gop.is_empty() {
+            if state.gop.len() < self.options.gop_frames {
+                state.gop.push(frame.clone());
+            } else {
+                tracing::warn!(
+                    "gop longer than {} frames, not cached",
+                    self.options.gop_frames
+                );
+                state.gop.clear();
+            }
+        }
+
+        let HubState {
+            subscribers,
+            skipped,
+            ..
+        } = &mut *state;
+        subscribers.retain_mut(|subscriber| subscriber.send(&frame, skipped));
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        let (tx, frames) = mpsc::channel(self.options.queue_frames.max(1));
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Subscription { frames };
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        let mut subscriber = Subscriber {
+            id,
+            tx,
+            state: SubscriberState::Waiting,
+        };
+
+        let HubState {
+            gop,
+            subscribers,
+            skipped,
+            ..
+        } = &mut *state;
+        for frame in gop.iter() {
+            subscriber.send(frame, skipped);
+        }
+        tracing::info!(
+            "hub subscriber: {} joined, cached frames: {}",
+            id,
+            gop.len()
+        );
+        subscribers.push(subscriber);
+        Subscription { frames }
+    }
+
+    // ends every subscription once its queued frames are read
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.subscribers.clear();
+        state.gop.clear();
+    }
+
+    pub fn headers(&self) -> Option<Bytes> {
+        self.state.lock().unwrap().headers.clone()
+    }
+
+    pub fn subscribers(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.subscribers.retain(|s| !s.tx.is_closed());
+        state.subscribers.len()
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.state.lock().unwrap().skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACK: [u8; 14] = [0, 0, 1, 0xBA, 0x44, 0, 4, 0, 4, 1, 1, 0x89, 0xC3, 0xF8];
+    const PSM: [u8; 8] = [0, 0, 1, 0xBC, 0, 2, 0xE0, 0xFF];
+
+    fn frame(timestamp: u32, keyframe: bool) -> Frame {
+        let mut payload = PACK.to_vec();
+        if keyframe {
+            payload.extend_from_slice(&PSM);
+        }
+        payload.extend_from_slice(&[0, 0, 1, 0xE0, 0, 3, 0x80, 0, 0]);
+        Frame {
+            timestamp,
+            payloads: vec![Bytes::from(payload)],
+            keyframe: false,
+        }
+    }
+
+    fn options(queue_frames: usize, gop_frames: usize) -> HubOptions {
+        HubOptions {
+            queue_frames,
+            gop_frames,
+        }
+    }
+
+    fn received(subscription: &mut Subscription) -> Vec<u32> {
+        let mut timestamps = vec![];
+        while let Ok(frame) = subscription.frames.try_recv() {
+            timestamps.push(frame.timestamp);
+        }
+        timestamps
+    }
+
+    #[test]
+    fn late_subscriber_starts_on_the_cached_gop() {
+        let hub = Hub::new(options(16, 16));
+        hub.publish(frame(1, false));
+        hub.publish(frame(2, true));
+        hub.publish(frame(3, false));
+
+        let mut subscription = hub.subscribe();
+        hub.publish(frame(4, false));
+        assert_eq!(received(&mut subscription), vec![2, 3, 4]);
+        assert_eq!(hub.headers().as_deref(), Some(&PSM[..]));
+    }
+
+    #[test]
+    fn subscriber_without_gop_waits_for_a_keyframe() {
+        let hub = Hub::new(options(16, 16));
+        let mut subscription = hub.subscribe();
+        hub.publish(frame(1, false));
+        hub.publish(frame(2, true));
+        hub.publish(frame(3, false));
+        assert_eq!(received(&mut subscription), vec![2, 3]);
+        assert_eq!(hub.skipped(), 0);
+    }
+
+    #[test]
+    fn long_gop_is_not_cached() {
+        let hub = Hub::new(options(16, 2));
+        hub.publish(frame(1, true));
+        hub.publish(frame(2, false));
+        hub.publish(frame(3, false));
+
+        let mut subscription = hub.subscribe();
+        hub.publish(frame(4, false));
+        hub.publish(frame(5, true));
+        assert_eq!(received(&mut subscription), vec![5]);
+    }
+
+    #[test]
+    fn lagging_subscriber_skips_to_the_next_keyframe() {
+        let hub = Hub::new(options(2, 16));
+        let mut subscription = hub.subscribe();
+        hub.publish(frame(1, true));
+        hub.publish(frame(2, false));
+        // queue full, this and the following delta frames are skipped
+        hub.publish(frame(3, false));
+        hub.publish(frame(4, false));
+        assert_eq!(received(&mut subscription), vec![1, 2]);
+
+        hub.publish(frame(5, false));
+        hub.publish(frame(6, true));
+        hub.publish(frame(7, false));
+        assert_eq!(received(&mut subscription), vec![6, 7]);
+        assert_eq!(hub.skipped(), 3);
+    }
+
+    #[tokio::test]
+    async fn close_ends_subscriptions_after_their_queued_frames() {
+        let hub = Hub::new(options(16, 16));
+        let mut subscription = hub.subscribe();
+        hub.publish(frame(1, true));
+        assert_eq!(hub.subscribers(), 1);
+        hub.close();
+
+        assert_eq!(subscription.recv().await.map(|f| f.timestamp), Some(1));
+        assert!(subscription.recv().await.is_none());
+        assert!(hub.subscribe().recv().await.is_none());
+        hub.publish(frame(2, true));
+        assert_eq!(hub.subscribers(), 0);
+    }
+
+    #[test]
+    fn dropped_subscription_is_removed() {
+        let hub = Hub::new(options(16, 16));
+        let subscription = hub.subscribe();
+        let _other = hub.subscribe();
+        assert_eq!(hub.subscribers(), 2);
+        drop(subscription);
+        hub.publish(frame(1, true));
+        assert_eq!(hub.subscribers(), 1);
+    }
+}
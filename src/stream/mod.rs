@@ -1,3 +1,6 @@
 pub mod handler;
+pub mod hub;
+pub mod sender;
+pub mod server;
+pub mod talk;
 pub mod utils;
-pub mod server;
\ No newline at end of file
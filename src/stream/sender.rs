@@ -0,0 +1,246 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::AsyncWriteExt;
+use tracing::Instrument;
+use webrtc_util::{Marshal, MarshalSize};
+
+use super::handler::StreamHandler;
+use super::hub::Subscription;
+use super::utils::reorder::Frame;
+use super::utils::socket::{self, SocketOptions};
+use crate::gss::{StreamEventType, StreamSetupType};
+
+// rtp payload per packet, fits a 1500 byte mtu
+const MAX_PAYLOAD_SIZE: usize = 1400;
+// an active target has to take the connection in time
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// a passive target has to connect in time
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct SendTarget {
+    pub setup_type: StreamSetupType,
+    pub local_addr: SocketAddr,
+    // None for passive
+    pub dst: Option<SocketAddr>,
+    pub ssrc: u32,
+    pub payload_type: u8,
+}
+
+// sockets bound in the rpc, so a port in use fails it, connected by the task
+pub enum Bound {
+    Udp(tokio::net::UdpSocket, SocketAddr),
+    Active(tokio::net::TcpSocket, SocketAddr),
+    Passive(tokio::net::TcpListener),
+}
+
+pub enum Transport {
+    Udp(tokio::net::UdpSocket, SocketAddr),
+    Tcp(tokio::net::TcpStream),
+}
+
+impl Transport {
+    pub async fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        match self {
+            Transport::Udp(socket, dst) => socket.send_to(packet, *dst).await.map(|_| ()),
+            Transport::Tcp(stream) => stream.write_all(packet).await,
+        }
+    }
+}
+
+pub fn bind(target: &SendTarget, options: &SocketOptions) -> io::Result<Bound> {
+    let dst = || {
+        target
+            .dst
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no destination address"))
+    };
+    match target.setup_type {
+        StreamSetupType::Passive => Ok(Bound::Passive(socket::bind_tcp(
+            &target.local_addr,
+            options,
+        )?)),
+        StreamSetupType::Active => {
+            let tcp_socket = if target.local_addr.is_ipv4() {
+                tokio::net::TcpSocket::new_v4()?
+            } else {
+                tokio::net::TcpSocket::new_v6()?
+            };
+            tcp_socket.set_reuseaddr(true)?;
+            if options.send_buffer_size > 0 {
+                tcp_socket.set_send_buffer_size(options.send_buffer_size as u32)?;
+            }
+            tcp_socket.bind(target.local_addr)?;
+            Ok(Bound::Active(tcp_socket, dst()?))
+        }
+        _ => Ok(Bound::Udp(
+            socket::bind_udp(&target.local_addr, options)?,
+            dst()?,
+        )),
+    }
+}
+
+pub async fn connect(bound: Bound) -> io::Result<Transport> {
+    let timed_out = |what: &str| io::Error::new(io::ErrorKind::TimedOut, what.to_string());
+    match bound {
+        Bound::Udp(udp_socket, dst) => Ok(Transport::Udp(udp_socket, dst)),
+        Bound::Active(tcp_socket, dst) => {
+            let stream = tokio::time::timeout(CONNECT_TIMEOUT, tcp_socket.connect(dst))
+                .await
+                .map_err(|_| timed_out("connect timed out"))??;
+            Ok(Transport::Tcp(stream))
+        }
+        Bound::Passive(listener) => {
+            let (stream, addr) = tokio::time::timeout(ACCEPT_TIMEOUT, listener.accept())
+                .await
+                .map_err(|_| timed_out("accept timed out"))??;
+            tracing::info!("send rtp accepted: {}", addr);
+            Ok(Transport::Tcp(stream))
+        }
+    }
+}
+
+// our own sequence numbers and timestamps, the timestamps keep the spacing of
+// the received frames
+pub struct Packetizer {
+    ssrc: u32,
+    payload_type: u8,
+    // rfc 4571 length prefix
+    tcp: bool,
+    sequence: u16,
+    first_timestamp: Option<u32>,
+}
+
+impl Packetizer {
+    pub fn new(target: &SendTarget) -> Self {
+        Packetizer {
+            ssrc: target.ssrc,
+            payload_type: target.payload_type,
+            tcp: target.setup_type != StreamSetupType::Udp,
+            sequence: 0,
+            first_timestamp: None,
+        }
+    }
+
+    pub fn packetize(&mut self, frame: &Frame) -> Vec<Bytes> {
+        let first_timestamp = *self.first_timestamp.get_or_insert(frame.timestamp);
+        let timestamp = frame.timestamp.wrapping_sub(first_timestamp);
+
+        let mut packets = vec![];
+        let mut payloads = frame.payloads.iter();
+        let mut current: &[u8] = &[];
+        let mut remaining = frame.len();
+        while remaining > 0 {
+            let len = remaining.min(MAX_PAYLOAD_SIZE);
+            remaining -= len;
+            let header = rtp::header::Header {
+                version: 2,
+                marker: remaining == 0,
+                payload_type: self.payload_type,
+                sequence_number: self.sequence,
+                timestamp,
+                ssrc: self.ssrc,
+                ..Default::default()
+            };
+            self.sequence = self.sequence.wrapping_add(1);
+
+            let size = header.marshal_size() + len;
+            let mut packet = BytesMut::with_capacity(size + 2);
+            if self.tcp {
+                packet.put_u16(size as u16);
+            }
+            match header.marshal() {
+                Err(e) => {
+                    tracing::error!("rtp::header::Header::marshal error, e: {:?}", e);
+                    return packets;
+                }
+                Ok(header) => packet.extend_from_slice(&header),
+            }
+            let mut needed = len;
+            while needed > 0 {
+                if current.is_empty() {
+                    match payloads.next() {
+                        None => break,
+                        Some(payload) => current = payload,
+                    }
+                    continue;
+                }
+                let n = needed.min(current.len());
+                packet.extend_from_slice(&current[..n]);
+                current = &current[n..];
+                needed -= n;
+            }
+            packets.push(packet.freeze());
+        }
+        packets
+    }
+}
+
+// one target of a session, stopped by the rpc, when its session is freed or
+// when the target goes away
+pub struct RtpSender {
+    pub target: SendTarget,
+    pub packets: Arc<AtomicU64>,
+    pub bytes: Arc<AtomicU64>,
+    join_handle: tokio::task::JoinHandle<()>,
+}
+
+impl RtpSender {
+    pub fn start(
+        target: SendTarget,
+        bound: Bound,
+        mut subscription: Subscription,
+        stream_handler: &Arc<StreamHandler>,
+    ) -> Self {
+        let packets = Arc::new(AtomicU64::new(0));
+        let bytes = Arc::new(AtomicU64::new(0));
+        let mut packetizer = Packetizer::new(&target);
+        let (task_packets, task_bytes) = (packets.clone(), bytes.clone());
+        // the session owns its senders, so only a weak reference back
+        let weak_handler: Weak<StreamHandler> = Arc::downgrade(stream_handler);
+        let ssrc = target.ssrc;
+        let task = async move {
+            let result = async {
+                let mut transport = connect(bound).await?;
+                while let Some(frame) = subscription.recv().await {
+                    for packet in packetizer.packetize(&frame) {
+                        transport.send(&packet).await?;
+                        task_packets.fetch_add(1, Ordering::Relaxed);
+                        task_bytes.fetch_add(packet.len() as u64, Ordering::Relaxed);
+                    }
+                }
+                io::Result::Ok(())
+            }
+            .await;
+
+            // the session ending is reported as stream_freed
+            if let Err(e) = result {
+                tracing::error!("send rtp error, ssrc: {}, e: {:?}", ssrc, e);
+                if let Some(stream_handler) = weak_handler.upgrade() {
+                    stream_handler.emit_event(
+                        StreamEventType::SendRtpStopped,
+                        format!("ssrc: {}, e: {}", super::utils::ssrc::to_string(ssrc), e),
+                    );
+                }
+            }
+        };
+        RtpSender {
+            target,
+            packets,
+            bytes,
+            join_handle: tokio::spawn(task.instrument(stream_handler.span.clone())),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+
+    pub fn stop(&self) {
+        self.join_handle.abort();
+    }
+}
@@ -1,25 +1,66 @@
 use tokio::{self, io::AsyncReadExt};
+use tracing::Instrument;
 
 use super::handler::StreamHandler;
+use super::utils::pool::BufferPool;
 use super::utils::reorder::RtpPacketReOrder;
+use super::utils::socket::{self, RecvBatch, SocketOptions};
+use crate::gss::{AddressFamily, SessionType};
+use crate::utils::net;
+
+// received packets are packed into slabs of this size per session
+const BUFFER_POOL_SLAB_SIZE: usize = 256 * 1024;
+
+// first address of host in the family, "::" takes ipv4 unless only_v6
+pub async fn resolve(
+    host: &str,
+    port: u16,
+    family: AddressFamily,
+    options: &SocketOptions,
+) -> Result<std::net::SocketAddr, std::io::Error> {
+    let host_port = net::host_port(host, port);
+    let mut addrs = tokio::net::lookup_host(&host_port).await?;
+    addrs
+        .find(|addr| match family {
+            AddressFamily::Any => true,
+            AddressFamily::Ipv4 => {
+                addr.is_ipv4() || (addr.ip() == std::net::Ipv6Addr::UNSPECIFIED && !options.only_v6)
+            }
+            AddressFamily::Ipv6 => addr.is_ipv6(),
+        })
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("no {} address for {}", family.as_str_name(), host_port),
+            )
+        })
+}
 
 pub async fn bind(
-    host: &String,
+    host: &str,
     port: u16,
+    family: AddressFamily,
+    options: &SocketOptions,
 ) -> Result<(tokio::net::UdpSocket, tokio::net::TcpListener), std::io::Error> {
-    let local_addr = format!("{host}:{port}",);
+    let local_addr = resolve(host, port, family, options).await?;
 
     // udp server
-    match tokio::net::UdpSocket::bind(&local_addr).await {
+    match socket::bind_udp(&local_addr, options) {
         Err(e) => {
             tracing::error!("UdpSocket::bind({}) error, e: {:?}", &local_addr, e);
             Err(e)
         }
         Ok(udp_socket) => {
-            tracing::info!("UdpSocket::bind({}) ok", &local_addr);
+            let (rcvbuf, sndbuf) = socket::buffer_sizes(&udp_socket);
+            tracing::info!(
+                "UdpSocket::bind({}) ok, rcvbuf: {}, sndbuf: {}",
+                &local_addr,
+                rcvbuf,
+                sndbuf
+            );
 
             // tcp server
-            match tokio::net::TcpListener::bind(&local_addr).await {
+            match socket::bind_tcp(&local_addr, options) {
                 Err(e) => {
                     tracing::error!("TcpListener::bind({}) error, e: {:?}", &local_addr, e);
                     Err(e)
@@ -33,60 +74,139 @@ pub async fn bind(
     }
 }
 
+// rtcp on rtp port + 1, optional: rtcp-mux on the rtp port always works
+pub async fn bind_rtcp(
+    host: &str,
+    port: u16,
+    family: AddressFamily,
+    options: &SocketOptions,
+) -> Option<tokio::net::UdpSocket> {
+    let local_addr = resolve(host, port.checked_add(1)?, family, options)
+        .await
+        .ok()?;
+    match socket::bind_udp(&local_addr, options) {
+        Err(e) => {
+            tracing::warn!(
+                "UdpSocket::bind({}) for rtcp error, e: {:?}",
+                &local_addr,
+                e
+            );
+            None
+        }
+        Ok(udp_socket) => {
+            tracing::info!("UdpSocket::bind({}) for rtcp ok", &local_addr);
+            Some(udp_socket)
+        }
+    }
+}
+
+async fn recv_rtcp(
+    socket: &Option<tokio::net::UdpSocket>,
+    buff: &mut [u8],
+) -> std::io::Result<(usize, std::net::SocketAddr)> {
+    match socket {
+        None => std::future::pending().await,
+        Some(s) => s.recv_from(buff).await,
+    }
+}
+
+async fn rtcp_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        None => std::future::pending().await,
+        Some(i) => {
+            i.tick().await;
+        }
+    }
+}
+
 pub async fn run_forever(
     cancel_tx: tokio::sync::broadcast::Sender<()>,
-    socket_recv_buffer_size: usize,
+    max_datagram_size: usize,
+    recv_batch_size: usize,
+    rtcp_interval: u64,
     stream_handler: std::sync::Arc<StreamHandler>,
 ) -> Result<(tokio::task::JoinHandle<()>, tokio::task::JoinHandle<()>), std::io::Error> {
     // udp server
     let mut udp_cancel_rx = cancel_tx.subscribe();
     let udp_stream_handler = stream_handler.clone();
-    let udp_join_handle = tokio::spawn(async move {
+    let udp_task = async move {
         tracing::info!(
             "udp stream service start, port: {}",
             udp_stream_handler.port
         );
 
-        let mut recv_buff = Vec::<u8>::default();
-        recv_buff.resize(socket_recv_buffer_size, 0);
+        let mut recv_batch = RecvBatch::new(recv_batch_size, max_datagram_size);
+        let slab_size = recv_batch.slab_size();
+        // a few batches per slab even with a large udp_recv_batch
+        let mut buffer_pool = BufferPool::new(BUFFER_POOL_SLAB_SIZE.max(4 * slab_size));
+        let mut rtcp_recv_buff = vec![0u8; 1500];
 
         let mut packets_reorder =
             RtpPacketReOrder::new(3, &format!("udp.{}.output.ps", udp_stream_handler.port));
 
+        // receiver reports, for both udp and tcp
+        let mut rtcp_interval = if rtcp_interval == 0 {
+            None
+        } else {
+            Some(tokio::time::interval(std::time::Duration::from_secs(
+                rtcp_interval,
+            )))
+        };
+
         loop {
             tokio::select! {
                 _ = udp_cancel_rx.recv() => {
                     tracing::warn!("cancel udp recv_from");
                     break;
                 }
-                result = udp_stream_handler.stream_udp_socket.recv_from(recv_buff.as_mut_slice()) => {
+                result = socket::recv_batch(
+                    &udp_stream_handler.stream_udp_socket,
+                    &mut recv_batch,
+                    buffer_pool.slab(slab_size),
+                ) => {
                     match result {
                         Err(e) => {
-                            tracing::error!("UdpSocket::recv_from error, e: {:?}", e);
+                            tracing::error!("UdpSocket::recv_batch error, e: {:?}", e);
                             break;
                         }
-                        Ok((amount, addr)) => {
+                        Ok(_) => {
+                            if let Some(drops) = recv_batch.kernel_drops() {
+                                udp_stream_handler.stats.on_kernel_drops(drops);
+                            }
                             // dispatch rtp data
-                            udp_stream_handler.on_rtp(
-                                addr,
-                                &recv_buff.as_slice()[..amount],
-                                &mut packets_reorder,
-                            );
+                            udp_stream_handler.on_rtp_batch(&mut recv_batch, &mut packets_reorder);
+                        }
+                    }
+                }
+                result = recv_rtcp(&udp_stream_handler.stream_rtcp_socket, rtcp_recv_buff.as_mut_slice()) => {
+                    match result {
+                        Err(e) => {
+                            tracing::error!("UdpSocket::recv_from for rtcp error, e: {:?}", e);
+                        }
+                        Ok((amount, addr)) => {
+                            if udp_stream_handler.check_source(addr) {
+                                udp_stream_handler.on_rtcp(addr, &rtcp_recv_buff.as_slice()[..amount], true);
+                            }
                         }
                     }
                 }
+                _ = rtcp_tick(&mut rtcp_interval) => {
+                    udp_stream_handler.send_receiver_report().await;
+                }
             }
         }
 
+        packets_reorder.flush();
         tracing::info!("udp stream service stop, port: {}", udp_stream_handler.port);
-    });
+    };
+    let udp_join_handle = tokio::spawn(udp_task.instrument(stream_handler.span.clone()));
 
     // tcp server
     let mut tcp_cancel_accept_rx = cancel_tx.subscribe();
     let mut tcp_cancel_read_u16_rx = cancel_tx.subscribe();
     let mut tcp_cancel_read_extract_rx = cancel_tx.subscribe();
     let tcp_stream_handler = stream_handler.clone();
-    let tcp_join_handle = tokio::spawn(async move {
+    let tcp_task = async move {
         tracing::info!(
             "tcp stream service start, port: {}",
             tcp_stream_handler.port
@@ -104,8 +224,19 @@ pub async fn run_forever(
                             tracing::error!("TcpListener::accept error, e: {:?}", e);
                             continue;
                         }
-                        Ok((mut tcp_stream, addr)) => {
+                        Ok((tcp_stream, addr)) => {
+                            // a disallowed peer is closed before anything is read
+                            if !tcp_stream_handler.check_source(addr) {
+                                drop(tcp_stream);
+                                continue;
+                            }
+
                             let mut packets_reorder = RtpPacketReOrder::new(3, &format!("tcp.{}.output.ps", tcp_stream_handler.port));
+                            let mut buffer_pool = BufferPool::new(BUFFER_POOL_SLAB_SIZE);
+
+                            // write half is kept for receiver reports
+                            let (mut tcp_stream, tcp_writer) = tcp_stream.into_split();
+                            *tcp_stream_handler.tcp_writer.lock().await = Some(tcp_writer);
 
                             loop {
                                 tokio::select! {
@@ -118,11 +249,18 @@ pub async fn run_forever(
                                         match u16_result {
                                             Err(e) => {
                                                 tracing::error!("TcpStream::read_u16 error, e: {:?}", e);
+                                                // playback and download end by closing the connection, reset
+                                                // when our receiver reports were left unread
+                                                if matches!(e.kind(), std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset)
+                                                    && tcp_stream_handler.info.session_type != SessionType::Realtime
+                                                {
+                                                    tcp_stream_handler.on_media_end(format!("tcp connection closed by: {}", addr));
+                                                }
                                                 break;
                                             }
                                             Ok(n) => {
-                                                // read n bytes content
-                                                let mut recv_buff = vec![0; n as usize];
+                                                // read n bytes content, straight into the pool
+                                                let mut recv_buff = buffer_pool.take(n as usize);
                                                 tokio::select! {
                                                     _ = tcp_cancel_read_extract_rx.recv() => {
                                                         tracing::warn!("cancel tcp read_extract");
@@ -142,7 +280,7 @@ pub async fn run_forever(
                                                                 // dispatch rtp data
                                                                 tcp_stream_handler.on_rtp(
                                                                     addr,
-                                                                    &recv_buff.as_slice()[..amount],
+                                                                    recv_buff.split_to(amount).freeze(),
                                                                     &mut packets_reorder,
                                                                 );
                                                             }
@@ -154,6 +292,11 @@ pub async fn run_forever(
                                     }
                                 }
                             }
+
+                            packets_reorder.flush();
+                            if let Some(mut tcp_writer) = tcp_stream_handler.tcp_writer.lock().await.take() {
+                                let _ = tokio::io::AsyncWriteExt::shutdown(&mut tcp_writer).await;
+                            }
                         }
                     }
                 }
@@ -161,7 +304,8 @@ pub async fn run_forever(
         }
 
         tracing::info!("tcp stream service stop, port: {}", tcp_stream_handler.port);
-    });
+    };
+    let tcp_join_handle = tokio::spawn(tcp_task.instrument(stream_handler.span.clone()));
 
     Ok((udp_join_handle, tcp_join_handle))
 }
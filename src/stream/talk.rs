@@ -0,0 +1,298 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use super::sender::{self, Bound, Packetizer, SendTarget};
+use super::utils::g711::{self, AudioEncoder};
+use super::utils::psmux::{Codec, PsMuxer};
+use super::utils::reorder::Frame;
+use super::utils::ssrc;
+use crate::gss::{AudioFormat, StreamEvent, StreamEventType, TalkPayload};
+
+// a feed without audio for this long ends the talk, the first chunk included
+const AUDIO_TIMEOUT: Duration = Duration::from_secs(30);
+// chunks queued from the websocket
+const FEED_QUEUE: usize = 64;
+// one ptime of 20 ms
+const FRAME_DURATION: Duration = Duration::from_millis(20);
+// timestamp steps per frame, 8 khz for raw g711, 90 khz for ps
+const RAW_TIMESTAMP_STEP: u32 = g711::FRAME_SAMPLES as u32;
+const PS_TIMESTAMP_STEP: u64 = 1800;
+
+impl TalkPayload {
+    pub fn codec(&self) -> Codec {
+        match self {
+            TalkPayload::PsPcmu | TalkPayload::RtpPcmu => Codec::G711U,
+            _ => Codec::G711A,
+        }
+    }
+
+    pub fn payload_type(&self) -> u8 {
+        match self {
+            TalkPayload::PsPcma | TalkPayload::PsPcmu => 96,
+            TalkPayload::RtpPcma => 8,
+            TalkPayload::RtpPcmu => 0,
+        }
+    }
+}
+
+// wav with pcm 16 bit, a-law or mu-law at 8 khz mono, anything else is taken
+// as raw audio of the requested format
+pub fn load_file(path: &Path, format: AudioFormat) -> io::Result<(AudioFormat, Vec<u8>)> {
+    let data = std::fs::read(path)?;
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Ok((format, data));
+    }
+
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let u32_at =
+        |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let len = u32_at(offset + 4) as usize;
+        let body = offset + 8;
+        let end = (body + len).min(data.len());
+        if id == b"fmt " && len >= 16 && body + 16 <= data.len() {
+            let (tag, channels, rate, bits) = (
+                u16_at(body),
+                u16_at(body + 2),
+                u32_at(body + 4),
+                u16_at(body + 14),
+            );
+            if channels != 1 || rate != 8000 {
+                return Err(invalid("wav is not 8 khz mono"));
+            }
+            format = Some(match (tag, bits) {
+                (1, 16) => AudioFormat::PcmS16le,
+                (6, 8) => AudioFormat::Pcma,
+                (7, 8) => AudioFormat::Pcmu,
+                _ => return Err(invalid("wav is not pcm 16 bit, a-law or mu-law")),
+            });
+        } else if id == b"data" {
+            let format = format.ok_or_else(|| invalid("wav data before fmt"))?;
+            return Ok((format, data[body..end].to_vec()));
+        }
+        // chunks are word aligned
+        offset = body + len + (len & 1);
+    }
+    Err(invalid("wav without data"))
+}
+
+pub enum TalkSource {
+    File(AudioFormat, Vec<u8>),
+    // fed by the websocket, see Talk::take_feed
+    Feed(AudioFormat),
+}
+
+enum AudioInput {
+    File {
+        data: Vec<u8>,
+        offset: usize,
+        chunk: usize,
+        interval: tokio::time::Interval,
+    },
+    Feed(mpsc::Receiver<Bytes>),
+}
+
+impl AudioInput {
+    // None at the end of the file or when the websocket closed
+    async fn next(&mut self) -> io::Result<Option<Bytes>> {
+        match self {
+            AudioInput::File {
+                data,
+                offset,
+                chunk,
+                interval,
+            } => {
+                if *offset >= data.len() {
+                    return Ok(None);
+                }
+                // the file is sent in real time
+                interval.tick().await;
+                let end = (*offset + *chunk).min(data.len());
+                let bytes = Bytes::copy_from_slice(&data[*offset..end]);
+                *offset = end;
+                Ok(Some(bytes))
+            }
+            AudioInput::Feed(rx) => tokio::time::timeout(AUDIO_TIMEOUT, rx.recv())
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no audio")),
+        }
+    }
+}
+
+// g711 frames as raw rtp or ps, with their own timestamps
+enum Framer {
+    Raw(u32),
+    Ps(PsMuxer, u64),
+}
+
+impl Framer {
+    fn frame(&mut self, g711: Bytes) -> Frame {
+        match self {
+            Framer::Raw(timestamp) => {
+                let frame = Frame {
+                    timestamp: *timestamp,
+                    payloads: vec![g711],
+                    keyframe: false,
+                };
+                *timestamp = timestamp.wrapping_add(RAW_TIMESTAMP_STEP);
+                frame
+            }
+            Framer::Ps(muxer, pts) => {
+                let frame = muxer.mux_audio(&g711, *pts);
+                *pts += PS_TIMESTAMP_STEP;
+                frame
+            }
+        }
+    }
+}
+
+// audio toward one device, stopped by the rpc, at the end of its file or
+// when the device or the websocket goes away
+pub struct Talk {
+    pub gb_code: String,
+    pub target: SendTarget,
+    pub payload: TalkPayload,
+    // authorizes the websocket feed, empty for a file
+    pub token: String,
+    pub packets: Arc<AtomicU64>,
+    pub bytes: Arc<AtomicU64>,
+    // taken by the one websocket allowed
+    feed_tx: Mutex<Option<mpsc::Sender<Bytes>>>,
+    join_handle: tokio::task::JoinHandle<()>,
+}
+
+impl Talk {
+    pub fn start(
+        gb_code: &str,
+        target: SendTarget,
+        bound: Bound,
+        payload: TalkPayload,
+        source: TalkSource,
+        event_tx: tokio::sync::broadcast::Sender<StreamEvent>,
+    ) -> Self {
+        let (mut input, mut encoder, feed_tx, token) = match source {
+            TalkSource::File(format, data) => {
+                let mut interval = tokio::time::interval(FRAME_DURATION);
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                let input = AudioInput::File {
+                    data,
+                    offset: 0,
+                    chunk: g711::frame_bytes(format),
+                    interval,
+                };
+                (
+                    input,
+                    AudioEncoder::new(format, payload.codec()),
+                    None,
+                    String::new(),
+                )
+            }
+            TalkSource::Feed(format) => {
+                let (tx, rx) = mpsc::channel(FEED_QUEUE);
+                (
+                    AudioInput::Feed(rx),
+                    AudioEncoder::new(format, payload.codec()),
+                    Some(tx),
+                    new_token(),
+                )
+            }
+        };
+        let mut framer = match payload {
+            TalkPayload::PsPcma | TalkPayload::PsPcmu => {
+                Framer::Ps(PsMuxer::new(None, Some(payload.codec())), 0)
+            }
+            _ => Framer::Raw(0),
+        };
+        let mut packetizer = Packetizer::new(&target);
+
+        let packets = Arc::new(AtomicU64::new(0));
+        let bytes = Arc::new(AtomicU64::new(0));
+        let (task_packets, task_bytes) = (packets.clone(), bytes.clone());
+        let event = StreamEvent {
+            event_type: StreamEventType::TalkStopped.into(),
+            gb_code: gb_code.to_string(),
+            media_server_port: target.local_addr.port() as u32,
+            ssrc: ssrc::to_string(target.ssrc),
+            ..Default::default()
+        };
+        let task = async move {
+            let result = async {
+                let mut transport = sender::connect(bound).await?;
+                while let Some(chunk) = input.next().await? {
+                    encoder.push(&chunk);
+                    while let Some(g711) = encoder.pop_frame() {
+                        for packet in packetizer.packetize(&framer.frame(g711)) {
+                            transport.send(&packet).await?;
+                            task_packets.fetch_add(1, Ordering::Relaxed);
+                            task_bytes.fetch_add(packet.len() as u64, Ordering::Relaxed);
+                        }
+                    }
+                }
+                io::Result::Ok(())
+            }
+            .await;
+
+            let message = match result {
+                Ok(()) => "audio ended".to_string(),
+                Err(e) => {
+                    tracing::error!("talk error, e: {:?}", e);
+                    e.to_string()
+                }
+            };
+            tracing::info!("talk stopped, message: {}", &message);
+            // no subscribers is not an error
+            let _ = event_tx.send(StreamEvent {
+                message,
+                timestamp: chrono::Local::now().timestamp_millis(),
+                ..event
+            });
+        };
+        let span = tracing::info_span!("talk", gb_code, port = target.local_addr.port());
+        Talk {
+            gb_code: gb_code.to_string(),
+            target,
+            payload,
+            token,
+            packets,
+            bytes,
+            feed_tx: Mutex::new(feed_tx),
+            join_handle: tokio::spawn(task.instrument(span)),
+        }
+    }
+
+    // once, for the websocket presenting the token
+    pub fn take_feed(&self, token: &str) -> Option<mpsc::Sender<Bytes>> {
+        if self.token.is_empty()
+            || !crate::utils::auth::constant_time_eq(self.token.as_bytes(), token.as_bytes())
+        {
+            return None;
+        }
+        self.feed_tx.lock().unwrap().take()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+
+    pub fn stop(&self) {
+        self.join_handle.abort();
+    }
+}
+
+// 128 bits from the os random source, the token alone grants the feed
+fn new_token() -> String {
+    let mut token = [0u8; 16];
+    getrandom::getrandom(&mut token).expect("os random source");
+    hex::encode(token)
+}
@@ -0,0 +1,232 @@
+use bytes::{Bytes, BytesMut};
+
+use super::psmux::Codec;
+use crate::gss::AudioFormat;
+
+// 20 ms at 8 khz, one sample per byte
+pub const FRAME_SAMPLES: usize = 160;
+
+// itu-t g.711, after the sun reference implementation
+const ALAW_SEGMENT_END: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+const ULAW_SEGMENT_END: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+const ULAW_BIAS: i32 = 0x84;
+const ULAW_CLIP: i32 = 8159;
+
+fn segment(value: i32, ends: &[i32; 8]) -> usize {
+    ends.iter().position(|end| value <= *end).unwrap_or(8)
+}
+
+pub fn alaw_encode(sample: i16) -> u8 {
+    let mut value = sample as i32 >> 3;
+    let mask = if value >= 0 {
+        0xD5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+    let seg = segment(value, &ALAW_SEGMENT_END);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    let shift = if seg < 2 { 1 } else { seg };
+    (((seg as i32) << 4 | ((value >> shift) & 0x0F)) as u8) ^ mask
+}
+
+pub fn alaw_decode(value: u8) -> i16 {
+    let value = value ^ 0x55;
+    let mut t = ((value & 0x0F) as i32) << 4;
+    let seg = (value & 0x70) >> 4;
+    match seg {
+        0 => t += 8,
+        1 => t += 0x108,
+        _ => t = (t + 0x108) << (seg - 1),
+    }
+    if value & 0x80 != 0 {
+        t as i16
+    } else {
+        -t as i16
+    }
+}
+
+pub fn ulaw_encode(sample: i16) -> u8 {
+    let mut value = sample as i32 >> 2;
+    let mask = if value < 0 {
+        value = -value;
+        0x7F
+    } else {
+        0xFF
+    };
+    value = value.min(ULAW_CLIP) + (ULAW_BIAS >> 2);
+    let seg = segment(value, &ULAW_SEGMENT_END);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    (((seg as i32) << 4 | ((value >> (seg + 1)) & 0x0F)) as u8) ^ mask
+}
+
+pub fn ulaw_decode(value: u8) -> i16 {
+    let value = !value;
+    let t = ((((value & 0x0F) as i32) << 3) + ULAW_BIAS) << ((value & 0x70) >> 4);
+    if value & 0x80 != 0 {
+        (ULAW_BIAS - t) as i16
+    } else {
+        (t - ULAW_BIAS) as i16
+    }
+}
+
+// any input format to 20 ms frames of g711a or g711u, g711 in the output law
+// passes through
+pub struct AudioEncoder {
+    input: AudioFormat,
+    output: Codec,
+    pending: BytesMut,
+    // half a pcm sample split across chunks
+    odd: Option<u8>,
+}
+
+impl AudioEncoder {
+    pub fn new(input: AudioFormat, output: Codec) -> Self {
+        AudioEncoder {
+            input,
+            output,
+            pending: BytesMut::new(),
+            odd: None,
+        }
+    }
+
+    fn encode(&self, sample: i16) -> u8 {
+        match self.output {
+            Codec::G711U => ulaw_encode(sample),
+            _ => alaw_encode(sample),
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        match (self.input, self.output) {
+            (AudioFormat::Pcma, Codec::G711A) | (AudioFormat::Pcmu, Codec::G711U) => {
+                self.pending.extend_from_slice(chunk)
+            }
+            (AudioFormat::Pcma, _) => {
+                for value in chunk {
+                    let value = self.encode(alaw_decode(*value));
+                    self.pending.extend_from_slice(&[value]);
+                }
+            }
+            (AudioFormat::Pcmu, _) => {
+                for value in chunk {
+                    let value = self.encode(ulaw_decode(*value));
+                    self.pending.extend_from_slice(&[value]);
+                }
+            }
+            (AudioFormat::PcmS16le, _) => {
+                let mut bytes = chunk.iter();
+                loop {
+                    let low = match self.odd.take() {
+                        Some(low) => low,
+                        None => match bytes.next() {
+                            None => break,
+                            Some(low) => *low,
+                        },
+                    };
+                    match bytes.next() {
+                        None => {
+                            self.odd = Some(low);
+                            break;
+                        }
+                        Some(high) => {
+                            let value = self.encode(i16::from_le_bytes([low, *high]));
+                            self.pending.extend_from_slice(&[value]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn pop_frame(&mut self) -> Option<Bytes> {
+        if self.pending.len() < FRAME_SAMPLES {
+            return None;
+        }
+        Some(self.pending.split_to(FRAME_SAMPLES).freeze())
+    }
+}
+
+// bytes of 20 ms of input
+pub fn frame_bytes(format: AudioFormat) -> usize {
+    match format {
+        AudioFormat::PcmS16le => FRAME_SAMPLES * 2,
+        _ => FRAME_SAMPLES,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_reference_values() {
+        assert_eq!(alaw_encode(0), 0xD5);
+        assert_eq!(alaw_encode(i16::MAX), 0xAA);
+        assert_eq!(alaw_encode(i16::MIN), 0x2A);
+        assert_eq!(ulaw_encode(0), 0xFF);
+        assert_eq!(ulaw_encode(i16::MAX), 0x80);
+        assert_eq!(ulaw_encode(i16::MIN), 0x00);
+    }
+
+    #[test]
+    fn decode_then_encode_keeps_the_code() {
+        for value in 0..=255u8 {
+            assert_eq!(alaw_encode(alaw_decode(value)), value);
+            // negative zero encodes as positive zero
+            if value != 0x7F {
+                assert_eq!(ulaw_encode(ulaw_decode(value)), value);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_then_decode_is_close() {
+        for sample in (i16::MIN..=i16::MAX).step_by(97) {
+            // a segment step of 1024 at the top of the range
+            let tolerance = (sample as i32).abs() / 16 + 16;
+            assert!((alaw_decode(alaw_encode(sample)) as i32 - sample as i32).abs() <= tolerance);
+            assert!((ulaw_decode(ulaw_encode(sample)) as i32 - sample as i32).abs() <= tolerance);
+        }
+    }
+
+    #[test]
+    fn pcm_split_across_chunks_makes_20ms_frames() {
+        let pcm: Vec<u8> = (0..FRAME_SAMPLES as i16 + 10)
+            .flat_map(|i| (i * 100).to_le_bytes())
+            .collect();
+        let mut encoder = AudioEncoder::new(AudioFormat::PcmS16le, Codec::G711A);
+        // odd chunk sizes split samples between pushes
+        for chunk in pcm.chunks(7) {
+            encoder.push(chunk);
+        }
+        let frame = encoder.pop_frame().unwrap();
+        assert_eq!(frame.len(), FRAME_SAMPLES);
+        assert!(encoder.pop_frame().is_none());
+        let expected: Vec<u8> = (0..FRAME_SAMPLES as i16)
+            .map(|i| alaw_encode(i * 100))
+            .collect();
+        assert_eq!(&frame[..], &expected[..]);
+        assert_eq!(frame_bytes(AudioFormat::PcmS16le), 2 * FRAME_SAMPLES);
+    }
+
+    #[test]
+    fn g711_passes_through_or_transcodes() {
+        let alaw: Vec<u8> = (0..FRAME_SAMPLES).map(|i| i as u8).collect();
+
+        let mut encoder = AudioEncoder::new(AudioFormat::Pcma, Codec::G711A);
+        encoder.push(&alaw);
+        assert_eq!(&encoder.pop_frame().unwrap()[..], &alaw[..]);
+
+        let mut encoder = AudioEncoder::new(AudioFormat::Pcma, Codec::G711U);
+        encoder.push(&alaw);
+        let ulaw = encoder.pop_frame().unwrap();
+        for (a, u) in alaw.iter().zip(ulaw.iter()) {
+            assert_eq!(*u, ulaw_encode(alaw_decode(*a)));
+        }
+    }
+}
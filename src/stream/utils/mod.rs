@@ -1 +1,10 @@
+pub mod g711;
+pub mod mp4;
+pub mod pool;
+pub mod ps;
+pub mod psmux;
 pub mod reorder;
+pub mod rtcp;
+pub mod socket;
+pub mod ssrc;
+pub mod stats;
@@ -0,0 +1,1035 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::{BufMut, BytesMut};
+
+use super::psmux::Codec;
+
+// iso 14496-12 and 14496-15, one sample per chunk, moov written at the end
+const MOVIE_TIMESCALE: u32 = 1000;
+const VIDEO_TIMESCALE: u32 = 90000;
+const AUDIO_TIMESCALE: u32 = 8000;
+// 40 ms, for a video sample without a next one
+const DEFAULT_VIDEO_DURATION: u32 = 3600;
+// a dts jump larger than this is a discontinuity, not a duration
+const MAX_VIDEO_DURATION: u64 = 10 * VIDEO_TIMESCALE as u64;
+const TIMESTAMP_MASK: u64 = 0x1_FFFF_FFFF;
+// the mdat follows ftyp, its 16 byte header has a 64 bit size
+const MDAT_START: u64 = 32;
+const MDAT_HEADER_SIZE: u64 = 16;
+
+// rbsp of a nal unit, emulation prevention bytes removed
+fn rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], byte: usize) -> Self {
+        BitReader {
+            data,
+            bit: byte * 8,
+        }
+    }
+
+    fn u(&mut self, n: usize) -> Option<u32> {
+        let mut v = 0u32;
+        for _ in 0..n {
+            let byte = *self.data.get(self.bit / 8)?;
+            v = (v << 1) | ((byte >> (7 - self.bit % 8)) & 1) as u32;
+            self.bit += 1;
+        }
+        Some(v)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.bit += n;
+        (self.bit <= self.data.len() * 8).then_some(())
+    }
+
+    // exp-golomb
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.u(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.u(zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let v = self.ue()?;
+        Some(if v & 1 == 1 {
+            v.div_ceil(2) as i32
+        } else {
+            -((v / 2) as i32)
+        })
+    }
+}
+
+// (width, height) from an h.264 sps
+fn h264_size(sps: &[u8]) -> Option<(u32, u32)> {
+    let data = rbsp(sps);
+    let mut r = BitReader::new(&data, 1);
+    let profile = r.u(8)?;
+    r.skip(16)?;
+    r.ue()?;
+    let mut chroma_format = 1;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = r.ue()?;
+        if chroma_format == 3 {
+            r.skip(1)?;
+        }
+        r.ue()?;
+        r.ue()?;
+        r.skip(1)?;
+        if r.u(1)? == 1 {
+            for i in 0..if chroma_format == 3 { 12 } else { 8 } {
+                if r.u(1)? == 0 {
+                    continue;
+                }
+                let (mut last, mut next) = (8i32, 8i32);
+                for _ in 0..if i < 6 { 16 } else { 64 } {
+                    if next != 0 {
+                        next = last.checked_add(r.se()?)?.checked_add(256)?.rem_euclid(256);
+                    }
+                    last = if next == 0 { last } else { next };
+                }
+            }
+        }
+    }
+    r.ue()?;
+    match r.ue()? {
+        0 => {
+            r.ue()?;
+        }
+        1 => {
+            r.skip(1)?;
+            r.se()?;
+            r.se()?;
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?;
+    r.skip(1)?;
+    let width_mbs = r.ue()?.checked_add(1)?;
+    let height_units = r.ue()?.checked_add(1)?;
+    let frame_mbs_only = r.u(1)?;
+    if frame_mbs_only == 0 {
+        r.skip(1)?;
+    }
+    r.skip(1)?;
+    let (mut left, mut right, mut top, mut bottom) = (0, 0, 0, 0);
+    if r.u(1)? == 1 {
+        (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+    }
+    let (crop_x, crop_y) = match chroma_format {
+        0 | 3 => (1, 2 - frame_mbs_only),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (2, 2 * (2 - frame_mbs_only)),
+    };
+    // the sps comes from the device, nothing here may overflow
+    let width = width_mbs
+        .checked_mul(16)?
+        .checked_sub(left.checked_add(right)?.checked_mul(crop_x)?)?;
+    let height = height_units
+        .checked_mul(16 * (2 - frame_mbs_only))?
+        .checked_sub(top.checked_add(bottom)?.checked_mul(crop_y)?)?;
+    Some((width, height))
+}
+
+// what an hvcc needs from an h.265 sps
+struct H265Sps {
+    // general profile, tier and level, 12 bytes
+    profile_tier_level: Vec<u8>,
+    max_sub_layers: u32,
+    temporal_id_nesting: u32,
+    chroma_format: u32,
+    bit_depth_luma: u32,
+    bit_depth_chroma: u32,
+    width: u32,
+    height: u32,
+}
+
+impl H265Sps {
+    // for an sps that does not parse, the decoder reads the parameter sets
+    // in the hvcC arrays anyway, the number of temporal layers is unknown
+    fn unknown(sps: &[u8]) -> Self {
+        H265Sps {
+            profile_tier_level: rbsp(sps).get(3..15).unwrap_or(&[0; 12]).to_vec(),
+            max_sub_layers: 0,
+            temporal_id_nesting: 0,
+            chroma_format: 1,
+            bit_depth_luma: 8,
+            bit_depth_chroma: 8,
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+fn h265_sps(sps: &[u8]) -> Option<H265Sps> {
+    let data = rbsp(sps);
+    let mut r = BitReader::new(&data, 2);
+    r.skip(4)?;
+    let max_sub_layers_minus1 = r.u(3)?;
+    let temporal_id_nesting = r.u(1)?;
+    let profile_tier_level = data.get(3..15)?.to_vec();
+    r.skip(96)?;
+    let mut present = vec![];
+    for _ in 0..max_sub_layers_minus1 {
+        present.push((r.u(1)?, r.u(1)?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1 as usize))?;
+    }
+    for (profile, level) in present {
+        if profile == 1 {
+            r.skip(88)?;
+        }
+        if level == 1 {
+            r.skip(8)?;
+        }
+    }
+    r.ue()?;
+    let chroma_format = r.ue()?;
+    if chroma_format > 3 {
+        return None;
+    }
+    if chroma_format == 3 {
+        r.skip(1)?;
+    }
+    let mut width = r.ue()?;
+    let mut height = r.ue()?;
+    if r.u(1)? == 1 {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let (sub_x, sub_y) = match chroma_format {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        width = width.checked_sub(left.checked_add(right)?.checked_mul(sub_x)?)?;
+        height = height.checked_sub(top.checked_add(bottom)?.checked_mul(sub_y)?)?;
+    }
+    // 8 to 16 bits
+    let (bit_depth_luma, bit_depth_chroma) = (r.ue()?, r.ue()?);
+    if bit_depth_luma > 8 || bit_depth_chroma > 8 {
+        return None;
+    }
+    Some(H265Sps {
+        profile_tier_level,
+        max_sub_layers: max_sub_layers_minus1 + 1,
+        temporal_id_nesting,
+        chroma_format,
+        bit_depth_luma: bit_depth_luma + 8,
+        bit_depth_chroma: bit_depth_chroma + 8,
+        width,
+        height,
+    })
+}
+
+// nal units of an annex b access unit
+fn nal_units(es: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= es.len() {
+        if es[i] == 0 && es[i + 1] == 0 && es[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let mut units = vec![];
+    for (n, start) in starts.iter().enumerate() {
+        let mut end = starts.get(n + 1).map(|next| next - 3).unwrap_or(es.len());
+        // the leading zero of a 4 byte start code
+        while end > *start && es[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > *start {
+            units.push(&es[*start..end]);
+        }
+    }
+    units
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    offset: u64,
+    size: u32,
+    duration: u32,
+    // pts - dts
+    composition_offset: u32,
+    sync: bool,
+}
+
+struct Track {
+    codec: Codec,
+    timescale: u32,
+    samples: Vec<Sample>,
+    // set by the first sample written
+    first_dts: Option<u64>,
+    last_dts: u64,
+    // parameter sets, vps, sps and pps
+    vps: Vec<u8>,
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+}
+
+impl Track {
+    fn new(codec: Codec) -> Self {
+        Track {
+            codec,
+            timescale: if codec.is_video() {
+                VIDEO_TIMESCALE
+            } else {
+                AUDIO_TIMESCALE
+            },
+            samples: vec![],
+            first_dts: None,
+            last_dts: 0,
+            vps: vec![],
+            sps: vec![],
+            pps: vec![],
+        }
+    }
+
+    fn duration(&self) -> u64 {
+        self.samples.iter().map(|s| s.duration as u64).sum()
+    }
+
+    // in the movie timescale
+    fn movie_duration(&self) -> u64 {
+        self.duration() * MOVIE_TIMESCALE as u64 / self.timescale as u64
+    }
+}
+
+fn put_box(buff: &mut BytesMut, kind: &[u8; 4], body: impl FnOnce(&mut BytesMut)) {
+    let start = buff.len();
+    buff.put_u32(0);
+    buff.put_slice(kind);
+    body(buff);
+    let size = (buff.len() - start) as u32;
+    buff[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn put_full_box(
+    buff: &mut BytesMut,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut BytesMut),
+) {
+    put_box(buff, kind, |buff| {
+        buff.put_u32((version as u32) << 24 | flags);
+        body(buff);
+    });
+}
+
+fn put_matrix(buff: &mut BytesMut) {
+    for v in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        buff.put_u32(v);
+    }
+}
+
+// h.264, h.265 and g711 into an mp4 file as they arrive, the file is named
+// .part until finished
+pub struct Mp4Writer {
+    path: PathBuf,
+    part_path: PathBuf,
+    file: BufWriter<File>,
+    // bytes written, where the next sample goes
+    position: u64,
+    video: Option<Track>,
+    audio: Option<Track>,
+    // pts of the first sample of either track, the later track starts with a gap
+    start_pts: Option<u64>,
+    video_start: u64,
+    audio_start: u64,
+}
+
+impl Mp4Writer {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+        let mut file = BufWriter::new(File::create(&part_path)?);
+
+        let mut head = BytesMut::new();
+        put_box(&mut head, b"ftyp", |buff| {
+            buff.put_slice(b"isom");
+            buff.put_u32(0x200);
+            for brand in [b"isom", b"iso2", b"avc1", b"mp41"] {
+                buff.put_slice(brand);
+            }
+        });
+        // size patched in finish
+        head.put_u32(1);
+        head.put_slice(b"mdat");
+        head.put_u64(MDAT_HEADER_SIZE);
+        file.write_all(&head)?;
+
+        Ok(Mp4Writer {
+            path: path.to_path_buf(),
+            part_path,
+            file,
+            position: head.len() as u64,
+            video: None,
+            audio: None,
+            start_pts: None,
+            video_start: 0,
+            audio_start: 0,
+        })
+    }
+
+    // 90 khz offset of pts from the first sample, wrapping at 33 bits
+    fn since_start(&mut self, pts: u64) -> u64 {
+        let start = *self.start_pts.get_or_insert(pts);
+        pts.wrapping_sub(start) & TIMESTAMP_MASK
+    }
+
+    fn write_sample(&mut self, data: &[&[u8]]) -> io::Result<(u64, u32)> {
+        let offset = self.position;
+        let mut size = 0;
+        for part in data {
+            self.file.write_all(part)?;
+            size += part.len();
+        }
+        self.position += size as u64;
+        Ok((offset, size as u32))
+    }
+
+    // an annex b access unit, nothing is written before the first keyframe
+    pub fn write_video(&mut self, codec: Codec, es: &[u8], pts: u64, dts: u64) -> io::Result<()> {
+        let mut sync = false;
+        let mut sample = vec![];
+        let track = self.video.get_or_insert_with(|| Track::new(codec));
+        for nal in nal_units(es) {
+            let kind = match codec {
+                Codec::H265 => (nal[0] >> 1) & 0x3F,
+                _ => nal[0] & 0x1F,
+            };
+            match (codec, kind) {
+                (Codec::H265, 32) => track.vps = nal.to_vec(),
+                (Codec::H265, 33) | (Codec::H264, 7) => track.sps = nal.to_vec(),
+                (Codec::H265, 34) | (Codec::H264, 8) => track.pps = nal.to_vec(),
+                // access unit delimiters
+                (Codec::H265, 35) | (Codec::H264, 9) => {}
+                (Codec::H265, 16..=21) | (Codec::H264, 5) => {
+                    sync = true;
+                    sample.push(nal);
+                }
+                _ => sample.push(nal),
+            }
+        }
+        let ready = !track.sps.is_empty() && !track.pps.is_empty();
+        if sample.is_empty() || (track.samples.is_empty() && !(sync && ready)) {
+            return Ok(());
+        }
+
+        let first = track.first_dts.is_none();
+        let dts = dts & TIMESTAMP_MASK;
+        if first {
+            self.video_start = self.since_start(dts);
+        }
+        let track = self.video.as_mut().unwrap();
+        track.first_dts.get_or_insert(dts);
+        if let Some(previous) = track.samples.last_mut() {
+            let delta = dts.wrapping_sub(track.last_dts) & TIMESTAMP_MASK;
+            previous.duration = if delta == 0 || delta > MAX_VIDEO_DURATION {
+                DEFAULT_VIDEO_DURATION
+            } else {
+                delta as u32
+            };
+        }
+        track.last_dts = dts;
+        let composition_offset = ((pts & TIMESTAMP_MASK).wrapping_sub(dts) & TIMESTAMP_MASK)
+            .min(MAX_VIDEO_DURATION) as u32;
+
+        let lengths: Vec<[u8; 4]> = sample
+            .iter()
+            .map(|nal| (nal.len() as u32).to_be_bytes())
+            .collect();
+        let mut parts: Vec<&[u8]> = vec![];
+        for (nal, length) in sample.iter().zip(&lengths) {
+            parts.push(length);
+            parts.push(nal);
+        }
+        let (offset, size) = self.write_sample(&parts)?;
+        self.video.as_mut().unwrap().samples.push(Sample {
+            offset,
+            size,
+            duration: DEFAULT_VIDEO_DURATION,
+            composition_offset,
+            sync,
+        });
+        Ok(())
+    }
+
+    // g711, one byte per sample
+    pub fn write_audio(&mut self, codec: Codec, data: &[u8], pts: u64) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.audio.is_none() {
+            self.audio = Some(Track::new(codec));
+            // 90 khz to 8 khz
+            self.audio_start = self.since_start(pts) * AUDIO_TIMESCALE as u64 / 90000;
+        }
+        let (offset, size) = self.write_sample(&[data])?;
+        self.audio.as_mut().unwrap().samples.push(Sample {
+            offset,
+            size,
+            duration: size,
+            composition_offset: 0,
+            sync: true,
+        });
+        Ok(())
+    }
+
+    // seconds of media written
+    pub fn duration(&self) -> f64 {
+        [&self.video, &self.audio]
+            .iter()
+            .filter_map(|t| t.as_ref())
+            .map(|t| t.duration() as f64 / t.timescale as f64)
+            .fold(0.0, f64::max)
+    }
+
+    // moov after the samples, then the file gets its name
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        let mdat_size = self.position - MDAT_START;
+        let moov = self.moov();
+        self.file.write_all(&moov)?;
+        self.file.flush()?;
+
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(MDAT_START + 8))?;
+        file.write_all(&mdat_size.to_be_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&self.part_path, &self.path)?;
+        Ok(self.path)
+    }
+
+    fn moov(&self) -> BytesMut {
+        let tracks: Vec<(&Track, u64)> = [
+            (self.video.as_ref(), self.video_start),
+            (self.audio.as_ref(), self.audio_start),
+        ]
+        .into_iter()
+        .filter_map(|(t, start)| {
+            let t = t.filter(|t| !t.samples.is_empty())?;
+            Some((t, start * MOVIE_TIMESCALE as u64 / t.timescale as u64))
+        })
+        .collect();
+        let duration = tracks
+            .iter()
+            .map(|(t, start)| start + t.movie_duration())
+            .max()
+            .unwrap_or(0);
+
+        let mut buff = BytesMut::new();
+        put_box(&mut buff, b"moov", |buff| {
+            put_full_box(buff, b"mvhd", 1, 0, |buff| {
+                buff.put_u64(0);
+                buff.put_u64(0);
+                buff.put_u32(MOVIE_TIMESCALE);
+                buff.put_u64(duration);
+                buff.put_u32(0x0001_0000);
+                buff.put_u16(0x0100);
+                buff.put_bytes(0, 10);
+                put_matrix(buff);
+                buff.put_bytes(0, 24);
+                buff.put_u32(tracks.len() as u32 + 1);
+            });
+            for (i, (track, start)) in tracks.iter().enumerate() {
+                Self::put_trak(buff, track, i as u32 + 1, *start);
+            }
+        });
+        buff
+    }
+
+    fn put_trak(buff: &mut BytesMut, track: &Track, track_id: u32, start: u64) {
+        let video = track.codec.is_video();
+        let (width, height) = Self::video_size(track);
+        let movie_duration = track.movie_duration();
+        put_box(buff, b"trak", |buff| {
+            put_full_box(buff, b"tkhd", 1, 3, |buff| {
+                buff.put_u64(0);
+                buff.put_u64(0);
+                buff.put_u32(track_id);
+                buff.put_u32(0);
+                buff.put_u64(start + movie_duration);
+                buff.put_bytes(0, 8);
+                buff.put_u16(0);
+                buff.put_u16(0);
+                buff.put_u16(if video { 0 } else { 0x0100 });
+                buff.put_u16(0);
+                put_matrix(buff);
+                buff.put_u32(width << 16);
+                buff.put_u32(height << 16);
+            });
+            // a track starting after the other one begins with an empty edit
+            put_box(buff, b"edts", |buff| {
+                put_full_box(buff, b"elst", 1, 0, |buff| {
+                    buff.put_u32(if start > 0 { 2 } else { 1 });
+                    if start > 0 {
+                        buff.put_u64(start);
+                        buff.put_i64(-1);
+                        buff.put_u32(0x0001_0000);
+                    }
+                    buff.put_u64(movie_duration);
+                    buff.put_i64(0);
+                    buff.put_u32(0x0001_0000);
+                });
+            });
+            put_box(buff, b"mdia", |buff| {
+                put_full_box(buff, b"mdhd", 1, 0, |buff| {
+                    buff.put_u64(0);
+                    buff.put_u64(0);
+                    buff.put_u32(track.timescale);
+                    buff.put_u64(track.duration());
+                    // und
+                    buff.put_u16(0x55C4);
+                    buff.put_u16(0);
+                });
+                put_full_box(buff, b"hdlr", 0, 0, |buff| {
+                    buff.put_u32(0);
+                    buff.put_slice(if video { b"vide" } else { b"soun" });
+                    buff.put_bytes(0, 12);
+                    buff.put_slice(if video {
+                        b"VideoHandler\0"
+                    } else {
+                        b"SoundHandler\0"
+                    });
+                });
+                put_box(buff, b"minf", |buff| {
+                    if video {
+                        put_full_box(buff, b"vmhd", 0, 1, |buff| buff.put_bytes(0, 8));
+                    } else {
+                        put_full_box(buff, b"smhd", 0, 0, |buff| buff.put_u32(0));
+                    }
+                    put_box(buff, b"dinf", |buff| {
+                        put_full_box(buff, b"dref", 0, 0, |buff| {
+                            buff.put_u32(1);
+                            put_full_box(buff, b"url ", 0, 1, |_| {});
+                        });
+                    });
+                    Self::put_stbl(buff, track, width, height);
+                });
+            });
+        });
+    }
+
+    fn video_size(track: &Track) -> (u32, u32) {
+        let size = match track.codec {
+            Codec::H264 => h264_size(&track.sps),
+            Codec::H265 => h265_sps(&track.sps).map(|sps| (sps.width, sps.height)),
+            _ => None,
+        };
+        // 16 bits in the sample entry
+        size.filter(|(width, height)| *width <= u16::MAX as u32 && *height <= u16::MAX as u32)
+            .unwrap_or((0, 0))
+    }
+
+    fn put_stbl(buff: &mut BytesMut, track: &Track, width: u32, height: u32) {
+        put_box(buff, b"stbl", |buff| {
+            put_full_box(buff, b"stsd", 0, 0, |buff| {
+                buff.put_u32(1);
+                Self::put_sample_entry(buff, track, width, height);
+            });
+
+            let mut runs: Vec<(u32, u32)> = vec![];
+            for sample in &track.samples {
+                match runs.last_mut() {
+                    Some((count, duration)) if *duration == sample.duration => *count += 1,
+                    _ => runs.push((1, sample.duration)),
+                }
+            }
+            put_full_box(buff, b"stts", 0, 0, |buff| {
+                buff.put_u32(runs.len() as u32);
+                for (count, duration) in &runs {
+                    buff.put_u32(*count);
+                    buff.put_u32(*duration);
+                }
+            });
+
+            if track.samples.iter().any(|s| s.composition_offset != 0) {
+                let mut runs: Vec<(u32, u32)> = vec![];
+                for sample in &track.samples {
+                    match runs.last_mut() {
+                        Some((count, offset)) if *offset == sample.composition_offset => {
+                            *count += 1
+                        }
+                        _ => runs.push((1, sample.composition_offset)),
+                    }
+                }
+                put_full_box(buff, b"ctts", 0, 0, |buff| {
+                    buff.put_u32(runs.len() as u32);
+                    for (count, offset) in &runs {
+                        buff.put_u32(*count);
+                        buff.put_u32(*offset);
+                    }
+                });
+            }
+
+            if track.codec.is_video() {
+                let sync: Vec<u32> = (1..)
+                    .zip(&track.samples)
+                    .filter(|(_, s)| s.sync)
+                    .map(|(i, _)| i)
+                    .collect();
+                put_full_box(buff, b"stss", 0, 0, |buff| {
+                    buff.put_u32(sync.len() as u32);
+                    for i in sync {
+                        buff.put_u32(i);
+                    }
+                });
+            }
+
+            put_full_box(buff, b"stsc", 0, 0, |buff| {
+                buff.put_u32(1);
+                buff.put_u32(1);
+                buff.put_u32(1);
+                buff.put_u32(1);
+            });
+            put_full_box(buff, b"stsz", 0, 0, |buff| {
+                buff.put_u32(0);
+                buff.put_u32(track.samples.len() as u32);
+                for sample in &track.samples {
+                    buff.put_u32(sample.size);
+                }
+            });
+            put_full_box(buff, b"co64", 0, 0, |buff| {
+                buff.put_u32(track.samples.len() as u32);
+                for sample in &track.samples {
+                    buff.put_u64(sample.offset);
+                }
+            });
+        });
+    }
+
+    fn put_sample_entry(buff: &mut BytesMut, track: &Track, width: u32, height: u32) {
+        let kind = match track.codec {
+            Codec::H264 => b"avc1",
+            Codec::H265 => b"hvc1",
+            Codec::G711A => b"alaw",
+            Codec::G711U => b"ulaw",
+        };
+        put_box(buff, kind, |buff| {
+            buff.put_bytes(0, 6);
+            // data reference index
+            buff.put_u16(1);
+            if !track.codec.is_video() {
+                buff.put_bytes(0, 8);
+                // mono, 16 bit once decoded
+                buff.put_u16(1);
+                buff.put_u16(16);
+                buff.put_u32(0);
+                buff.put_u32(AUDIO_TIMESCALE << 16);
+                return;
+            }
+
+            buff.put_bytes(0, 16);
+            buff.put_u16(width as u16);
+            buff.put_u16(height as u16);
+            // 72 dpi
+            buff.put_u32(0x0048_0000);
+            buff.put_u32(0x0048_0000);
+            buff.put_u32(0);
+            buff.put_u16(1);
+            buff.put_bytes(0, 32);
+            buff.put_u16(0x0018);
+            buff.put_i16(-1);
+            match track.codec {
+                Codec::H264 => Self::put_avcc(buff, track),
+                _ => Self::put_hvcc(buff, track),
+            }
+        });
+    }
+
+    fn put_avcc(buff: &mut BytesMut, track: &Track) {
+        put_box(buff, b"avcC", |buff| {
+            buff.put_u8(1);
+            // profile, compatibility and level
+            buff.put_slice(track.sps.get(1..4).unwrap_or(&[0, 0, 0]));
+            // 4 byte lengths
+            buff.put_u8(0xFF);
+            buff.put_u8(0xE1);
+            buff.put_u16(track.sps.len() as u16);
+            buff.put_slice(&track.sps);
+            buff.put_u8(1);
+            buff.put_u16(track.pps.len() as u16);
+            buff.put_slice(&track.pps);
+        });
+    }
+
+    fn put_hvcc(buff: &mut BytesMut, track: &Track) {
+        let sps = h265_sps(&track.sps).unwrap_or_else(|| {
+            tracing::warn!("h265 sps not parsed, hvcC written with defaults");
+            H265Sps::unknown(&track.sps)
+        });
+        put_box(buff, b"hvcC", |buff| {
+            buff.put_u8(1);
+            buff.put_slice(&sps.profile_tier_level);
+            buff.put_u16(0xF000);
+            buff.put_u8(0xFC);
+            buff.put_u8(0xFC | sps.chroma_format as u8);
+            buff.put_u8(0xF8 | (sps.bit_depth_luma - 8) as u8);
+            buff.put_u8(0xF8 | (sps.bit_depth_chroma - 8) as u8);
+            buff.put_u16(0);
+            // 4 byte lengths
+            buff.put_u8(
+                ((sps.max_sub_layers as u8 & 0x07) << 3)
+                    | ((sps.temporal_id_nesting as u8) << 2)
+                    | 0x03,
+            );
+            let arrays: Vec<(u8, &Vec<u8>)> =
+                [(32, &track.vps), (33, &track.sps), (34, &track.pps)]
+                    .into_iter()
+                    .filter(|(_, nal)| !nal.is_empty())
+                    .collect();
+            buff.put_u8(arrays.len() as u8);
+            for (kind, nal) in arrays {
+                buff.put_u8(0x80 | kind);
+                buff.put_u16(1);
+                buff.put_u16(nal.len() as u16);
+                buff.put_slice(nal);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // baseline 640x480
+    const SPS: [u8; 9] = [0x67, 0x42, 0xC0, 0x1E, 0xDA, 0x02, 0x80, 0xF6, 0x40];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x38, 0x80];
+
+    // (type, body) of the boxes in buff
+    fn boxes(mut buff: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut boxes = vec![];
+        while buff.len() >= 8 {
+            let kind = buff[4..8].try_into().unwrap();
+            let (header, size) = match u32::from_be_bytes(buff[..4].try_into().unwrap()) {
+                1 => (
+                    16,
+                    u64::from_be_bytes(buff[8..16].try_into().unwrap()) as usize,
+                ),
+                size => (8, size as usize),
+            };
+            boxes.push((kind, &buff[header..size]));
+            buff = &buff[size..];
+        }
+        boxes
+    }
+
+    fn child<'a>(buff: &'a [u8], kind: &[u8; 4]) -> &'a [u8] {
+        boxes(buff)
+            .into_iter()
+            .find(|(k, _)| k == kind)
+            .map(|(_, body)| body)
+            .unwrap()
+    }
+
+    fn stbl(moov: &[u8], track: usize) -> &[u8] {
+        let trak = boxes(moov)
+            .into_iter()
+            .filter(|(k, _)| k == b"trak")
+            .nth(track)
+            .unwrap()
+            .1;
+        child(child(child(trak, b"mdia"), b"minf"), b"stbl")
+    }
+
+    fn u32_at(buff: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(buff[at..at + 4].try_into().unwrap())
+    }
+
+    fn annex_b(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&[0, 0, 0, 1], *nal].concat())
+            .collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}.{}.mp4", name, std::process::id()))
+    }
+
+    #[test]
+    fn h264_size_from_the_sps() {
+        assert_eq!(h264_size(&SPS), Some((640, 480)));
+        assert_eq!(h264_size(&SPS[..3]), None);
+    }
+
+    #[test]
+    fn splits_annex_b_nal_units() {
+        let es = [
+            0, 0, 0, 1, 0x09, 0xF0, 0, 0, 1, 0x65, 1, 0, 0, 0, 0, 1, 0x41,
+        ];
+        assert_eq!(
+            nal_units(&es),
+            vec![&[0x09, 0xF0][..], &[0x65, 1][..], &[0x41][..]]
+        );
+    }
+
+    #[test]
+    fn writes_h264_and_g711a_boxes() {
+        let path = temp_path("boxes");
+        let mut writer = Mp4Writer::create(&path).unwrap();
+        let idr = [0x65, 0x88, 0x84];
+        let slice = [0x41, 0x9A, 0x02];
+        writer
+            .write_video(Codec::H264, &annex_b(&[&SPS, &PPS, &idr]), 3600, 0)
+            .unwrap();
+        writer.write_audio(Codec::G711A, &[0xD5; 160], 0).unwrap();
+        writer
+            .write_video(Codec::H264, &annex_b(&[&slice]), 7200, 3600)
+            .unwrap();
+        assert!((writer.duration() - 0.08).abs() < 1e-9);
+        assert_eq!(writer.finish().unwrap(), path);
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let top = boxes(&file);
+        let kinds: Vec<&[u8; 4]> = top.iter().map(|(k, _)| k).collect();
+        assert_eq!(kinds, vec![b"ftyp", b"mdat", b"moov"]);
+        assert_eq!(&top[0].1[..4], b"isom");
+
+        // length prefixed nal units, the parameter sets go to the avcC
+        let mdat = top[1].1;
+        let mut expected = vec![0, 0, 0, 3];
+        expected.extend_from_slice(&idr);
+        expected.extend_from_slice(&[0xD5; 160]);
+        expected.extend_from_slice(&[0, 0, 0, 3]);
+        expected.extend_from_slice(&slice);
+        assert_eq!(mdat, &expected[..]);
+
+        let moov = top[2].1;
+        let video = stbl(moov, 0);
+        // full box header and entry count, then the sample entry
+        let (kind, avc1) = boxes(&child(video, b"stsd")[8..])[0];
+        assert_eq!(&kind, b"avc1");
+        assert_eq!(
+            (&avc1[24..26], &avc1[26..28]),
+            (&[2, 0x80][..], &[1, 0xE0][..])
+        );
+        let avcc = child(&avc1[78..], b"avcC");
+        assert_eq!(&avcc[1..4], &SPS[1..4]);
+        assert_eq!(&avcc[8..8 + SPS.len()], &SPS);
+        assert_eq!(&avcc[avcc.len() - PPS.len()..], &PPS);
+
+        // one sync sample, offsets into the mdat
+        let stss = child(video, b"stss");
+        assert_eq!((u32_at(stss, 4), u32_at(stss, 8)), (1, 1));
+        let stsz = child(video, b"stsz");
+        assert_eq!((u32_at(stsz, 8), u32_at(stsz, 12)), (2, 7));
+        let co64 = child(video, b"co64");
+        assert_eq!(&co64[8..16], &(MDAT_START + MDAT_HEADER_SIZE).to_be_bytes());
+        assert!(!child(video, b"ctts").is_empty());
+
+        let audio = stbl(moov, 1);
+        assert_eq!(&boxes(&child(audio, b"stsd")[8..])[0].0, b"alaw");
+        let stts = child(audio, b"stts");
+        assert_eq!((u32_at(stts, 8), u32_at(stts, 12)), (1, 160));
+    }
+
+    #[test]
+    fn rejects_overflowing_sps_sizes() {
+        let header = [0x67, 0x42, 0xC0, 0x1E];
+        for rest in [
+            // pic_width_in_mbs_minus1 of 2^32 - 2
+            &[0xDA, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xE8][..],
+            // pic_height_in_map_units_minus1 of 2^32 - 2
+            &[0xDA, 0x40, 0x00, 0x00, 0x00, 0x7F, 0xFF, 0xFF, 0xFF, 0xE8],
+            // frame_crop_left_offset of 2^32 - 2
+            &[
+                0xDA, 0x7C, 0x00, 0x00, 0x00, 0x07, 0xFF, 0xFF, 0xFF, 0xFF, 0x80,
+            ],
+        ] {
+            assert_eq!(h264_size(&[&header[..], rest].concat()), None);
+        }
+    }
+
+    #[test]
+    fn writes_an_hvcc_for_an_unparsed_h265_sps() {
+        let path = temp_path("hvcc");
+        let mut writer = Mp4Writer::create(&path).unwrap();
+        let vps = [0x40, 0x01, 0x0C];
+        let sps = [0x42, 0x01, 0x01];
+        let pps = [0x44, 0x01, 0xC1];
+        assert!(h265_sps(&sps).is_none());
+        writer
+            .write_video(
+                Codec::H265,
+                &annex_b(&[&vps, &sps, &pps, &[0x26, 0x01, 7]]),
+                0,
+                0,
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let video = stbl(boxes(&file)[2].1, 0);
+        let (kind, hvc1) = boxes(&child(video, b"stsd")[8..])[0];
+        assert_eq!(&kind, b"hvc1");
+        let hvcc = child(&hvc1[78..], b"hvcC");
+        // three arrays of one parameter set each
+        assert_eq!(hvcc[22], 3);
+        for (at, nal) in [(23, &vps), (31, &sps), (39, &pps)] {
+            assert_eq!(hvcc[at] & 0x3F, nal[0] >> 1);
+            assert_eq!(&hvcc[at + 5..at + 8], nal);
+        }
+    }
+
+    #[test]
+    fn drops_video_before_the_first_keyframe() {
+        let path = temp_path("keyframe");
+        let mut writer = Mp4Writer::create(&path).unwrap();
+        writer
+            .write_video(Codec::H264, &annex_b(&[&[0x41, 1]]), 0, 0)
+            .unwrap();
+        // a keyframe without parameter sets is not decodable either
+        writer
+            .write_video(Codec::H264, &annex_b(&[&[0x65, 2]]), 3600, 3600)
+            .unwrap();
+        writer
+            .write_video(Codec::H264, &annex_b(&[&SPS, &PPS, &[0x65, 3]]), 7200, 7200)
+            .unwrap();
+        writer.finish().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let top = boxes(&file);
+        assert_eq!(top[1].1, &[0, 0, 0, 2, 0x65, 3]);
+        assert_eq!(u32_at(child(stbl(top[2].1, 0), b"stsz"), 8), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use bytes::BytesMut;
+
+// slabs waiting for their Bytes to drop, older ones are let go
+const MAX_RETIRED_SLABS: usize = 64;
+
+// carves Bytes out of large slabs, packets of a session are packed densely
+// and a slab is reused once every Bytes carved from it is dropped, so a
+// steady stream stops allocating
+pub struct BufferPool {
+    slab_size: usize,
+    current: BytesMut,
+    retired: VecDeque<BytesMut>,
+    allocated: u64,
+    reused: u64,
+}
+
+impl BufferPool {
+    pub fn new(slab_size: usize) -> Self {
+        BufferPool {
+            slab_size,
+            current: BytesMut::new(),
+            retired: VecDeque::new(),
+            allocated: 0,
+            reused: 0,
+        }
+    }
+
+    fn reserve(&mut self, len: usize) {
+        if self.current.capacity() >= len {
+            return;
+        }
+        if len > self.slab_size {
+            // oversized, not pooled
+            let slab = std::mem::replace(&mut self.current, BytesMut::with_capacity(len));
+            self.retire(slab);
+            self.allocated += 1;
+            return;
+        }
+
+        let reclaimed = self
+            .retired
+            .iter_mut()
+            .position(|slab| slab.try_reclaim(self.slab_size));
+        let slab = match reclaimed.and_then(|i| self.retired.remove(i)) {
+            Some(slab) => {
+                self.reused += 1;
+                slab
+            }
+            None => {
+                self.allocated += 1;
+                BytesMut::with_capacity(self.slab_size)
+            }
+        };
+        let slab = std::mem::replace(&mut self.current, slab);
+        self.retire(slab);
+    }
+
+    fn retire(&mut self, slab: BytesMut) {
+        // the initial empty buffer, or a slab carved to the last byte
+        if slab.capacity() == 0 {
+            return;
+        }
+        if self.retired.len() >= MAX_RETIRED_SLABS {
+            self.retired.pop_front();
+        }
+        self.retired.push_back(slab);
+    }
+
+    // zeroed buffer of len bytes to read into, freeze it once filled
+    pub fn take(&mut self, len: usize) -> BytesMut {
+        self.reserve(len);
+        self.current.resize(len, 0);
+        self.current.split()
+    }
+
+    // the current slab with room for len bytes, to receive into in place, the
+    // Bytes split off it are pooled like the ones of take
+    pub fn slab(&mut self, len: usize) -> &mut BytesMut {
+        self.reserve(len);
+        &mut self.current
+    }
+
+    // slabs allocated and reused so far
+    pub fn allocated(&self) -> u64 {
+        self.allocated
+    }
+
+    pub fn reused(&self) -> u64 {
+        self.reused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_buffers_into_one_slab() {
+        let mut pool = BufferPool::new(4096);
+        let a = pool.take(100);
+        let b = pool.take(200);
+        assert_eq!((a.len(), b.len()), (100, 200));
+        assert!(a.iter().chain(b.iter()).all(|&x| x == 0));
+        // b follows a in the same allocation
+        assert_eq!(unsafe { a.as_ptr().add(100) }, b.as_ptr());
+        assert_eq!((pool.allocated(), pool.reused()), (1, 0));
+    }
+
+    #[test]
+    fn reuses_a_slab_once_its_bytes_drop() {
+        let mut pool = BufferPool::new(1024);
+        let first = pool.take(600).freeze();
+        let held = pool.take(600).freeze();
+        assert_eq!(pool.allocated(), 2);
+        drop(first);
+
+        // the rest of the second slab is too small, the first is free again
+        let again = pool.take(600);
+        assert_eq!((pool.allocated(), pool.reused()), (2, 1));
+        assert_eq!(again.len(), 600);
+        drop(held);
+    }
+
+    #[test]
+    fn does_not_reuse_a_slab_still_referenced() {
+        let mut pool = BufferPool::new(1024);
+        let first = pool.take(600).freeze();
+        let _second = pool.take(600).freeze();
+        let _third = pool.take(600).freeze();
+        assert_eq!((pool.allocated(), pool.reused()), (3, 0));
+        drop(first);
+    }
+
+    #[test]
+    fn oversized_buffers_are_not_pooled() {
+        let mut pool = BufferPool::new(1024);
+        let big = pool.take(4096);
+        assert_eq!(big.len(), 4096);
+        assert_eq!(pool.allocated(), 1);
+        // the oversized buffer is used up, the next take gets a pooled slab
+        let small = pool.take(10);
+        assert_eq!(small.len(), 10);
+        assert_eq!((pool.allocated(), pool.reused()), (2, 0));
+    }
+
+    #[test]
+    fn slab_splits_share_the_pool() {
+        let mut pool = BufferPool::new(4096);
+        let slab = pool.slab(64);
+        assert!(slab.capacity() >= 64);
+        slab.extend_from_slice(b"hello");
+        let hello = slab.split().freeze();
+        let next = pool.take(3);
+        assert_eq!(&hello[..], b"hello");
+        assert_eq!(unsafe { hello.as_ptr().add(5) }, next.as_ptr());
+        assert_eq!(pool.allocated(), 1);
+    }
+}
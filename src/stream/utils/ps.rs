@@ -0,0 +1,336 @@
+use bytes::Bytes;
+
+// mpeg-ps start codes, iso 13818-1
+pub const PACK_HEADER: u8 = 0xBA;
+pub const SYSTEM_HEADER: u8 = 0xBB;
+pub const PROGRAM_STREAM_MAP: u8 = 0xBC;
+pub const PADDING_STREAM: u8 = 0xBE;
+pub const END_CODE: u8 = 0xB9;
+
+// one pes packet of a ps buffer, a packet without pts continues the previous
+// one of its stream
+#[derive(Debug)]
+pub struct Pes<'a> {
+    pub stream_id: u8,
+    // 90 khz
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    pub payload: &'a [u8],
+}
+
+pub fn is_video(stream_id: u8) -> bool {
+    (0xE0..=0xEF).contains(&stream_id)
+}
+
+pub fn is_audio(stream_id: u8) -> bool {
+    (0xC0..=0xDF).contains(&stream_id)
+}
+
+// offset and code of the next 00 00 01 <code> from start
+fn next_start_code(buff: &[u8], start: usize) -> Option<(usize, u8)> {
+    let mut i = start;
+    while i + 4 <= buff.len() {
+        if buff[i] == 0 && buff[i + 1] == 0 && buff[i + 2] == 1 {
+            return Some((i, buff[i + 3]));
+        }
+        i += 1;
+    }
+    None
+}
+
+// length of the packet at offset, from its 16 bit length field
+fn packet_len(buff: &[u8], offset: usize) -> Option<usize> {
+    let len = buff.get(offset + 4..offset + 6)?;
+    Some(6 + u16::from_be_bytes([len[0], len[1]]) as usize)
+}
+
+// 33 bit timestamp of a pts or dts field
+fn timestamp(b: &[u8]) -> Option<u64> {
+    let b = b.get(..5)?;
+    Some(
+        ((b[0] as u64 >> 1) & 0x07) << 30
+            | (b[1] as u64) << 22
+            | (b[2] as u64 >> 1) << 15
+            | (b[3] as u64) << 7
+            | (b[4] as u64 >> 1),
+    )
+}
+
+// length of the pack header at offset, mpeg-2 with its stuffing or mpeg-1
+fn pack_header_len(buff: &[u8], offset: usize) -> Option<usize> {
+    if buff.get(offset + 4)? & 0xC0 == 0x40 {
+        Some(14 + (buff.get(offset + 13)? & 0x07) as usize)
+    } else {
+        Some(12)
+    }
+}
+
+fn pes(buff: &[u8], offset: usize, end: usize) -> Option<Pes<'_>> {
+    let stream_id = buff[offset + 3];
+    let flags = *buff.get(offset + 7)?;
+    let header_len = *buff.get(offset + 8)? as usize;
+    let start = offset + 9 + header_len;
+    if start > end {
+        return None;
+    }
+    // the timestamps are read from the header only, never from the payload
+    let header = &buff[offset + 9..start];
+    let (pts, dts) = match flags & 0xC0 {
+        0xC0 if header_len >= 10 => (Some(timestamp(header)?), Some(timestamp(&header[5..])?)),
+        0x80 if header_len >= 5 => {
+            let pts = timestamp(header)?;
+            (Some(pts), Some(pts))
+        }
+        0xC0 | 0x80 => return None,
+        _ => (None, None),
+    };
+    Some(Pes {
+        stream_id,
+        pts,
+        dts,
+        payload: &buff[start..end],
+    })
+}
+
+// audio and video pes packets of a buffer of whole packs, a truncated packet
+// ends it
+pub fn pes_packets(buff: &[u8]) -> Vec<Pes<'_>> {
+    let mut packets = vec![];
+    let mut offset = 0;
+    while let Some((at, code)) = next_start_code(buff, offset) {
+        let len = match code {
+            PACK_HEADER => pack_header_len(buff, at),
+            END_CODE => Some(4),
+            0xBB..=0xFF => packet_len(buff, at),
+            // not a packet of the ps layer
+            _ => Some(4),
+        };
+        let Some(end) = len.map(|len| at + len).filter(|end| *end <= buff.len()) else {
+            break;
+        };
+        if is_video(code) || is_audio(code) {
+            match pes(buff, at, end) {
+                None => break,
+                Some(pes) => packets.push(pes),
+            }
+        }
+        offset = end;
+    }
+    packets
+}
+
+// offset of the system header or psm when it follows the pack header, gb28181
+// devices send them in front of every keyframe only
+fn keyframe_headers_start(first_payload: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some((offset, code)) = next_start_code(first_payload, start) {
+        match code {
+            PACK_HEADER => start = offset + 4,
+            SYSTEM_HEADER | PROGRAM_STREAM_MAP => return Some(offset),
+            _ => return None,
+        }
+    }
+    None
+}
+
+pub fn is_keyframe(first_payload: &[u8]) -> bool {
+    keyframe_headers_start(first_payload).is_some()
+}
+
+// system header through psm of a keyframe, what a demuxer needs to find the
+// elementary streams and their codecs, shares memory with the payload
+pub fn headers(first_payload: &Bytes) -> Option<Bytes> {
+    let start = keyframe_headers_start(first_payload)?;
+    let mut offset = start;
+    loop {
+        let (at, code) = next_start_code(first_payload, offset)?;
+        let end = at + packet_len(first_payload, at)?;
+        if end > first_payload.len() {
+            return None;
+        }
+        match code {
+            SYSTEM_HEADER => offset = end,
+            PROGRAM_STREAM_MAP => return Some(first_payload.slice(start..end)),
+            _ => return None,
+        }
+    }
+}
+
+// stream_type of a psm entry, iso 13818-1 and gb28181 appendix
+fn codec_name(stream_type: u8) -> &'static str {
+    match stream_type {
+        0x0F => "aac",
+        0x10 => "mpeg4",
+        0x1B => "h264",
+        0x24 => "h265",
+        0x80 => "svac",
+        0x90 => "g711a",
+        0x91 => "g711u",
+        0x92 => "g722.1",
+        0x93 => "g723.1",
+        0x99 => "g729",
+        0x9B => "svac_audio",
+        _ => "unknown",
+    }
+}
+
+// (video, audio) codecs of the elementary streams in the psm of headers,
+// empty when there is no such stream
+pub fn codecs(headers: &[u8]) -> (&'static str, &'static str) {
+    let mut codecs = ("", "");
+    let mut offset = 0;
+    let psm = loop {
+        match next_start_code(headers, offset) {
+            None => return codecs,
+            Some((at, PROGRAM_STREAM_MAP)) => break at,
+            Some((at, _)) => offset = at + 4,
+        }
+    };
+    let u16_at = |at: usize| -> Option<usize> {
+        let b = headers.get(at..at + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]) as usize)
+    };
+
+    // header, version and marker bytes, then the program stream info
+    let Some(info_len) = u16_at(psm + 8) else {
+        return codecs;
+    };
+    let map_start = psm + 10 + info_len;
+    let Some(map_len) = u16_at(map_start) else {
+        return codecs;
+    };
+    let mut entry = map_start + 2;
+    let map_end = (map_start + 2 + map_len).min(headers.len());
+    while entry + 4 <= map_end {
+        let (stream_type, stream_id) = (headers[entry], headers[entry + 1]);
+        match stream_id {
+            0xE0..=0xEF if codecs.0.is_empty() => codecs.0 = codec_name(stream_type),
+            0xC0..=0xDF if codecs.1.is_empty() => codecs.1 = codec_name(stream_type),
+            _ => {}
+        }
+        entry += 4 + u16_at(entry + 2).unwrap_or(0);
+    }
+    codecs
+}
+
+// one access unit of an elementary stream, payloads of its pes packets joined
+#[derive(Debug, Clone)]
+pub struct EsFrame {
+    pub stream_id: u8,
+    pub pts: u64,
+    pub dts: u64,
+    pub data: Vec<u8>,
+}
+
+// elementary stream frames out of ps frames, a frame is complete once the
+// next one of its stream starts
+#[derive(Default)]
+pub struct Demuxer {
+    pending: Vec<EsFrame>,
+    codecs: (&'static str, &'static str),
+}
+
+impl Demuxer {
+    pub fn push(&mut self, buff: &[u8]) -> Vec<EsFrame> {
+        if let Some((at, _)) = next_start_code(buff, 0).filter(|(_, code)| *code == PACK_HEADER) {
+            if is_keyframe(&buff[at..]) {
+                let (video, audio) = codecs(&buff[at..]);
+                if !video.is_empty() || !audio.is_empty() {
+                    self.codecs = (video, audio);
+                }
+            }
+        }
+
+        let mut frames = vec![];
+        for pes in pes_packets(buff) {
+            let pending = self
+                .pending
+                .iter()
+                .position(|f| f.stream_id == pes.stream_id);
+            match (pes.pts, pes.dts, pending) {
+                (Some(pts), Some(dts), _) => {
+                    if let Some(i) = pending {
+                        frames.push(self.pending.swap_remove(i));
+                    }
+                    self.pending.push(EsFrame {
+                        stream_id: pes.stream_id,
+                        pts,
+                        dts,
+                        data: pes.payload.to_vec(),
+                    });
+                }
+                (_, _, Some(i)) => self.pending[i].data.extend_from_slice(pes.payload),
+                // no timestamp yet, a stream starting mid frame
+                _ => {}
+            }
+        }
+        frames
+    }
+
+    pub fn flush(&mut self) -> Vec<EsFrame> {
+        std::mem::take(&mut self.pending)
+    }
+
+    // (video, audio) of the latest psm
+    pub fn codecs(&self) -> (&'static str, &'static str) {
+        self.codecs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 90 khz timestamp field with its marker bits
+    fn ts(prefix: u8, t: u64) -> [u8; 5] {
+        [
+            prefix << 4 | ((t >> 29) & 0x0E) as u8 | 1,
+            (t >> 22) as u8,
+            ((t >> 14) & 0xFE) as u8 | 1,
+            (t >> 7) as u8,
+            ((t << 1) & 0xFE) as u8 | 1,
+        ]
+    }
+
+    fn pes_packet(flags: u8, header: &[u8], payload: &[u8]) -> Vec<u8> {
+        let len = (3 + header.len() + payload.len()) as u16;
+        let mut buff = vec![0, 0, 1, 0xE0];
+        buff.extend_from_slice(&len.to_be_bytes());
+        buff.extend_from_slice(&[0x80, flags, header.len() as u8]);
+        buff.extend_from_slice(header);
+        buff.extend_from_slice(payload);
+        buff
+    }
+
+    #[test]
+    fn reads_pts_and_dts_from_the_header() {
+        let header = [ts(3, 7200), ts(1, 3600)].concat();
+        let buff = pes_packet(0xC0, &header, b"abc");
+        let packets = pes_packets(&buff);
+        assert_eq!(packets.len(), 1);
+        assert_eq!((packets[0].pts, packets[0].dts), (Some(7200), Some(3600)));
+        assert_eq!(packets[0].payload, b"abc");
+
+        // stuffing after the pts
+        let header = [&ts(2, 0x1_2345_6789)[..], &[0xFF, 0xFF]].concat();
+        let buff = pes_packet(0x80, &header, b"d");
+        let packets = pes_packets(&buff);
+        assert_eq!(packets[0].pts, Some(0x1_2345_6789));
+        assert_eq!(packets[0].dts, Some(0x1_2345_6789));
+        assert_eq!(packets[0].payload, b"d");
+
+        let buff = pes_packet(0x00, &[], b"e");
+        let packets = pes_packets(&buff);
+        assert_eq!((packets[0].pts, packets[0].payload), (None, &b"e"[..]));
+    }
+
+    #[test]
+    fn rejects_timestamps_beyond_the_header() {
+        // pts and dts flagged, room for the pts only, the dts would be payload
+        let buff = pes_packet(0xC0, &ts(3, 7200), &ts(1, 3600));
+        assert!(pes_packets(&buff).is_empty());
+        // pts flagged without room for it
+        let buff = pes_packet(0x80, &[0x21, 0, 1], b"payload");
+        assert!(pes_packets(&buff).is_empty());
+    }
+}
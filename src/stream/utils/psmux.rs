@@ -0,0 +1,379 @@
+use bytes::{BufMut, BytesMut};
+
+use super::ps;
+use super::reorder::Frame;
+
+// pes packet length is 16 bits, less the flags and a pts and a dts
+const MAX_PES_PAYLOAD: usize = 0xFFFF - 13;
+// in units of 50 bytes per second, what gb28181 devices send
+const MUX_RATE: u32 = 6106;
+// an audio only stream has no keyframes, the headers are repeated instead
+const AUDIO_HEADERS_INTERVAL: u64 = 50;
+const VIDEO_STREAM_ID: u8 = 0xE0;
+const AUDIO_STREAM_ID: u8 = 0xC0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    H265,
+    G711A,
+    G711U,
+}
+
+impl Codec {
+    // names of ps::codecs
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "h264" => Some(Codec::H264),
+            "h265" => Some(Codec::H265),
+            "g711a" => Some(Codec::G711A),
+            "g711u" => Some(Codec::G711U),
+            _ => None,
+        }
+    }
+
+    // psm stream_type, iso 13818-1 and gb28181 appendix
+    pub fn stream_type(&self) -> u8 {
+        match self {
+            Codec::H264 => 0x1B,
+            Codec::H265 => 0x24,
+            Codec::G711A => 0x90,
+            Codec::G711U => 0x91,
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, Codec::H264 | Codec::H265)
+    }
+}
+
+// idr or parameter sets in front of the first slice of an annex b access unit
+fn is_keyframe(codec: Codec, es: &[u8]) -> bool {
+    let mut i = 0;
+    while i + 3 < es.len() {
+        if es[i] != 0 || es[i + 1] != 0 || es[i + 2] != 1 {
+            i += 1;
+            continue;
+        }
+        let header = es[i + 3];
+        match codec {
+            Codec::H264 => match header & 0x1F {
+                5 | 7 => return true,
+                1..=4 => return false,
+                _ => {}
+            },
+            _ => match (header >> 1) & 0x3F {
+                16..=21 | 32 | 33 => return true,
+                0..=31 => return false,
+                _ => {}
+            },
+        }
+        i += 3;
+    }
+    false
+}
+
+// crc of the psm, mpeg-2 polynomial, not reflected
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn put_timestamp(buff: &mut BytesMut, prefix: u8, ts: u64) {
+    buff.put_u8(prefix << 4 | ((ts >> 29) & 0x0E) as u8 | 0x01);
+    buff.put_u8((ts >> 22) as u8);
+    buff.put_u8(((ts >> 14) & 0xFE) as u8 | 0x01);
+    buff.put_u8((ts >> 7) as u8);
+    buff.put_u8(((ts << 1) & 0xFE) as u8 | 0x01);
+}
+
+// writes elementary stream frames as gb28181 ps, one pack per frame, system
+// header and psm in front of every keyframe, timestamps are 90 khz
+pub struct PsMuxer {
+    video: Option<Codec>,
+    audio: Option<Codec>,
+    audio_frames: u64,
+}
+
+impl PsMuxer {
+    pub fn new(video: Option<Codec>, audio: Option<Codec>) -> Self {
+        PsMuxer {
+            video,
+            audio,
+            audio_frames: 0,
+        }
+    }
+
+    pub fn mux_video(&mut self, es: &[u8], pts: u64, dts: u64) -> Frame {
+        let keyframe = self.video.is_some_and(|codec| is_keyframe(codec, es));
+        self.mux(VIDEO_STREAM_ID, es, pts, dts, keyframe)
+    }
+
+    pub fn mux_audio(&mut self, es: &[u8], pts: u64) -> Frame {
+        let keyframe =
+            self.video.is_none() && self.audio_frames.is_multiple_of(AUDIO_HEADERS_INTERVAL);
+        self.audio_frames += 1;
+        self.mux(AUDIO_STREAM_ID, es, pts, pts, keyframe)
+    }
+
+    fn mux(&self, stream_id: u8, es: &[u8], pts: u64, dts: u64, keyframe: bool) -> Frame {
+        let (pts, dts) = (pts & 0x1_FFFF_FFFF, dts & 0x1_FFFF_FFFF);
+        let packets = es.len().div_ceil(MAX_PES_PAYLOAD).max(1);
+        let mut buff = BytesMut::with_capacity(es.len() + 64 + packets * 19);
+
+        self.put_pack_header(&mut buff, dts);
+        if keyframe {
+            self.put_system_header(&mut buff);
+            self.put_psm(&mut buff);
+        }
+        let mut chunks = es.chunks(MAX_PES_PAYLOAD);
+        let first = chunks.next().unwrap_or_default();
+        Self::put_pes(&mut buff, stream_id, first, Some((pts, dts)));
+        for chunk in chunks {
+            Self::put_pes(&mut buff, stream_id, chunk, None);
+        }
+
+        Frame {
+            timestamp: pts as u32,
+            payloads: vec![buff.freeze()],
+            keyframe,
+        }
+    }
+
+    fn put_pack_header(&self, buff: &mut BytesMut, scr: u64) {
+        buff.put_slice(&[0, 0, 1, ps::PACK_HEADER]);
+        // scr base, no extension
+        buff.put_u8(0x44 | ((scr >> 27) & 0x38) as u8 | ((scr >> 28) & 0x03) as u8);
+        buff.put_u8((scr >> 20) as u8);
+        buff.put_u8(((scr >> 12) & 0xF8) as u8 | 0x04 | ((scr >> 13) & 0x03) as u8);
+        buff.put_u8((scr >> 5) as u8);
+        buff.put_u8(((scr << 3) & 0xF8) as u8 | 0x04);
+        buff.put_u8(0x01);
+        buff.put_u8((MUX_RATE >> 14) as u8);
+        buff.put_u8((MUX_RATE >> 6) as u8);
+        buff.put_u8(((MUX_RATE << 2) & 0xFC) as u8 | 0x03);
+        // no stuffing
+        buff.put_u8(0xF8);
+    }
+
+    fn streams(&self) -> impl Iterator<Item = (Codec, u8)> {
+        let video = self.video.map(|codec| (codec, VIDEO_STREAM_ID));
+        let audio = self.audio.map(|codec| (codec, AUDIO_STREAM_ID));
+        video.into_iter().chain(audio)
+    }
+
+    fn put_system_header(&self, buff: &mut BytesMut) {
+        let streams = self.streams().count();
+        buff.put_slice(&[0, 0, 1, ps::SYSTEM_HEADER]);
+        buff.put_u16((6 + 3 * streams) as u16);
+        buff.put_u8(0x80 | (MUX_RATE >> 15) as u8);
+        buff.put_u8((MUX_RATE >> 7) as u8);
+        buff.put_u8(((MUX_RATE << 1) & 0xFE) as u8 | 0x01);
+        // audio bound, video bound, no packet rate restriction
+        buff.put_u8((self.audio.is_some() as u8) << 2);
+        buff.put_u8(0xE0 | self.video.is_some() as u8);
+        buff.put_u8(0x7F);
+        for (codec, stream_id) in self.streams() {
+            buff.put_u8(stream_id);
+            if codec.is_video() {
+                // 400 kb, in units of 1024 bytes
+                buff.put_u16(0xE000 | 400);
+            } else {
+                // 4 kb, in units of 128 bytes
+                buff.put_u16(0xC000 | 32);
+            }
+        }
+    }
+
+    fn put_psm(&self, buff: &mut BytesMut) {
+        let streams = self.streams().count();
+        let start = buff.len();
+        buff.put_slice(&[0, 0, 1, ps::PROGRAM_STREAM_MAP]);
+        buff.put_u16((10 + 4 * streams) as u16);
+        // current, version 0
+        buff.put_u8(0xE0);
+        buff.put_u8(0xFF);
+        // no program stream info
+        buff.put_u16(0);
+        buff.put_u16((4 * streams) as u16);
+        for (codec, stream_id) in self.streams() {
+            buff.put_u8(codec.stream_type());
+            buff.put_u8(stream_id);
+            buff.put_u16(0);
+        }
+        let crc = crc32(&buff[start..]);
+        buff.put_u32(crc);
+    }
+
+    // pts and dts on the first packet of a frame only
+    fn put_pes(buff: &mut BytesMut, stream_id: u8, payload: &[u8], timestamps: Option<(u64, u64)>) {
+        buff.put_slice(&[0, 0, 1, stream_id]);
+        match timestamps {
+            None => {
+                buff.put_u16((3 + payload.len()) as u16);
+                buff.put_slice(&[0x80, 0x00, 0x00]);
+            }
+            Some((pts, dts)) if pts == dts => {
+                buff.put_u16((8 + payload.len()) as u16);
+                // data aligned, pts only
+                buff.put_slice(&[0x84, 0x80, 0x05]);
+                put_timestamp(buff, 0x2, pts);
+            }
+            Some((pts, dts)) => {
+                buff.put_u16((13 + payload.len()) as u16);
+                buff.put_slice(&[0x84, 0xC0, 0x0A]);
+                put_timestamp(buff, 0x3, pts);
+                put_timestamp(buff, 0x1, dts);
+            }
+        }
+        buff.put_slice(payload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // first packs of a udp session dump, a keyframe then video and audio
+    const CAPTURED: &[u8] = include_bytes!("testdata/udp.output.ps");
+
+    fn demux(frames: &[Frame]) -> (Vec<ps::EsFrame>, (&'static str, &'static str)) {
+        let mut demuxer = ps::Demuxer::default();
+        let mut es = vec![];
+        for frame in frames {
+            es.extend(demuxer.push(&frame.to_vec()));
+        }
+        es.extend(demuxer.flush());
+        (es, demuxer.codecs())
+    }
+
+    fn assert_es(es: &ps::EsFrame, stream_id: u8, pts: u64, dts: u64, data: &[u8]) {
+        assert_eq!(es.stream_id, stream_id);
+        assert_eq!((es.pts, es.dts), (pts, dts));
+        assert_eq!(es.data, data);
+    }
+
+    #[test]
+    fn h264_and_g711a_round_trip() {
+        let idr = [&[0, 0, 0, 1, 0x67, 0x42][..], &[0, 0, 0, 1, 0x65, 1, 2, 3]].concat();
+        let slice = [0, 0, 0, 1, 0x41, 4, 5, 6];
+        let audio = [0xD5u8; 160];
+
+        let mut muxer = PsMuxer::new(Some(Codec::H264), Some(Codec::G711A));
+        let frames = vec![
+            muxer.mux_video(&idr, 3600, 0),
+            muxer.mux_audio(&audio, 1800),
+            muxer.mux_video(&slice, 7200, 3600),
+        ];
+        assert_eq!(
+            frames.iter().map(|f| f.keyframe).collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+        assert!(ps::is_keyframe(&frames[0].payloads[0]));
+        assert!(!ps::is_keyframe(&frames[2].payloads[0]));
+
+        let (es, codecs) = demux(&frames);
+        assert_eq!(codecs, ("h264", "g711a"));
+        assert_eq!(es.len(), 3);
+        assert_es(&es[0], VIDEO_STREAM_ID, 3600, 0, &idr);
+        assert_es(&es[1], AUDIO_STREAM_ID, 1800, 1800, &audio);
+        assert_es(&es[2], VIDEO_STREAM_ID, 7200, 3600, &slice);
+    }
+
+    #[test]
+    fn h265_keyframes_carry_the_headers() {
+        // vps, then an idr_w_radl slice, and a trailing picture
+        let irap = [0, 0, 0, 1, 0x40, 0x01, 0, 0, 0, 1, 0x26, 0x01, 9];
+        let trail = [0, 0, 0, 1, 0x02, 0x01, 8];
+
+        let mut muxer = PsMuxer::new(Some(Codec::H265), None);
+        let frames = vec![
+            muxer.mux_video(&irap, 0, 0),
+            muxer.mux_video(&trail, 3600, 3600),
+        ];
+        assert!(frames[0].keyframe && !frames[1].keyframe);
+        let headers = ps::headers(&frames[0].payloads[0]).unwrap();
+        assert_eq!(ps::codecs(&headers), ("h265", ""));
+
+        let (es, _) = demux(&frames);
+        assert_es(&es[0], VIDEO_STREAM_ID, 0, 0, &irap);
+        assert_es(&es[1], VIDEO_STREAM_ID, 3600, 3600, &trail);
+    }
+
+    #[test]
+    fn audio_only_repeats_the_headers() {
+        let mut muxer = PsMuxer::new(None, Some(Codec::G711U));
+        let frames: Vec<Frame> = (0..AUDIO_HEADERS_INTERVAL + 1)
+            .map(|i| muxer.mux_audio(&[i as u8; 160], i * 1800))
+            .collect();
+        let keyframes: Vec<u64> = (0..frames.len() as u64)
+            .filter(|i| frames[*i as usize].keyframe)
+            .collect();
+        assert_eq!(keyframes, vec![0, AUDIO_HEADERS_INTERVAL]);
+
+        let (es, codecs) = demux(&frames);
+        assert_eq!(codecs, ("", "g711u"));
+        assert_eq!(es.len(), frames.len());
+        assert_es(&es[7], AUDIO_STREAM_ID, 7 * 1800, 7 * 1800, &[7; 160]);
+    }
+
+    #[test]
+    fn large_frames_span_several_pes_packets() {
+        let mut idr = vec![0, 0, 0, 1, 0x65];
+        idr.extend((0..3 * MAX_PES_PAYLOAD).map(|i| (i % 251) as u8 | 1));
+        let mut muxer = PsMuxer::new(Some(Codec::H264), None);
+        let frame = muxer.mux_video(&idr, 0x1_2345_6789, 0x1_2345_6789);
+        assert_eq!(ps::pes_packets(&frame.payloads[0]).len(), 4);
+
+        let (es, _) = demux(&[frame]);
+        assert_eq!(es.len(), 1);
+        assert_es(&es[0], VIDEO_STREAM_ID, 0x1_2345_6789, 0x1_2345_6789, &idr);
+    }
+
+    #[test]
+    fn captured_dump_round_trip() {
+        let captured = Frame {
+            timestamp: 0,
+            payloads: vec![bytes::Bytes::from_static(CAPTURED)],
+            keyframe: false,
+        };
+        let (frames, codecs) = demux(&[captured]);
+        assert_eq!(codecs, ("h264", "g711a"));
+        assert!(frames.iter().any(|f| ps::is_video(f.stream_id)));
+        assert!(frames.iter().any(|f| ps::is_audio(f.stream_id)));
+
+        let mut muxer = PsMuxer::new(Codec::from_name(codecs.0), Codec::from_name(codecs.1));
+        let remuxed: Vec<Frame> = frames
+            .iter()
+            .map(|f| {
+                if ps::is_video(f.stream_id) {
+                    muxer.mux_video(&f.data, f.pts, f.dts)
+                } else {
+                    muxer.mux_audio(&f.data, f.pts)
+                }
+            })
+            .collect();
+        assert!(remuxed[0].keyframe);
+
+        let (again, _) = demux(&remuxed);
+        assert_eq!(again.len(), frames.len());
+        for (a, b) in frames.iter().zip(&again) {
+            let stream_id = if ps::is_video(a.stream_id) {
+                VIDEO_STREAM_ID
+            } else {
+                AUDIO_STREAM_ID
+            };
+            assert_es(b, stream_id, a.pts, a.dts, &a.data);
+        }
+    }
+}
@@ -1,14 +1,59 @@
 use std::collections::BTreeMap;
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{IoSlice, Write};
 
+use bytes::Bytes;
 use rtp;
 
+// payloads of one timestamp in sequence order, sharing memory with the
+// received packets, cheap to clone for every output
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub timestamp: u32,
+    pub payloads: Vec<Bytes>,
+    // set by the hub
+    pub keyframe: bool,
+}
+
+impl Frame {
+    pub fn len(&self) -> usize {
+        self.payloads.iter().map(|p| p.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payloads.iter().all(|p| p.is_empty())
+    }
+
+    // contiguous copy, only for consumers that need one
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buff = Vec::with_capacity(self.len());
+        for payload in &self.payloads {
+            buff.extend_from_slice(payload);
+        }
+        buff
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut slices: Vec<IoSlice> = self.payloads.iter().map(|p| IoSlice::new(p)).collect();
+        let mut slices = slices.as_mut_slice();
+        while !slices.is_empty() {
+            match writer.write_vectored(slices) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => IoSlice::advance_slices(&mut slices, n),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct RtpPacketReOrder {
     min_timestamp: u32,
     limit_frames: usize,
     packet_groups: BTreeMap<u32, BTreeMap<u16, rtp::packet::Packet>>, // timestamp -> {sequence_number -> packet}
     output_file: Option<std::fs::File>,
+    dropped: u64,
 }
 
 impl RtpPacketReOrder {
@@ -30,6 +75,7 @@ impl RtpPacketReOrder {
             limit_frames,
             packet_groups: BTreeMap::new(),
             output_file: file,
+            dropped: 0,
         }
     }
 
@@ -40,6 +86,7 @@ impl RtpPacketReOrder {
         // drop
         if timestamp < self.min_timestamp {
             tracing::warn!("expired packet, {} < {}", timestamp, self.min_timestamp);
+            self.dropped += 1;
             return false;
         }
 
@@ -52,20 +99,37 @@ impl RtpPacketReOrder {
         self.packet_groups.len() > self.limit_frames
     }
 
-    pub fn pop_frame(&mut self) -> (u32, Vec<u8>) {
-        // pop minimum tree, merge its packet payload into a frame
-        let mut ts = 0;
-        let mut frame = Vec::new();
-        if let Some((key, timestamp_tree)) = self.packet_groups.pop_first() {
-            ts = key;
-            for (_sn, packet) in timestamp_tree {
-                frame.extend_from_slice(&packet.payload);
+    // write out every buffered frame, on session end
+    pub fn flush(&mut self) {
+        while !self.packet_groups.is_empty() {
+            self.pop_frame();
+        }
+        if let Some(ref mut file) = self.output_file {
+            if let Err(e) = file.flush() {
+                tracing::error!("std::io::flush error, e: {:?}", e);
             }
+        }
+    }
+
+    // expired packets dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn pop_frame(&mut self) -> Frame {
+        // pop minimum tree, its packet payloads make a frame without copying
+        let mut frame = Frame::default();
+        if let Some((key, timestamp_tree)) = self.packet_groups.pop_first() {
+            frame.timestamp = key;
+            frame.payloads = timestamp_tree
+                .into_values()
+                .map(|packet| packet.payload)
+                .collect();
 
             // write frame to file
             if let Some(ref mut file) = self.output_file {
-                if let Err(e) = file.write_all(&frame) {
-                    tracing::error!("std::io::write_all error, e: {:?}", e);
+                if let Err(e) = frame.write_to(file) {
+                    tracing::error!("Frame::write_to error, e: {:?}", e);
                 }
             }
         }
@@ -75,6 +139,85 @@ impl RtpPacketReOrder {
             self.min_timestamp = *self.packet_groups.keys().min().unwrap_or(&0);
         }
 
-        (ts, frame)
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence_number: u16, timestamp: u32, payload: &'static [u8]) -> rtp::packet::Packet {
+        rtp::packet::Packet {
+            header: rtp::header::Header {
+                sequence_number,
+                timestamp,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(payload),
+        }
+    }
+
+    #[test]
+    fn orders_packets_by_timestamp_then_sequence() {
+        let mut reorder = RtpPacketReOrder::new(2, "");
+        assert!(!reorder.feed_rtp(packet(3, 200, b"c")));
+        assert!(!reorder.feed_rtp(packet(2, 100, b"b")));
+        assert!(!reorder.feed_rtp(packet(1, 100, b"a")));
+        // a duplicate keeps the first copy
+        assert!(!reorder.feed_rtp(packet(1, 100, b"x")));
+        // a third frame is over the limit
+        assert!(reorder.feed_rtp(packet(4, 300, b"d")));
+
+        let frame = reorder.pop_frame();
+        assert_eq!(frame.timestamp, 100);
+        assert_eq!(frame.to_vec(), b"ab");
+        assert_eq!(frame.len(), 2);
+        assert_eq!(reorder.pop_frame().to_vec(), b"c");
+    }
+
+    #[test]
+    fn drops_packets_older_than_the_popped_frames() {
+        let mut reorder = RtpPacketReOrder::new(1, "");
+        reorder.feed_rtp(packet(1, 100, b"a"));
+        reorder.feed_rtp(packet(2, 200, b"b"));
+        reorder.pop_frame();
+
+        assert!(!reorder.feed_rtp(packet(0, 50, b"late")));
+        assert_eq!(reorder.dropped(), 1);
+        assert_eq!(reorder.pop_frame().timestamp, 200);
+        assert!(reorder.pop_frame().is_empty());
+    }
+
+    #[test]
+    fn writes_frames_to_the_output_file() {
+        let path = std::env::temp_dir().join(format!("reorder.{}.ps", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut reorder = RtpPacketReOrder::new(3, path.to_str().unwrap());
+        reorder.feed_rtp(packet(2, 100, b"world"));
+        reorder.feed_rtp(packet(1, 100, b"hello "));
+        reorder.feed_rtp(packet(3, 200, b"!"));
+        reorder.flush();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world!");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frame_writes_all_payloads() {
+        let frame = Frame {
+            timestamp: 0,
+            payloads: vec![
+                Bytes::from_static(b"ab"),
+                Bytes::new(),
+                Bytes::from_static(b"cd"),
+            ],
+            keyframe: false,
+        };
+        let mut out = Vec::new();
+        frame.write_to(&mut out).unwrap();
+        assert_eq!(out, b"abcd");
+        assert!(!frame.is_empty());
+        assert!(Frame::default().is_empty());
     }
 }
@@ -0,0 +1,423 @@
+// minimal rtcp (rfc 3550) support: sender report and bye parsing,
+// receiver statistics and receiver report generation
+pub const PT_SR: u8 = 200;
+pub const PT_RR: u8 = 201;
+pub const PT_SDES: u8 = 202;
+pub const PT_BYE: u8 = 203;
+pub const PT_APP: u8 = 204;
+
+const SDES_CNAME: u8 = 1;
+
+pub struct SenderReport {
+    pub ssrc: u32,
+    pub ntp_timestamp: u64,
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+}
+
+pub struct Bye {
+    pub ssrcs: Vec<u32>,
+    pub reason: String,
+}
+
+pub enum RtcpPacket {
+    SenderReport(SenderReport),
+    Bye(Bye),
+    Other(u8),
+}
+
+// rfc 5761 section 4: rtcp packet types 192..=223 never clash with
+// dynamic rtp payload types
+pub fn is_rtcp(buff: &[u8]) -> bool {
+    buff.len() >= 4 && buff[0] >> 6 == 2 && (192..=223).contains(&buff[1])
+}
+
+fn read_u32(buff: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buff[offset],
+        buff[offset + 1],
+        buff[offset + 2],
+        buff[offset + 3],
+    ])
+}
+
+// split a compound packet, malformed tails are dropped
+pub fn parse(buff: &[u8]) -> Result<Vec<RtcpPacket>, String> {
+    let mut packets = vec![];
+    let mut offset = 0;
+    while offset + 4 <= buff.len() {
+        let header = &buff[offset..];
+        if header[0] >> 6 != 2 {
+            return Err(format!("bad rtcp version at offset {}", offset));
+        }
+        let count = (header[0] & 0x1f) as usize;
+        let packet_type = header[1];
+        let length = (u16::from_be_bytes([header[2], header[3]]) as usize + 1) * 4;
+        if offset + length > buff.len() {
+            return Err(format!(
+                "truncated rtcp packet, type: {}, length: {}, remain: {}",
+                packet_type,
+                length,
+                buff.len() - offset
+            ));
+        }
+        let body = &buff[offset..offset + length];
+
+        match packet_type {
+            PT_SR if length >= 28 => {
+                packets.push(RtcpPacket::SenderReport(SenderReport {
+                    ssrc: read_u32(body, 4),
+                    ntp_timestamp: ((read_u32(body, 8) as u64) << 32) | read_u32(body, 12) as u64,
+                    rtp_timestamp: read_u32(body, 16),
+                    packet_count: read_u32(body, 20),
+                    octet_count: read_u32(body, 24),
+                }));
+            }
+            PT_BYE => {
+                let mut ssrcs = vec![];
+                for i in 0..count {
+                    if 4 + i * 4 + 4 <= length {
+                        ssrcs.push(read_u32(body, 4 + i * 4));
+                    }
+                }
+                let reason_offset = 4 + count * 4;
+                let reason = if reason_offset < length {
+                    let n = body[reason_offset] as usize;
+                    let end = (reason_offset + 1 + n).min(length);
+                    String::from_utf8_lossy(&body[reason_offset + 1..end]).to_string()
+                } else {
+                    String::new()
+                };
+                packets.push(RtcpPacket::Bye(Bye { ssrcs, reason }));
+            }
+            _ => packets.push(RtcpPacket::Other(packet_type)),
+        }
+
+        offset += length;
+    }
+    Ok(packets)
+}
+
+// rfc 3550 appendix a.1 and a.8
+#[derive(Default)]
+pub struct ReceiverStatistics {
+    initialized: bool,
+    base_seq: u16,
+    max_seq: u16,
+    cycles: u32,
+    received: u64,
+    expected_prior: u64,
+    received_prior: u64,
+    transit: i64,
+    jitter: f64,
+}
+
+impl ReceiverStatistics {
+    pub fn update(&mut self, sequence_number: u16, rtp_timestamp: u32, arrival: u32) {
+        if !self.initialized {
+            self.initialized = true;
+            self.base_seq = sequence_number;
+            self.max_seq = sequence_number;
+        } else {
+            let delta = sequence_number.wrapping_sub(self.max_seq);
+            if delta != 0 && delta < 0x8000 {
+                if sequence_number < self.max_seq {
+                    self.cycles += 1 << 16;
+                }
+                self.max_seq = sequence_number;
+            }
+        }
+        self.received += 1;
+
+        let transit = arrival.wrapping_sub(rtp_timestamp) as i32 as i64;
+        if self.received > 1 {
+            let d = (transit - self.transit).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.transit = transit;
+    }
+
+    pub fn extended_max_seq(&self) -> u32 {
+        self.cycles + self.max_seq as u32
+    }
+
+    pub fn expected(&self) -> u64 {
+        if !self.initialized {
+            return 0;
+        }
+        self.extended_max_seq() as u64 - self.base_seq as u64 + 1
+    }
+
+    pub fn lost(&self) -> i64 {
+        self.expected() as i64 - self.received as i64
+    }
+
+    pub fn jitter(&self) -> u32 {
+        self.jitter as u32
+    }
+
+    pub fn has_data(&self) -> bool {
+        self.initialized
+    }
+
+    // fraction lost since the previous call
+    pub fn take_fraction_lost(&mut self) -> u8 {
+        let expected = self.expected();
+        let expected_interval = expected - self.expected_prior;
+        let received_interval = self.received - self.received_prior;
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64) as u8
+        }
+    }
+}
+
+pub struct ReportBlock {
+    pub ssrc: u32,
+    pub fraction_lost: u8,
+    pub total_lost: i64,
+    pub last_sequence_number: u32,
+    pub jitter: u32,
+    pub last_sender_report: u32,
+    pub delay: u32,
+}
+
+// compound packet: rr + sdes cname
+pub fn make_receiver_report(ssrc: u32, block: &ReportBlock, cname: &str) -> Vec<u8> {
+    let mut buff = Vec::with_capacity(64);
+
+    // rr
+    buff.extend_from_slice(&[0x80 | 1, PT_RR, 0, 7]);
+    buff.extend_from_slice(&ssrc.to_be_bytes());
+    buff.extend_from_slice(&block.ssrc.to_be_bytes());
+    let lost = block.total_lost.clamp(-0x80_0000, 0x7f_ffff) as i32 as u32 & 0x00ff_ffff;
+    buff.extend_from_slice(&(((block.fraction_lost as u32) << 24) | lost).to_be_bytes());
+    buff.extend_from_slice(&block.last_sequence_number.to_be_bytes());
+    buff.extend_from_slice(&block.jitter.to_be_bytes());
+    buff.extend_from_slice(&block.last_sender_report.to_be_bytes());
+    buff.extend_from_slice(&block.delay.to_be_bytes());
+
+    // sdes
+    let cname = &cname.as_bytes()[..cname.len().min(255)];
+    let chunk_len = 4 + 2 + cname.len() + 1;
+    let padded_len = chunk_len.div_ceil(4) * 4;
+    buff.extend_from_slice(&[0x80 | 1, PT_SDES]);
+    buff.extend_from_slice(&((padded_len / 4) as u16).to_be_bytes());
+    buff.extend_from_slice(&ssrc.to_be_bytes());
+    buff.extend_from_slice(&[SDES_CNAME, cname.len() as u8]);
+    buff.extend_from_slice(cname);
+    buff.resize(buff.len() + padded_len - chunk_len + 1, 0);
+
+    buff
+}
+
+// middle 32 bits of a 64 bits ntp timestamp
+pub fn ntp_middle(ntp_timestamp: u64) -> u32 {
+    (ntp_timestamp >> 16) as u32
+}
+
+pub fn ntp_to_unix_millis(ntp_timestamp: u64) -> i64 {
+    const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+    let seconds = (ntp_timestamp >> 32) as i64 - NTP_UNIX_OFFSET;
+    let fraction = ((ntp_timestamp & 0xffff_ffff) * 1000) >> 32;
+    seconds * 1000 + fraction as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender_report(ssrc: u32) -> Vec<u8> {
+        let mut buff = vec![0x80, PT_SR, 0, 6];
+        buff.extend_from_slice(&ssrc.to_be_bytes());
+        buff.extend_from_slice(&0xe000_0000_8000_0000u64.to_be_bytes());
+        buff.extend_from_slice(&90000u32.to_be_bytes());
+        buff.extend_from_slice(&10u32.to_be_bytes());
+        buff.extend_from_slice(&12000u32.to_be_bytes());
+        buff
+    }
+
+    fn bye(ssrcs: &[u32], reason: &str) -> Vec<u8> {
+        let mut buff = vec![0x80 | ssrcs.len() as u8, PT_BYE, 0, 0];
+        for ssrc in ssrcs {
+            buff.extend_from_slice(&ssrc.to_be_bytes());
+        }
+        if !reason.is_empty() {
+            buff.push(reason.len() as u8);
+            buff.extend_from_slice(reason.as_bytes());
+        }
+        buff.resize(buff.len().div_ceil(4) * 4, 0);
+        buff[3] = (buff.len() / 4 - 1) as u8;
+        buff
+    }
+
+    #[test]
+    fn parses_a_compound_sender_report_and_bye() {
+        let mut buff = sender_report(0x1234_5678);
+        buff.extend_from_slice(&bye(&[0x1234_5678, 7], "teardown"));
+        let packets = parse(&buff).unwrap();
+        assert_eq!(packets.len(), 2);
+        match &packets[0] {
+            RtcpPacket::SenderReport(sr) => {
+                assert_eq!(sr.ssrc, 0x1234_5678);
+                assert_eq!(sr.ntp_timestamp, 0xe000_0000_8000_0000);
+                assert_eq!(sr.rtp_timestamp, 90000);
+                assert_eq!(sr.packet_count, 10);
+                assert_eq!(sr.octet_count, 12000);
+            }
+            _ => panic!("not a sender report"),
+        }
+        match &packets[1] {
+            RtcpPacket::Bye(bye) => {
+                assert_eq!(bye.ssrcs, vec![0x1234_5678, 7]);
+                assert_eq!(bye.reason, "teardown");
+            }
+            _ => panic!("not a bye"),
+        }
+    }
+
+    #[test]
+    fn parses_a_bye_without_reason() {
+        match &parse(&bye(&[42], "")).unwrap()[..] {
+            [RtcpPacket::Bye(bye)] => {
+                assert_eq!(bye.ssrcs, vec![42]);
+                assert!(bye.reason.is_empty());
+            }
+            _ => panic!("not a single bye"),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_version_and_truncation() {
+        let mut buff = sender_report(1);
+        buff[0] = 0x40;
+        assert!(parse(&buff).is_err());
+
+        let buff = sender_report(1);
+        assert!(parse(&buff[..buff.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn tells_rtcp_from_rtp() {
+        assert!(is_rtcp(&sender_report(1)));
+        assert!(is_rtcp(&bye(&[1], "")));
+        // ps over rtp, payload type 96, with and without the marker
+        assert!(!is_rtcp(&[0x80, 96, 0, 1]));
+        assert!(!is_rtcp(&[0x80, 0x80 | 96, 0, 1]));
+        assert!(!is_rtcp(&[0x80, PT_SR]));
+    }
+
+    #[test]
+    fn builds_a_receiver_report_with_cname() {
+        let block = ReportBlock {
+            ssrc: 0x0102_0304,
+            fraction_lost: 64,
+            total_lost: -3,
+            last_sequence_number: 0x0001_0005,
+            jitter: 100,
+            last_sender_report: 0xabcd_ef01,
+            delay: 65536,
+        };
+        let buff = make_receiver_report(0x0a0b_0c0d, &block, "10.0.0.1");
+        assert_eq!(buff.len() % 4, 0);
+
+        let packets = parse(&buff).unwrap();
+        assert!(matches!(
+            packets[..],
+            [RtcpPacket::Other(PT_RR), RtcpPacket::Other(PT_SDES)]
+        ));
+
+        // rr: header, sender ssrc, one report block
+        assert_eq!(&buff[..4], &[0x81, PT_RR, 0, 7]);
+        assert_eq!(read_u32(&buff, 4), 0x0a0b_0c0d);
+        assert_eq!(read_u32(&buff, 8), 0x0102_0304);
+        // fraction lost, then the 24 bits signed cumulative loss
+        assert_eq!(read_u32(&buff, 12), 0x40ff_fffd);
+        assert_eq!(read_u32(&buff, 16), 0x0001_0005);
+        assert_eq!(read_u32(&buff, 20), 100);
+        assert_eq!(read_u32(&buff, 24), 0xabcd_ef01);
+        assert_eq!(read_u32(&buff, 28), 65536);
+
+        // sdes: one chunk, cname null terminated and padded
+        let sdes = &buff[32..];
+        assert_eq!(&sdes[..2], &[0x81, PT_SDES]);
+        assert_eq!(
+            (u16::from_be_bytes([sdes[2], sdes[3]]) as usize + 1) * 4,
+            sdes.len()
+        );
+        assert_eq!(read_u32(sdes, 4), 0x0a0b_0c0d);
+        assert_eq!(&sdes[8..10], &[SDES_CNAME, 8]);
+        assert_eq!(&sdes[10..18], b"10.0.0.1");
+        assert!(sdes[18..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn receiver_report_clamps_the_cumulative_loss() {
+        let block = ReportBlock {
+            ssrc: 1,
+            fraction_lost: 0,
+            total_lost: 1 << 30,
+            last_sequence_number: 0,
+            jitter: 0,
+            last_sender_report: 0,
+            delay: 0,
+        };
+        let buff = make_receiver_report(2, &block, "");
+        assert_eq!(read_u32(&buff, 12), 0x007f_ffff);
+    }
+
+    #[test]
+    fn statistics_count_loss_across_wrap() {
+        let mut statistics = ReceiverStatistics::default();
+        assert!(!statistics.has_data());
+        for seq in [65533u16, 65534, 65535, 1, 2] {
+            statistics.update(seq, 0, 0);
+        }
+        // 0 is missing
+        assert_eq!(statistics.extended_max_seq(), 0x0001_0002);
+        assert_eq!(statistics.expected(), 6);
+        assert_eq!(statistics.lost(), 1);
+        // 1 of 6 in 8 bits fixed point
+        assert_eq!(statistics.take_fraction_lost(), (256 / 6) as u8);
+        // nothing new since the previous call
+        assert_eq!(statistics.take_fraction_lost(), 0);
+    }
+
+    #[test]
+    fn statistics_ignore_late_packets_for_max_seq() {
+        let mut statistics = ReceiverStatistics::default();
+        for seq in [10u16, 12, 11] {
+            statistics.update(seq, 0, 0);
+        }
+        assert_eq!(statistics.extended_max_seq(), 12);
+        assert_eq!(statistics.lost(), 0);
+    }
+
+    #[test]
+    fn statistics_estimate_jitter() {
+        let mut statistics = ReceiverStatistics::default();
+        // every packet 160 units later than its timestamp says
+        for i in 0..4u32 {
+            statistics.update(i as u16, i * 3600, i * 3760);
+        }
+        assert!(statistics.jitter() > 0);
+        let mut steady = ReceiverStatistics::default();
+        for i in 0..4u32 {
+            steady.update(i as u16, i * 3600, 1000 + i * 3600);
+        }
+        assert_eq!(steady.jitter(), 0);
+    }
+
+    #[test]
+    fn converts_ntp_timestamps() {
+        let ntp = (2_208_988_800u64 + 1_700_000_000) << 32 | 0x8000_0000;
+        assert_eq!(ntp_to_unix_millis(ntp), 1_700_000_000_500);
+        assert_eq!(ntp_middle(0x1122_3344_5566_7788), 0x3344_5566);
+    }
+}
@@ -0,0 +1,327 @@
+use std::io;
+use std::net::SocketAddr;
+
+use bytes::{Bytes, BytesMut};
+use socket2::{Domain, SockRef, Socket, Type};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocketOptions {
+    // kernel SO_RCVBUF/SO_SNDBUF bytes, 0 keeps the system default
+    pub recv_buffer_size: usize,
+    pub send_buffer_size: usize,
+    // udp only, listeners always reuse like tokio's TcpListener::bind
+    pub reuse_addr: bool,
+    // IPV6_V6ONLY on ipv6 sockets, off takes ipv4 as mapped addresses too
+    pub only_v6: bool,
+}
+
+fn socket(addr: &SocketAddr, ty: Type, options: &SocketOptions) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(*addr), ty, None)?;
+    socket.set_nonblocking(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(options.only_v6)?;
+    }
+    // capped by net.core.rmem_max/wmem_max, see the effective values
+    if options.recv_buffer_size > 0 {
+        socket.set_recv_buffer_size(options.recv_buffer_size)?;
+    }
+    if options.send_buffer_size > 0 {
+        socket.set_send_buffer_size(options.send_buffer_size)?;
+    }
+    Ok(socket)
+}
+
+pub fn bind_udp(addr: &SocketAddr, options: &SocketOptions) -> io::Result<tokio::net::UdpSocket> {
+    let socket = socket(addr, Type::DGRAM, options)?;
+    if options.reuse_addr {
+        socket.set_reuse_address(true)?;
+    }
+    enable_drop_counter(&socket);
+    socket.bind(&(*addr).into())?;
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+pub fn bind_tcp(addr: &SocketAddr, options: &SocketOptions) -> io::Result<tokio::net::TcpListener> {
+    let socket = socket(addr, Type::STREAM, options)?;
+    // rebinding a freed port must not wait for TIME_WAIT
+    socket.set_reuse_address(true)?;
+    socket.bind(&(*addr).into())?;
+    socket.listen(1024)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+// effective kernel (SO_RCVBUF, SO_SNDBUF), linux reports twice the requested size
+pub fn buffer_sizes<S: std::os::fd::AsFd>(socket: &S) -> (usize, usize) {
+    let socket = SockRef::from(socket);
+    (
+        socket.recv_buffer_size().unwrap_or(0),
+        socket.send_buffer_size().unwrap_or(0),
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn enable_drop_counter(socket: &Socket) {
+    use std::os::fd::AsRawFd;
+
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RXQ_OVFL,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of_val(&on) as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        tracing::warn!(
+            "setsockopt(SO_RXQ_OVFL) error, e: {:?}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_drop_counter(_socket: &Socket) {}
+
+#[cfg(target_os = "linux")]
+unsafe fn drop_counter(msg: &libc::msghdr) -> Option<u32> {
+    let mut drops = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SO_RXQ_OVFL {
+            drops = Some(std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const u32));
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    drops
+}
+
+// room for the u32 drop counter cmsg, u64 for cmsghdr alignment
+#[cfg(target_os = "linux")]
+type Control = [u64; 8];
+
+// slab room per datagram, enough for rtp over an ethernet mtu, the part of
+// a larger datagram beyond it goes to the overflow buffer of its slot
+const SLOT_SIZE: usize = 1500;
+
+// datagrams of one wakeup, received in place into a slab the caller provides
+// and handed out as Bytes of it, only datagrams larger than a slot are copied
+pub struct RecvBatch {
+    batch_size: usize,
+    slot_size: usize,
+    // max_datagram_size - slot_size bytes per slot, allocated once and reused
+    overflows: Vec<Vec<u8>>,
+    packets: Vec<(SocketAddr, Bytes)>,
+    drops: Option<u32>,
+    #[cfg(target_os = "linux")]
+    names: Vec<libc::sockaddr_storage>,
+    #[cfg(target_os = "linux")]
+    controls: Vec<Control>,
+}
+
+impl RecvBatch {
+    pub fn new(batch_size: usize, max_datagram_size: usize) -> Self {
+        // a single recv_from into a full size slot elsewhere
+        let (batch_size, slot_size) = if cfg!(target_os = "linux") {
+            (batch_size.max(1), max_datagram_size.min(SLOT_SIZE))
+        } else {
+            (1, max_datagram_size)
+        };
+        RecvBatch {
+            batch_size,
+            slot_size,
+            overflows: vec![vec![0; max_datagram_size - slot_size]; batch_size],
+            packets: Vec::with_capacity(batch_size),
+            drops: None,
+            #[cfg(target_os = "linux")]
+            names: vec![unsafe { std::mem::zeroed() }; batch_size],
+            #[cfg(target_os = "linux")]
+            controls: vec![[0; 8]; batch_size],
+        }
+    }
+
+    // room recv_batch needs in the slab
+    pub fn slab_size(&self) -> usize {
+        self.batch_size * self.slot_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (SocketAddr, Bytes)> + '_ {
+        self.packets.drain(..)
+    }
+
+    // latest SO_RXQ_OVFL counter seen in the batch
+    pub fn kernel_drops(&self) -> Option<u32> {
+        self.drops
+    }
+
+    // datagram i of len bytes, its slot is at the front of the slab
+    fn packet(&self, slab: &mut BytesMut, index: usize, len: usize) -> Bytes {
+        if len <= self.slot_size {
+            return slab.split_to(len).freeze();
+        }
+        let mut packet = BytesMut::with_capacity(len);
+        packet.extend_from_slice(&slab.split_to(self.slot_size));
+        packet.extend_from_slice(&self.overflows[index][..len - self.slot_size]);
+        packet.freeze()
+    }
+}
+
+// reads up to the batch size datagrams per wakeup with recvmmsg into the slab,
+// which needs slab_size() of capacity, the packets are kept in the batch until
+// drained, what they leave of the slab is its spare capacity again
+#[cfg(target_os = "linux")]
+pub async fn recv_batch(
+    socket: &tokio::net::UdpSocket,
+    batch: &mut RecvBatch,
+    slab: &mut BytesMut,
+) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    let fd = socket.as_raw_fd();
+    socket
+        .async_io(tokio::io::Interest::READABLE, || recvmmsg(fd, batch, slab))
+        .await
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn recv_batch(
+    socket: &tokio::net::UdpSocket,
+    batch: &mut RecvBatch,
+    slab: &mut BytesMut,
+) -> io::Result<usize> {
+    batch.packets.clear();
+    slab.clear();
+    slab.resize(batch.slot_size, 0);
+    let result = socket.recv_from(&mut slab[..]).await;
+    if let Ok((amount, addr)) = result {
+        let packet = batch.packet(slab, 0, amount);
+        batch.packets.push((addr, packet));
+    }
+    slab.clear();
+    result.map(|_| 1)
+}
+
+#[cfg(target_os = "linux")]
+fn recvmmsg(
+    fd: std::os::fd::RawFd,
+    batch: &mut RecvBatch,
+    slab: &mut BytesMut,
+) -> io::Result<usize> {
+    batch.packets.clear();
+    slab.clear();
+    slab.resize(batch.slab_size(), 0);
+
+    let mut iovs: Vec<[libc::iovec; 2]> = slab
+        .chunks_mut(batch.slot_size)
+        .zip(batch.overflows.iter_mut())
+        .map(|(slot, overflow)| {
+            [
+                libc::iovec {
+                    iov_base: slot.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: slot.len(),
+                },
+                libc::iovec {
+                    iov_base: overflow.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: overflow.len(),
+                },
+            ]
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovs
+        .iter_mut()
+        .zip(batch.names.iter_mut())
+        .zip(batch.controls.iter_mut())
+        .map(|((iov, name), control)| {
+            let mut msg: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            msg.msg_hdr.msg_name = name as *mut libc::sockaddr_storage as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as _;
+            msg.msg_hdr.msg_iov = iov.as_mut_ptr();
+            msg.msg_hdr.msg_iovlen = if iov[1].iov_len == 0 { 1 } else { 2 };
+            msg.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_hdr.msg_controllen = std::mem::size_of::<Control>() as _;
+            msg
+        })
+        .collect();
+
+    let n = unsafe {
+        libc::recvmmsg(
+            fd,
+            msgs.as_mut_ptr(),
+            msgs.len() as _,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    if n < 0 {
+        slab.clear();
+        return Err(io::Error::last_os_error());
+    }
+
+    // slots are split off in order, the unused room between datagrams is dropped
+    let mut offset = 0;
+    for (index, msg) in msgs.iter().enumerate().take(n as usize) {
+        if let Some(drops) = unsafe { drop_counter(&msg.msg_hdr) } {
+            batch.drops = Some(drops);
+        }
+        if msg.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+            tracing::warn!(
+                "datagram larger than max_datagram_size: {}, dropped",
+                msg.msg_len
+            );
+            continue;
+        }
+        let addr = unsafe { socket2::SockAddr::new(batch.names[index], msg.msg_hdr.msg_namelen) };
+        if let Some(addr) = addr.as_socket() {
+            let start = index * batch.slot_size;
+            drop(slab.split_to(start - offset));
+            let len = msg.msg_len as usize;
+            let packet = batch.packet(slab, index, len);
+            offset = start + len.min(batch.slot_size);
+            batch.packets.push((addr, packet));
+        }
+    }
+    slab.clear();
+    Ok(batch.packets.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn receives_a_batch_into_the_slab() {
+        let local: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let socket = bind_udp(&local, &SocketOptions::default()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let sender = std::net::UdpSocket::bind(local).unwrap();
+        sender.send_to(b"first", addr).unwrap();
+        sender.send_to(b"second", addr).unwrap();
+        // larger than a slot, partly in the overflow buffer
+        let jumbo: Vec<u8> = (0..4000u32).map(|i| i as u8).collect();
+        sender.send_to(&jumbo, addr).unwrap();
+
+        let mut batch = RecvBatch::new(8, 65535);
+        let mut slab = BytesMut::with_capacity(batch.slab_size());
+        let mut packets = Vec::new();
+        while packets.len() < 3 {
+            recv_batch(&socket, &mut batch, &mut slab).await.unwrap();
+            packets.extend(batch.drain());
+        }
+        assert!(batch.is_empty());
+
+        let from = sender.local_addr().unwrap();
+        assert!(packets.iter().all(|(addr, _)| *addr == from));
+        assert_eq!(&packets[0].1[..], b"first");
+        assert_eq!(&packets[1].1[..], b"second");
+        assert_eq!(&packets[2].1[..], &jumbo[..]);
+    }
+}
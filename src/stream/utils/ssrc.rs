@@ -0,0 +1,79 @@
+// GB28181 ssrc: 10 decimal digits
+//   [0]    0 realtime, 1 playback
+//   [1..6] domain, the 4th to 8th digits of the sip domain
+//   [6..]  sequence
+pub const SSRC_LEN: usize = 10;
+pub const SSRC_DOMAIN_LEN: usize = 5;
+pub const SSRC_SEQUENCE_MAX: u32 = 10000;
+
+pub fn make(playback: bool, domain: &str, sequence: u32) -> String {
+    format!(
+        "{}{}{:04}",
+        if playback { 1 } else { 0 },
+        domain,
+        sequence % SSRC_SEQUENCE_MAX
+    )
+}
+
+pub fn parse(ssrc: &str) -> Option<u32> {
+    if ssrc.len() != SSRC_LEN || !ssrc.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    ssrc.parse::<u32>().ok()
+}
+
+pub fn domain(ssrc_domain: &str, gb_code: &str) -> String {
+    let is_domain = |s: &str| s.len() == SSRC_DOMAIN_LEN && s.bytes().all(|b| b.is_ascii_digit());
+    if is_domain(ssrc_domain) {
+        return ssrc_domain.to_string();
+    }
+
+    match gb_code.get(3..3 + SSRC_DOMAIN_LEN) {
+        Some(d) if is_domain(d) => d.to_string(),
+        _ => "0".repeat(SSRC_DOMAIN_LEN),
+    }
+}
+
+pub fn to_string(ssrc: u32) -> String {
+    format!("{:010}", ssrc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_pads_and_wraps_the_sequence() {
+        assert_eq!(make(false, "20000", 7), "0200000007");
+        assert_eq!(make(true, "20000", 12345), "1200002345");
+    }
+
+    #[test]
+    fn parse_takes_ten_digits_only() {
+        assert_eq!(parse("0200000007"), Some(200000007));
+        assert_eq!(parse("4294967295"), Some(u32::MAX));
+        // ten digits, but not a u32
+        assert_eq!(parse("4294967296"), None);
+        assert_eq!(parse("020000007"), None);
+        assert_eq!(parse("02000000070"), None);
+        assert_eq!(parse("+200000007"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn domain_prefers_the_configured_one() {
+        assert_eq!(domain("12345", "34020000001320000001"), "12345");
+        // the 4th to 8th digits of the gb code otherwise
+        assert_eq!(domain("", "34020000001320000001"), "20000");
+        assert_eq!(domain("1234", "34020000001320000001"), "20000");
+        assert_eq!(domain("", "dev4"), "00000");
+        assert_eq!(domain("", "340ab000"), "00000");
+    }
+
+    #[test]
+    fn to_string_round_trips() {
+        for ssrc in ["0000000001", "1200000001", "4294967295"] {
+            assert_eq!(to_string(parse(ssrc).unwrap()), ssrc);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+use crate::gss;
+
+#[derive(Default)]
+pub struct StreamStats {
+    pub rtp_packets: AtomicU64,
+    pub rtp_bytes: AtomicU64,
+    pub ssrc_mismatch_packets: AtomicU64,
+    pub rtcp_packets: AtomicU64,
+    pub packets_lost: AtomicI64,
+    pub jitter: AtomicU32,
+    pub sr_ntp_timestamp: AtomicU64,
+    pub sr_rtp_timestamp: AtomicU32,
+    pub rejected_packets: AtomicU64,
+    pub reorder_drops: AtomicU64,
+    // SO_RXQ_OVFL, dropped by a full kernel receive buffer
+    pub kernel_drops: AtomicU64,
+    pub frames: AtomicU64,
+    pub fps: AtomicU32,
+    fps_window_start_ms: AtomicU64,
+    fps_window_frames: AtomicU32,
+}
+
+impl StreamStats {
+    pub fn on_rtp(&self, bytes: usize) {
+        self.rtp_packets.fetch_add(1, Ordering::Relaxed);
+        self.rtp_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    // the kernel reports a cumulative u32 per socket
+    pub fn on_kernel_drops(&self, drops: u32) {
+        let previous = self.kernel_drops.swap(drops as u64, Ordering::Relaxed);
+        if drops as u64 > previous {
+            tracing::warn!(
+                "kernel receive buffer overflow, dropped: {}",
+                drops as u64 - previous
+            );
+        }
+    }
+
+    // fps is measured over windows of at least one second
+    pub fn on_frame(&self, now_ms: u64) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        let frames = self.fps_window_frames.fetch_add(1, Ordering::Relaxed) + 1;
+        let start_ms = self.fps_window_start_ms.load(Ordering::Relaxed);
+        if now_ms >= start_ms + 1000 {
+            self.fps.store(
+                (frames as u64 * 1000 / (now_ms - start_ms)) as u32,
+                Ordering::Relaxed,
+            );
+            self.fps_window_start_ms.store(now_ms, Ordering::Relaxed);
+            self.fps_window_frames.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> gss::StreamStats {
+        gss::StreamStats {
+            rtp_packets: self.rtp_packets.load(Ordering::Relaxed),
+            rtp_bytes: self.rtp_bytes.load(Ordering::Relaxed),
+            ssrc_mismatch_packets: self.ssrc_mismatch_packets.load(Ordering::Relaxed),
+            rtcp_packets: self.rtcp_packets.load(Ordering::Relaxed),
+            packets_lost: self.packets_lost.load(Ordering::Relaxed),
+            jitter: self.jitter.load(Ordering::Relaxed),
+            sr_ntp_timestamp: self.sr_ntp_timestamp.load(Ordering::Relaxed),
+            sr_rtp_timestamp: self.sr_rtp_timestamp.load(Ordering::Relaxed),
+            rejected_packets: self.rejected_packets.load(Ordering::Relaxed),
+            reorder_drops: self.reorder_drops.load(Ordering::Relaxed),
+            kernel_drops: self.kernel_drops.load(Ordering::Relaxed),
+            frames: self.frames.load(Ordering::Relaxed),
+            fps: self.fps.load(Ordering::Relaxed),
+        }
+    }
+}
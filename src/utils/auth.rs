@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const HEADER_AUTHORIZATION: &str = "authorization";
+pub const HEADER_KEY_ID: &str = "x-msprs-key-id";
+pub const HEADER_TIMESTAMP: &str = "x-msprs-timestamp";
+pub const HEADER_SIGNATURE: &str = "x-msprs-signature";
+
+// control api authentication, disabled when no token and no hmac key is set
+//   bearer: "authorization: Bearer <token>"
+//   hmac:   x-msprs-key-id, x-msprs-timestamp (unix seconds) and
+//           x-msprs-signature = hex(hmac_sha256(secret, "<key_id>:<timestamp>:<request>"))
+//           request is the grpc method path ("/gss.GbtStreamService/ListStreams"), or
+//           "<http method> <path and query> <hex(sha256(body))>" for the http api
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub tokens: Vec<String>,
+    pub hmac_keys: HashMap<String, String>,
+    // seconds a signed timestamp stays valid
+    pub hmac_max_skew: i64,
+    // tls on the grpc port when cert and key are set, mtls when client_ca is set too
+    pub tls_cert: String,
+    pub tls_key: String,
+    pub tls_client_ca: String,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            tokens: vec![],
+            hmac_keys: HashMap::new(),
+            hmac_max_skew: 300,
+            tls_cert: String::new(),
+            tls_key: String::new(),
+            tls_client_ca: String::new(),
+        }
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.hmac_keys.is_empty()
+    }
+
+    pub fn sign(secret: &str, key_id: &str, timestamp: i64, request: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}:{}:{}", key_id, timestamp, request).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    // the signed request of an http api call
+    pub fn http_request(method: &str, path_and_query: &str, body: &[u8]) -> String {
+        format!(
+            "{} {} {}",
+            method,
+            path_and_query,
+            hex::encode(Sha256::digest(body))
+        )
+    }
+
+    // header lookup is a closure so grpc metadata and http headers share it,
+    // request is what the signature has to cover
+    pub fn check<'a>(
+        &self,
+        header: impl Fn(&str) -> Option<&'a str>,
+        request: &str,
+    ) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        if let Some(authorization) = header(HEADER_AUTHORIZATION) {
+            let token = authorization
+                .strip_prefix("Bearer ")
+                .ok_or("authorization is not a bearer token")?;
+            if self
+                .tokens
+                .iter()
+                .any(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            {
+                return Ok(());
+            }
+            return Err("invalid bearer token".to_string());
+        }
+
+        if let Some(key_id) = header(HEADER_KEY_ID) {
+            let secret = self
+                .hmac_keys
+                .get(key_id)
+                .ok_or_else(|| format!("unknown key id: {}", key_id))?;
+            let timestamp = header(HEADER_TIMESTAMP)
+                .and_then(|t| t.parse::<i64>().ok())
+                .ok_or("missing or invalid timestamp")?;
+            // the timestamp is the client's, any i64 must not overflow
+            let skew = chrono::Utc::now()
+                .timestamp()
+                .checked_sub(timestamp)
+                .map(i64::unsigned_abs);
+            match skew {
+                Some(skew) if skew <= self.hmac_max_skew.max(0) as u64 => {}
+                Some(skew) => return Err(format!("timestamp skew too large: {}s", skew)),
+                None => return Err("timestamp skew too large".to_string()),
+            }
+            let signature = header(HEADER_SIGNATURE).ok_or("missing signature")?;
+            let expected = Self::sign(secret, key_id, timestamp, request);
+            if constant_time_eq(expected.as_bytes(), signature.to_lowercase().as_bytes()) {
+                return Ok(());
+            }
+            return Err(format!("invalid signature, key id: {}", key_id));
+        }
+
+        Err("missing credentials".to_string())
+    }
+
+    pub fn tls_enabled(&self) -> bool {
+        !self.tls_cert.is_empty() && !self.tls_key.is_empty()
+    }
+
+    pub fn server_tls_config(
+        &self,
+    ) -> Result<Option<tonic::transport::ServerTlsConfig>, Box<dyn std::error::Error>> {
+        if !self.tls_enabled() {
+            return Ok(None);
+        }
+        let cert = std::fs::read_to_string(&self.tls_cert)?;
+        let key = std::fs::read_to_string(&self.tls_key)?;
+        let mut tls_config = tonic::transport::ServerTlsConfig::new()
+            .identity(tonic::transport::Identity::from_pem(cert, key));
+        if !self.tls_client_ca.is_empty() {
+            let client_ca = std::fs::read_to_string(&self.tls_client_ca)?;
+            tls_config =
+                tls_config.client_ca_root(tonic::transport::Certificate::from_pem(client_ca));
+        }
+        Ok(Some(tls_config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST_STREAMS: &str = "/gss.GbtStreamService/ListStreams";
+
+    fn auth() -> AuthConfig {
+        AuthConfig {
+            tokens: vec!["t0ken".to_string()],
+            hmac_keys: HashMap::from([("k1".to_string(), "s3cret".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn check(auth: &AuthConfig, headers: &[(&str, String)], request: &str) -> Result<(), String> {
+        auth.check(
+            |key| {
+                headers
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.as_str())
+            },
+            request,
+        )
+    }
+
+    fn signed(
+        key_id: &str,
+        secret: &str,
+        timestamp: i64,
+        request: &str,
+    ) -> Vec<(&'static str, String)> {
+        vec![
+            (HEADER_KEY_ID, key_id.to_string()),
+            (HEADER_TIMESTAMP, timestamp.to_string()),
+            (
+                HEADER_SIGNATURE,
+                AuthConfig::sign(secret, key_id, timestamp, request),
+            ),
+        ]
+    }
+
+    #[test]
+    fn disabled_without_tokens_and_keys() {
+        let auth = AuthConfig::default();
+        assert!(!auth.is_enabled());
+        assert!(check(&auth, &[], LIST_STREAMS).is_ok());
+    }
+
+    #[test]
+    fn bearer_token() {
+        let auth = auth();
+        let bearer = |v: &str| vec![(HEADER_AUTHORIZATION, v.to_string())];
+        assert!(check(&auth, &bearer("Bearer t0ken"), LIST_STREAMS).is_ok());
+        assert!(check(&auth, &bearer("Bearer t0ke"), LIST_STREAMS).is_err());
+        assert!(check(&auth, &bearer("Basic t0ken"), LIST_STREAMS).is_err());
+        assert!(check(&auth, &[], LIST_STREAMS).is_err());
+    }
+
+    #[test]
+    fn hmac_within_the_skew_window() {
+        let auth = auth();
+        let now = chrono::Utc::now().timestamp();
+        for timestamp in [now, now - 290, now + 290] {
+            let headers = signed("k1", "s3cret", timestamp, LIST_STREAMS);
+            assert!(
+                check(&auth, &headers, LIST_STREAMS).is_ok(),
+                "{}",
+                timestamp - now
+            );
+        }
+        for timestamp in [now - 310, now + 310] {
+            let headers = signed("k1", "s3cret", timestamp, LIST_STREAMS);
+            let e = check(&auth, &headers, LIST_STREAMS).unwrap_err();
+            assert!(e.contains("skew"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn hmac_rejects_extreme_timestamps() {
+        let auth = auth();
+        for timestamp in [i64::MIN, i64::MIN + 1, -1 << 62, i64::MAX] {
+            let headers = signed("k1", "s3cret", timestamp, LIST_STREAMS);
+            let e = check(&auth, &headers, LIST_STREAMS).unwrap_err();
+            assert!(e.contains("skew"), "{}: {}", timestamp, e);
+        }
+    }
+
+    #[test]
+    fn hmac_accepts_an_upper_case_signature() {
+        let auth = auth();
+        let mut headers = signed("k1", "s3cret", chrono::Utc::now().timestamp(), LIST_STREAMS);
+        headers[2].1 = headers[2].1.to_uppercase();
+        assert!(check(&auth, &headers, LIST_STREAMS).is_ok());
+    }
+
+    #[test]
+    fn hmac_rejects_wrong_key_secret_or_request() {
+        let auth = auth();
+        let now = chrono::Utc::now().timestamp();
+        let headers = signed("k2", "s3cret", now, LIST_STREAMS);
+        assert!(check(&auth, &headers, LIST_STREAMS).is_err());
+        let headers = signed("k1", "other", now, LIST_STREAMS);
+        assert!(check(&auth, &headers, LIST_STREAMS).is_err());
+        // not replayable against another method
+        let headers = signed("k1", "s3cret", now, LIST_STREAMS);
+        assert!(check(&auth, &headers, "/gss.GbtStreamService/FreeStreamPort").is_err());
+        // nor with another timestamp
+        let mut headers = signed("k1", "s3cret", now, LIST_STREAMS);
+        headers[1].1 = (now - 1).to_string();
+        assert!(check(&auth, &headers, LIST_STREAMS).is_err());
+    }
+
+    #[test]
+    fn hmac_needs_timestamp_and_signature() {
+        let auth = auth();
+        let headers = signed("k1", "s3cret", chrono::Utc::now().timestamp(), LIST_STREAMS);
+        assert!(check(&auth, &headers[..2], LIST_STREAMS).is_err());
+        assert!(check(
+            &auth,
+            &[headers[0].clone(), headers[2].clone()],
+            LIST_STREAMS
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn http_request_covers_method_path_and_body() {
+        let request = AuthConfig::http_request("POST", "/api/streams", b"{}");
+        assert_eq!(
+            request,
+            "POST /api/streams 44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        assert_ne!(
+            request,
+            AuthConfig::http_request("POST", "/api/streams", b"{ }")
+        );
+        assert_ne!(
+            request,
+            AuthConfig::http_request("PUT", "/api/streams", b"{}")
+        );
+        assert_ne!(
+            request,
+            AuthConfig::http_request("POST", "/api/send_rtp", b"{}")
+        );
+    }
+}
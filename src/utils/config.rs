@@ -1,22 +1,123 @@
-use local_ip_address::local_ip;
+use local_ip_address::{local_ip, local_ipv6};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
 
+use super::auth::AuthConfig;
+use super::log::LogConfig;
+use super::net::{self, MediaInterface};
+use super::otel::OtelConfig;
+use super::webhook::WebhookConfig;
+use crate::gss::AddressFamily;
+use crate::stream::hub::HubOptions;
+use crate::stream::utils::socket::SocketOptions;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default = "default_host")]
     pub host: String,
     #[serde(default = "default_my_ip")]
     pub my_ip: String,
+    // advertised to ipv6 binds, detected when empty and host is an ipv6 address
+    #[serde(default)]
+    pub my_ip6: String,
+    // IPV6_V6ONLY on stream sockets, with host "::" false also takes ipv4
+    #[serde(default)]
+    pub ipv6_only: bool,
+    // extra networks for media, binds outside them use host and my_ip
+    #[serde(default)]
+    pub media_interfaces: Vec<MediaInterface>,
     #[serde(default = "default_grpc_port")]
     pub grpc_port: u16,
+    // http api and /metrics, 0 disables
+    #[serde(default = "default_http_port")]
+    pub http_port: u16,
     #[serde(default = "default_stream_port_start")]
     pub stream_port_start: u16,
     #[serde(default = "default_stream_port_stop")]
     pub stream_port_stop: u16,
-    #[serde(default = "default_socket_recv_buffer_size")]
-    pub socket_recv_buffer_size: usize,
+    // even rtp ports only, rtp port + 1 is reserved for rtcp
+    #[serde(default = "default_stream_port_pairs")]
+    pub stream_port_pairs: bool,
+    // seconds before a freed port is reused
+    #[serde(default = "default_stream_port_cooldown")]
+    pub stream_port_cooldown: u64,
+    // seconds a port that failed to bind stays out of the pool
+    #[serde(default = "default_stream_port_quarantine")]
+    pub stream_port_quarantine: u64,
+    // next ports tried when a bind fails
+    #[serde(default = "default_stream_port_bind_retries")]
+    pub stream_port_bind_retries: u32,
+    // udp read buffer, the largest datagram accepted
+    #[serde(
+        default = "default_max_datagram_size",
+        alias = "socket_recv_buffer_size"
+    )]
+    pub max_datagram_size: usize,
+    // datagrams read per wakeup with recvmmsg on linux, each takes a
+    // max_datagram_size buffer per session
+    #[serde(default = "default_udp_recv_batch")]
+    pub udp_recv_batch: usize,
+    // kernel SO_RCVBUF/SO_SNDBUF of stream sockets, 0 keeps the system default
+    #[serde(default = "default_socket_rcvbuf")]
+    pub socket_rcvbuf: usize,
+    #[serde(default)]
+    pub socket_sndbuf: usize,
+    // SO_REUSEADDR on stream udp sockets
+    #[serde(default)]
+    pub socket_reuse_addr: bool,
+    #[serde(default = "default_ssrc_domain")]
+    pub ssrc_domain: String,
+    #[serde(default = "default_ssrc_check")]
+    pub ssrc_check: bool,
+    #[serde(default = "default_rtcp_on_next_port")]
+    pub rtcp_on_next_port: bool,
+    #[serde(default = "default_rtcp_interval")]
+    pub rtcp_interval: u64,
+    // media source allowlists (cidr), empty allows any source
+    #[serde(default)]
+    pub source_allowlist: Vec<ipnet::IpNet>,
+    #[serde(default)]
+    pub gb_code_source_allowlists: HashMap<String, Vec<ipnet::IpNet>>,
+    // frames queued per output before it skips to the next keyframe
+    #[serde(default = "default_hub_queue_frames")]
+    pub hub_queue_frames: usize,
+    // longest gop kept for new outputs to start on
+    #[serde(default = "default_hub_gop_frames")]
+    pub hub_gop_frames: usize,
+    // seconds, 0 disables
+    #[serde(default = "default_no_data_on_start_timeout")]
+    pub no_data_on_start_timeout: u32,
+    #[serde(default = "default_no_data_timeout")]
+    pub no_data_timeout: u32,
+    // seconds an on-demand session may go without readers, 0 disables
+    #[serde(default = "default_no_reader_timeout")]
+    pub no_reader_timeout: u32,
+    // free on-demand sessions without readers, not only report them
+    #[serde(default)]
+    pub no_reader_free: bool,
+    // talk audio files are read from here only
+    #[serde(default = "default_talk_file_dir")]
+    pub talk_file_dir: String,
+    // mp4 files of download sessions, served from /api/downloads
+    #[serde(default = "default_download_dir")]
+    pub download_dir: String,
+    // seconds between download_progress events, 0 disables
+    #[serde(default = "default_download_progress_interval")]
+    pub download_progress_interval: u64,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub otel: OtelConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    // seconds to drain sessions and deliver final events on SIGTERM/SIGINT
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
 }
 
 fn default_host() -> String {
@@ -31,6 +132,10 @@ fn default_grpc_port() -> u16 {
     7080
 }
 
+fn default_http_port() -> u16 {
+    7081
+}
+
 fn default_stream_port_start() -> u16 {
     10001
 }
@@ -39,20 +144,255 @@ fn default_stream_port_stop() -> u16 {
     20000
 }
 
-fn default_socket_recv_buffer_size() -> usize {
-    1500
+fn default_stream_port_pairs() -> bool {
+    false
+}
+
+fn default_stream_port_cooldown() -> u64 {
+    5
+}
+
+fn default_stream_port_quarantine() -> u64 {
+    60
+}
+
+fn default_stream_port_bind_retries() -> u32 {
+    8
+}
+
+fn default_max_datagram_size() -> usize {
+    65535
+}
+
+fn default_udp_recv_batch() -> usize {
+    16
+}
+
+fn default_socket_rcvbuf() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_ssrc_domain() -> String {
+    "".to_string()
+}
+
+fn default_ssrc_check() -> bool {
+    true
+}
+
+fn default_rtcp_on_next_port() -> bool {
+    false
+}
+
+fn default_rtcp_interval() -> u64 {
+    5
+}
+
+fn default_hub_queue_frames() -> usize {
+    100
+}
+
+fn default_hub_gop_frames() -> usize {
+    250
+}
+
+fn default_no_data_on_start_timeout() -> u32 {
+    30
+}
+
+fn default_no_reader_timeout() -> u32 {
+    30
+}
+
+fn default_no_data_timeout() -> u32 {
+    20
+}
+
+fn default_talk_file_dir() -> String {
+    "audio".to_string()
+}
+
+fn default_download_dir() -> String {
+    "downloads".to_string()
+}
+
+fn default_download_progress_interval() -> u64 {
+    5
+}
+
+fn default_shutdown_timeout() -> u64 {
+    10
+}
+
+// flattened "a.b" keys, values as json
+fn flatten(prefix: &str, value: &serde_json::Value, out: &mut HashMap<String, serde_json::Value>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+// secrets never show up in diffs and logs
+fn redact(key: &str, value: Option<&serde_json::Value>) -> String {
+    match value {
+        None => "null".to_string(),
+        Some(_) if key == "auth.tokens" || key.starts_with("auth.hmac_keys.") => {
+            "<redacted>".to_string()
+        }
+        Some(value) => value.to_string(),
+    }
 }
 
 impl Config {
+    // (key, old, new) of every changed value
+    pub fn diff(&self, other: &Config) -> Vec<(String, String, String)> {
+        let mut old = HashMap::new();
+        let mut new = HashMap::new();
+        if let Ok(value) = serde_json::to_value(self) {
+            flatten("", &value, &mut old);
+        }
+        if let Ok(value) = serde_json::to_value(other) {
+            flatten("", &value, &mut new);
+        }
+
+        let mut keys = old.keys().chain(new.keys()).collect::<Vec<&String>>();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter(|key| old.get(*key) != new.get(*key))
+            .map(|key| {
+                (
+                    key.clone(),
+                    redact(key, old.get(key)),
+                    redact(key, new.get(key)),
+                )
+            })
+            .collect()
+    }
+
+    // sockets, listeners, log outputs, exporters and queues built at startup,
+    // changing these needs a restart, the current values are kept and the keys returned
+    pub fn keep_restart_keys(&mut self, current: &Config) -> Vec<&'static str> {
+        let mut kept = vec![];
+        macro_rules! keep {
+            ($key:literal, $($field:ident).+) => {
+                if self.$($field).+ != current.$($field).+ {
+                    self.$($field).+ = current.$($field).+.clone();
+                    kept.push($key);
+                }
+            };
+        }
+        keep!("host", host);
+        keep!("grpc_port", grpc_port);
+        keep!("http_port", http_port);
+        keep!("stream_port_pairs", stream_port_pairs);
+        keep!("log.dir", log.dir);
+        keep!("log.timezone", log.timezone);
+        keep!("log.stdout_format", log.stdout_format);
+        keep!("log.file_format", log.file_format);
+        keep!("log.rotation", log.rotation);
+        keep!("log.max_size", log.max_size);
+        keep!("log.max_files", log.max_files);
+        keep!("otel.endpoint", otel.endpoint);
+        keep!("otel.service_name", otel.service_name);
+        keep!("otel.level", otel.level);
+        keep!("otel.timeout", otel.timeout);
+        keep!("otel.metrics_interval", otel.metrics_interval);
+        keep!("auth.tls_cert", auth.tls_cert);
+        keep!("auth.tls_key", auth.tls_key);
+        keep!("auth.tls_client_ca", auth.tls_client_ca);
+        keep!("webhook.queue_size", webhook.queue_size);
+        keep!("webhook.concurrency", webhook.concurrency);
+        kept
+    }
+
+    pub fn source_allowlists(&self, gb_code: &str) -> Vec<Vec<ipnet::IpNet>> {
+        let mut allowlists = vec![self.source_allowlist.clone()];
+        if let Some(allowlist) = self.gb_code_source_allowlists.get(gb_code) {
+            allowlists.push(allowlist.clone());
+        }
+        allowlists
+    }
+
+    pub fn hub_options(&self) -> HubOptions {
+        HubOptions {
+            queue_frames: self.hub_queue_frames,
+            gop_frames: self.hub_gop_frames,
+        }
+    }
+
+    pub fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            recv_buffer_size: self.socket_rcvbuf,
+            send_buffer_size: self.socket_sndbuf,
+            reuse_addr: self.socket_reuse_addr,
+            only_v6: self.ipv6_only,
+        }
+    }
+
+    // host and my_ip
+    pub fn default_interface(&self) -> MediaInterface {
+        MediaInterface {
+            name: net::DEFAULT_MEDIA_INTERFACE.to_string(),
+            bind: self.host.clone(),
+            ip: self.my_ip.clone(),
+            ip6: self.my_ip6.clone(),
+            ..Default::default()
+        }
+    }
+
+    // by name, else the first whose subnets hold the device ip, else the default,
+    // None for an unknown name
+    pub fn media_interface(&self, name: &str, device_ip: Option<IpAddr>) -> Option<MediaInterface> {
+        let found = if name.is_empty() {
+            device_ip.and_then(|ip| self.media_interfaces.iter().find(|i| i.contains(&ip)))
+        } else {
+            match self.media_interfaces.iter().find(|i| i.name == name) {
+                None if name != net::DEFAULT_MEDIA_INTERFACE => return None,
+                found => found,
+            }
+        };
+        let mut interface = match found {
+            None => return Some(self.default_interface()),
+            Some(interface) => interface.clone(),
+        };
+        if interface.bind.is_empty() {
+            interface.bind = self.host.clone();
+        }
+        Some(interface)
+    }
+
     // 从YAML文件加载配置
     pub fn load_from_file(path: &PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
         let yaml_content = fs::read_to_string(path)?;
         let mut config: Config = serde_yaml::from_str(&yaml_content)?;
-        if config.my_ip.is_empty() {
+        let default_interface = config.default_interface();
+        if config.my_ip.is_empty()
+            && default_interface.serves(AddressFamily::Ipv4, config.ipv6_only)
+        {
             if let Ok(ip) = local_ip() {
                 config.my_ip = ip.to_string();
             }
         }
+        if config.my_ip6.is_empty() && matches!(net::parse_ip(&config.host), Some(IpAddr::V6(_))) {
+            if let Ok(ip) = local_ipv6() {
+                config.my_ip6 = ip.to_string();
+            }
+        }
+        for interface in config.media_interfaces.iter_mut() {
+            interface.detect();
+        }
 
         Ok(config)
     }
@@ -64,3 +404,120 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_yaml::from_str("{}").unwrap()
+    }
+
+    #[test]
+    fn flatten_joins_nested_keys() {
+        let value = serde_json::json!({
+            "a": 1,
+            "b": {"c": "x", "d": {"e": [1, 2]}},
+            "f": {},
+        });
+        let mut out = HashMap::new();
+        flatten("", &value, &mut out);
+        assert_eq!(out.len(), 3);
+        assert_eq!(out["a"], 1);
+        assert_eq!(out["b.c"], "x");
+        // lists are one value
+        assert_eq!(out["b.d.e"], serde_json::json!([1, 2]));
+    }
+
+    #[test]
+    fn redact_hides_tokens_and_hmac_secrets() {
+        let secret = serde_json::json!("s3cret");
+        assert_eq!(redact("auth.tokens", Some(&secret)), "<redacted>");
+        assert_eq!(redact("auth.hmac_keys.k1", Some(&secret)), "<redacted>");
+        assert_eq!(
+            redact("auth.hmac_max_skew", Some(&serde_json::json!(300))),
+            "300"
+        );
+        assert_eq!(
+            redact("host", Some(&serde_json::json!("0.0.0.0"))),
+            "\"0.0.0.0\""
+        );
+        // a removed key shows as null, even a secret one
+        assert_eq!(redact("auth.hmac_keys.k1", None), "null");
+    }
+
+    #[test]
+    fn diff_lists_changed_keys_sorted() {
+        let old = config();
+        assert!(old.diff(&old.clone()).is_empty());
+
+        let mut new = old.clone();
+        new.rtcp_interval = 10;
+        new.webhook.retries = 5;
+        new.source_allowlist = vec!["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                (
+                    "rtcp_interval".to_string(),
+                    "5".to_string(),
+                    "10".to_string()
+                ),
+                (
+                    "source_allowlist".to_string(),
+                    "[]".to_string(),
+                    "[\"10.0.0.0/8\"]".to_string()
+                ),
+                (
+                    "webhook.retries".to_string(),
+                    "3".to_string(),
+                    "5".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_redacts_secrets() {
+        let old = config();
+        let mut new = old.clone();
+        new.auth.tokens = vec!["t0ken".to_string()];
+        new.auth
+            .hmac_keys
+            .insert("k1".to_string(), "s3cret".to_string());
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            vec![
+                (
+                    "auth.hmac_keys.k1".to_string(),
+                    "null".to_string(),
+                    "<redacted>".to_string()
+                ),
+                (
+                    "auth.tokens".to_string(),
+                    "<redacted>".to_string(),
+                    "<redacted>".to_string()
+                ),
+            ]
+        );
+        assert!(!format!("{:?}", diff).contains("s3cret"));
+    }
+
+    #[test]
+    fn keep_restart_keys_keeps_current_values() {
+        let current = config();
+        let mut new = current.clone();
+        new.grpc_port = 9000;
+        new.webhook.queue_size = 1;
+        new.rtcp_interval = 10;
+        assert_eq!(
+            new.keep_restart_keys(&current),
+            vec!["grpc_port", "webhook.queue_size"]
+        );
+        assert_eq!(new.grpc_port, current.grpc_port);
+        assert_eq!(new.webhook.queue_size, current.webhook.queue_size);
+        // the rest applies
+        assert_eq!(current.diff(&new).len(), 1);
+    }
+}
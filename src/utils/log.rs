@@ -1,48 +1,237 @@
 use super::super::version;
 use super::color;
 use super::config::Config;
+use super::rolling::SizeRollingFile;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, OnceLock};
 use std::{env, panic};
 use time::{macros::format_description, UtcOffset};
 use tracing::{self, error};
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_subscriber::{fmt, prelude::*};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    filter::EnvFilter, fmt, fmt::MakeWriter, prelude::*, registry::Registry, reload, Layer,
+};
 
-pub fn init(config: &Config) -> anyhow::Result<Vec<WorkerGuard>> {
-    let mut log_dir = env::current_dir().unwrap();
-    log_dir.push("logs");
-    let file_appender = tracing_appender::rolling::daily(
-        &log_dir,
-        format!("{app_name}.log", app_name = version::APP_NAME),
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Pretty,
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Daily,
+    Hourly,
+    Never,
+    Size,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    // relative to the working directory, empty disables the file log
+    pub dir: String,
+    // tracing filter directives, e.g. "info" or "info,msprs::stream=debug"
+    pub stdout_level: String,
+    pub file_level: String,
+    // utc offset like "+08:00", "utc" or "local"
+    pub timezone: String,
+    pub stdout_format: LogFormat,
+    pub file_format: LogFormat,
+    pub rotation: LogRotation,
+    // megabytes per file with size rotation
+    pub max_size: u64,
+    // rotated files kept, 0 keeps all
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            dir: "logs".to_string(),
+            stdout_level: "debug".to_string(),
+            file_level: "info".to_string(),
+            timezone: "+08:00".to_string(),
+            stdout_format: LogFormat::Pretty,
+            file_format: LogFormat::Text,
+            rotation: LogRotation::Daily,
+            max_size: 100,
+            max_files: 30,
+        }
+    }
+}
+
+pub const LOG_OUTPUT_STDOUT: &str = "stdout";
+pub const LOG_OUTPUT_FILE: &str = "file";
+
+struct LogLevels {
+    stdout: reload::Handle<EnvFilter, Registry>,
+    file: Option<reload::Handle<EnvFilter, Registry>>,
+    // current directives, (stdout, file)
+    current: Mutex<(String, String)>,
+}
+
+static LOG_LEVELS: OnceLock<LogLevels> = OnceLock::new();
+
+// changes the level of "stdout", "file" or both when output is empty, returns
+// the (stdout, file) directives in effect
+pub fn set_level(output: &str, directives: &str) -> Result<(String, String), String> {
+    let levels = LOG_LEVELS.get().ok_or("log is not initialized")?;
+    let (stdout, file) = match output {
+        "" => (true, true),
+        LOG_OUTPUT_STDOUT => (true, false),
+        LOG_OUTPUT_FILE => (false, true),
+        _ => return Err(format!("unknown log output: {}", output)),
+    };
+    let filter = |directives: &str| {
+        EnvFilter::try_new(directives)
+            .map_err(|e| format!("invalid level {}, e: {}", directives, e))
+    };
+    filter(directives)?;
+
+    let mut current = levels.current.lock().unwrap();
+    if stdout {
+        levels
+            .stdout
+            .reload(filter(directives)?)
+            .map_err(|e| e.to_string())?;
+        current.0 = directives.to_string();
+    }
+    if let (true, Some(handle)) = (file, &levels.file) {
+        handle
+            .reload(filter(directives)?)
+            .map_err(|e| e.to_string())?;
+        current.1 = directives.to_string();
+    }
+    tracing::warn!(
+        "log level changed, stdout: {}, file: {}",
+        &current.0,
+        &current.1
     );
-    let (filelog, file_log_guard) = tracing_appender::non_blocking(file_appender);
-    let (stdoutlog, std_out_guard) = tracing_appender::non_blocking(std::io::stdout());
-    let local_time = tracing_subscriber::fmt::time::OffsetTime::new(
-        UtcOffset::from_hms(8, 0, 0).unwrap(),
+    Ok(current.clone())
+}
+
+fn parse_offset(timezone: &str) -> anyhow::Result<UtcOffset> {
+    match timezone {
+        "" | "utc" | "UTC" => return Ok(UtcOffset::UTC),
+        "local" => {
+            return UtcOffset::current_local_offset()
+                .map_err(|e| anyhow::anyhow!("local timezone error, e: {}", e))
+        }
+        _ => {}
+    }
+    let (sign, hhmm) = match timezone.split_at(1) {
+        ("+", hhmm) => (1, hhmm),
+        ("-", hhmm) => (-1, hhmm),
+        _ => anyhow::bail!("invalid timezone: {}", timezone),
+    };
+    let (hours, minutes) = hhmm.split_once(':').unwrap_or((hhmm, "0"));
+    let hours: i8 = hours.parse()?;
+    let minutes: i8 = minutes.parse()?;
+    Ok(UtcOffset::from_hms(sign * hours, sign * minutes, 0)?)
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn fmt_layer<W>(format: LogFormat, writer: W, offset: UtcOffset, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let timer = fmt::time::OffsetTime::new(
+        offset,
         format_description!("[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:6]"),
     );
+    let layer = fmt::Layer::new()
+        .with_writer(writer)
+        .with_timer(timer)
+        .with_target(true)
+        .with_file(true)
+        .with_line_number(true)
+        .with_thread_ids(true);
+    match format {
+        LogFormat::Pretty => layer.with_ansi(ansi).pretty().boxed(),
+        LogFormat::Text => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer.with_ansi(false).json().boxed(),
+    }
+}
+
+fn file_writer(config: &LogConfig) -> anyhow::Result<Box<dyn std::io::Write + Send>> {
+    let mut log_dir = env::current_dir()?;
+    log_dir.push(&config.dir);
+    // the appender prunes old files before it creates the directory
+    std::fs::create_dir_all(&log_dir)?;
+    let file_name = format!("{app_name}.log", app_name = version::APP_NAME);
+
+    let rotation = match config.rotation {
+        LogRotation::Size => {
+            return Ok(Box::new(SizeRollingFile::new(
+                log_dir,
+                file_name,
+                config.max_size.max(1) * 1024 * 1024,
+                config.max_files,
+            )?));
+        }
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name);
+    if config.max_files > 0 {
+        builder = builder.max_log_files(config.max_files);
+    }
+    Ok(Box::new(builder.build(&log_dir)?))
+}
+
+pub fn init(config: &Config) -> anyhow::Result<Vec<WorkerGuard>> {
+    let log_config = &config.log;
+    let offset = parse_offset(&log_config.timezone)?;
+    let mut guards = vec![];
+    let mut layers: Vec<BoxedLayer> = vec![];
+
+    let (stdout_filter, stdout_handle) =
+        reload::Layer::new(EnvFilter::try_new(&log_config.stdout_level)?);
+    let (stdoutlog, std_out_guard) = tracing_appender::non_blocking(std::io::stdout());
+    guards.push(std_out_guard);
+    layers.push(
+        fmt_layer(log_config.stdout_format, stdoutlog, offset, true)
+            .with_filter(stdout_filter)
+            .boxed(),
+    );
 
-    let subscriber = tracing_subscriber::registry()
-        .with(
-            fmt::Layer::new()
-                .with_writer(stdoutlog.with_max_level(tracing::Level::DEBUG))
-                .with_timer(local_time.clone())
-                .with_ansi(true)
-                .with_target(true)
-                .with_file(true)
-                .with_line_number(true)
-                .with_thread_ids(true)
-                .pretty(),
-        )
-        .with(
-            fmt::Layer::new()
-                .with_writer(filelog.with_max_level(tracing::Level::INFO))
-                .with_timer(local_time.clone())
-                .with_ansi(false)
-                .with_target(true)
-                .with_file(true)
-                .with_line_number(true)
-                .with_thread_ids(true),
+    let mut file_handle = None;
+    if !log_config.dir.is_empty() {
+        let (file_filter, handle) = reload::Layer::new(EnvFilter::try_new(&log_config.file_level)?);
+        let (filelog, file_log_guard) = tracing_appender::non_blocking(file_writer(log_config)?);
+        guards.push(file_log_guard);
+        layers.push(
+            fmt_layer(log_config.file_format, filelog, offset, false)
+                .with_filter(file_filter)
+                .boxed(),
         );
+        file_handle = Some(handle);
+    }
+
+    // spans to the otlp collector
+    if let Some(layer) = super::otel::layer(&config.otel)? {
+        layers.push(layer);
+    }
+
+    let _ = LOG_LEVELS.set(LogLevels {
+        stdout: stdout_handle,
+        file: file_handle,
+        current: Mutex::new((
+            log_config.stdout_level.clone(),
+            log_config.file_level.clone(),
+        )),
+    });
+
+    let subscriber = tracing_subscriber::registry().with(layers);
     tracing::subscriber::set_global_default(subscriber).map_err(|e| anyhow::anyhow!(e))?;
     tracing::info!(
         "start services{}
@@ -55,22 +244,26 @@ pub fn init(config: &Config) -> anyhow::Result<Vec<WorkerGuard>> {
 ║                                                          ║
 ║ host: {:<50} ║
 ║ my_ip: {:<49} ║
+║ my_ip6: {:<48} ║
 ║ grpc_port: {:<45} ║
 ║ stream_port_start: {:<37} ║
 ║ stream_port_stop: {:<38} ║
-║ socket_recv_buffer_size: {:<31} ║
+║ max_datagram_size: {:<37} ║
+║ socket_rcvbuf: {:<41} ║
 ╚══════════════════════════════════════════════════════════╝{}",
         color::PURPLE,
         version::APP_VERSION,
         &config.host,
         &config.my_ip,
+        &config.my_ip6,
         &config.grpc_port,
         &config.stream_port_start,
         &config.stream_port_stop,
-        &config.socket_recv_buffer_size,
+        &config.max_datagram_size,
+        &config.socket_rcvbuf,
         color::RESET
     );
-    Ok(vec![file_log_guard, std_out_guard])
+    Ok(guards)
 }
 
 pub fn init_panic() {
@@ -0,0 +1,59 @@
+use std::sync::OnceLock;
+
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry};
+
+pub struct Metrics {
+    pub registry: Registry,
+    pub rpc_requests: IntCounterVec,
+    pub rpc_duration: HistogramVec,
+    pub reorder_drops: IntCounter,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| {
+        let registry = Registry::new_custom(Some("msprs".to_string()), None).unwrap();
+
+        let rpc_requests = IntCounterVec::new(
+            Opts::new(
+                "rpc_requests_total",
+                "gRPC calls by method and response code",
+            ),
+            &["method", "code"],
+        )
+        .unwrap();
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new("rpc_duration_seconds", "gRPC call latency").buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+            &["method"],
+        )
+        .unwrap();
+        let reorder_drops = IntCounter::new(
+            "reorder_drops_total",
+            "rtp packets dropped by the reorder buffer as expired",
+        )
+        .unwrap();
+
+        registry.register(Box::new(rpc_requests.clone())).unwrap();
+        registry.register(Box::new(rpc_duration.clone())).unwrap();
+        registry.register(Box::new(reorder_drops.clone())).unwrap();
+
+        Metrics {
+            registry,
+            rpc_requests,
+            rpc_duration,
+            reorder_drops,
+        }
+    })
+}
+
+impl Metrics {
+    pub fn observe_rpc(&self, method: &str, code: &str, started: std::time::Instant) {
+        self.rpc_requests.with_label_values(&[method, code]).inc();
+        self.rpc_duration
+            .with_label_values(&[method])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
@@ -1,3 +1,10 @@
+pub mod auth;
 pub mod color;
 pub mod config;
 pub mod log;
+pub mod metrics;
+pub mod net;
+pub mod otel;
+pub mod rolling;
+pub mod signal;
+pub mod webhook;
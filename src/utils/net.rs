@@ -0,0 +1,109 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+use local_ip_address::list_afinet_netifas;
+use serde::{Deserialize, Serialize};
+
+use crate::gss::AddressFamily;
+
+// "host:port" for lookup_host and bind, ipv6 literals in brackets
+pub fn host_port(host: &str, port: u16) -> String {
+    match parse_ip(host) {
+        Some(IpAddr::V6(ip)) => format!("[{ip}]:{port}"),
+        _ => format!("{host}:{port}"),
+    }
+}
+
+// ip literal, with or without brackets, None for host names
+pub fn parse_ip(host: &str) -> Option<IpAddr> {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+        .parse()
+        .ok()
+}
+
+// sockets bound to "::" also take ipv4 unless IPV6_V6ONLY is set
+pub fn is_dual_stack(host: &str, ipv6_only: bool) -> bool {
+    !ipv6_only && parse_ip(host) == Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+}
+
+pub const DEFAULT_MEDIA_INTERFACE: &str = "default";
+
+// a network media is served on, picked per bind by name or device subnet
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaInterface {
+    pub name: String,
+    // os network interface, its addresses fill ip and ip6 when empty
+    pub nic: String,
+    // stream sockets bind here, empty for Config.host
+    pub bind: String,
+    // advertised to devices
+    pub ip: String,
+    pub ip6: String,
+    // public address of a nat in front of ip, advertised instead, ports map 1:1
+    pub nat_ip: String,
+    // device subnets reached through this interface
+    pub subnets: Vec<ipnet::IpNet>,
+}
+
+impl MediaInterface {
+    // fill ip and ip6 from a specific bind address or the nic
+    pub fn detect(&mut self) {
+        let mut addrs: Vec<IpAddr> = parse_ip(&self.bind)
+            .filter(|ip| !ip.is_unspecified())
+            .into_iter()
+            .collect();
+        if !self.nic.is_empty() {
+            match list_afinet_netifas() {
+                Err(e) => tracing::error!("list_afinet_netifas error, e: {:?}", e),
+                Ok(netifas) => addrs.extend(
+                    netifas
+                        .into_iter()
+                        .filter(|(nic, _)| nic == &self.nic)
+                        .map(|(_, ip)| ip),
+                ),
+            }
+        }
+        for addr in addrs {
+            match addr {
+                IpAddr::V4(_) if self.ip.is_empty() => self.ip = addr.to_string(),
+                IpAddr::V6(ip) if self.ip6.is_empty() && !ip.is_unicast_link_local() => {
+                    self.ip6 = addr.to_string()
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn contains(&self, device_ip: &IpAddr) -> bool {
+        self.subnets.iter().any(|net| net.contains(device_ip))
+    }
+
+    // whether stream sockets on bind take the family, a host name may resolve to either
+    pub fn serves(&self, family: AddressFamily, ipv6_only: bool) -> bool {
+        match (parse_ip(&self.bind), family) {
+            (None, _) | (_, AddressFamily::Any) => true,
+            (Some(IpAddr::V4(_)), AddressFamily::Ipv4) => true,
+            (Some(IpAddr::V6(_)), AddressFamily::Ipv6) => true,
+            (Some(IpAddr::V6(_)), AddressFamily::Ipv4) => is_dual_stack(&self.bind, ipv6_only),
+            (Some(IpAddr::V4(_)), AddressFamily::Ipv6) => false,
+        }
+    }
+
+    // media_server_ip for a bind of the family, None when it is not served
+    pub fn media_server_ip(&self, family: AddressFamily, ipv6_only: bool) -> Option<String> {
+        if !self.serves(family, ipv6_only) {
+            return None;
+        }
+        let mut advertised = [&self.nat_ip, &self.ip, &self.ip6]
+            .into_iter()
+            .filter(|ip| !ip.is_empty());
+        let is_v6 = |ip: &&String| matches!(parse_ip(ip), Some(IpAddr::V6(_)));
+        match family {
+            AddressFamily::Any => Some(advertised.next().cloned().unwrap_or_default()),
+            AddressFamily::Ipv4 => advertised.find(|ip| !is_v6(ip)).cloned(),
+            AddressFamily::Ipv6 => advertised.find(is_v6).cloned(),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
+
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::{filter::EnvFilter, registry::Registry, Layer};
+
+use crate::rpc::server::MyGbtStreamService;
+use crate::version;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtelConfig {
+    // otlp grpc collector, e.g. http://127.0.0.1:4317, empty disables the export
+    pub endpoint: String,
+    // service.name resource attribute, empty uses the app name
+    pub service_name: String,
+    // tracing filter directives for exported spans
+    pub level: String,
+    // seconds per export
+    pub timeout: u64,
+    // seconds between metric exports
+    pub metrics_interval: u64,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        OtelConfig {
+            endpoint: String::new(),
+            service_name: String::new(),
+            level: "info".to_string(),
+            timeout: 10,
+            metrics_interval: 15,
+        }
+    }
+}
+
+struct Providers {
+    tracer: TracerProvider,
+    meter: SdkMeterProvider,
+}
+
+static PROVIDERS: OnceLock<Providers> = OnceLock::new();
+
+fn resource(config: &OtelConfig) -> Resource {
+    let service_name = if config.service_name.is_empty() {
+        version::APP_NAME.to_string()
+    } else {
+        config.service_name.clone()
+    };
+    Resource::new(vec![
+        KeyValue::new("service.name", service_name),
+        KeyValue::new("service.version", version::APP_VERSION),
+    ])
+}
+
+// span export layer, None when no endpoint is set. metrics are exported once
+// start_metrics registers the instruments
+pub fn layer(
+    config: &OtelConfig,
+) -> anyhow::Result<Option<Box<dyn Layer<Registry> + Send + Sync>>> {
+    if config.endpoint.is_empty() {
+        return Ok(None);
+    }
+    let timeout = Duration::from_secs(config.timeout);
+
+    let span_exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .with_timeout(timeout)
+        .build()?;
+    let tracer_provider = TracerProvider::builder()
+        .with_batch_exporter(span_exporter, runtime::Tokio)
+        .with_resource(resource(config))
+        .build();
+
+    let metric_exporter = opentelemetry_otlp::MetricExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .with_timeout(timeout)
+        .build()?;
+    let reader = PeriodicReader::builder(metric_exporter, runtime::Tokio)
+        .with_interval(Duration::from_secs(config.metrics_interval.max(1)))
+        .build();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource(config))
+        .build();
+
+    let tracer = tracer_provider.tracer(version::APP_NAME);
+    let _ = PROVIDERS.set(Providers {
+        tracer: tracer_provider,
+        meter: meter_provider,
+    });
+
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(EnvFilter::try_new(&config.level)?)
+            .boxed(),
+    ))
+}
+
+// pool and per-session gauges, read at export time like /metrics
+pub fn start_metrics(service: &Arc<MyGbtStreamService>) {
+    let providers = match PROVIDERS.get() {
+        None => return,
+        Some(p) => p,
+    };
+    let meter = providers.meter.meter(version::APP_NAME);
+
+    let weak = Arc::downgrade(service);
+    let sessions = move |f: &mut dyn FnMut(&MyGbtStreamService)| {
+        if let Some(service) = Weak::upgrade(&weak) {
+            f(&service);
+        }
+    };
+    let session_attributes = |service: &MyGbtStreamService| {
+        let mut sessions = vec![];
+        if let Ok(join_handlers) = service.join_handlers.lock() {
+            for (port, task) in join_handlers.iter() {
+                let info = &task.stream_handler.info;
+                let attributes = vec![
+                    KeyValue::new("gb_code", info.gb_code.clone()),
+                    KeyValue::new("stream_id", info.stream_id as i64),
+                    KeyValue::new("port", *port as i64),
+                ];
+                sessions.push((attributes, task.stream_handler.stats.snapshot()));
+            }
+        }
+        sessions
+    };
+
+    let ports = sessions.clone();
+    meter
+        .u64_observable_gauge("msprs.stream_ports")
+        .with_description("stream port pool by state")
+        .with_callback(move |observer| {
+            ports(&mut |service| {
+                let usage = service.port_pool_usage();
+                for (state, value) in [
+                    ("free", usage.free),
+                    ("used", usage.used),
+                    ("cooling", usage.cooling),
+                    ("quarantined", usage.quarantined),
+                ] {
+                    observer.observe(value as u64, &[KeyValue::new("state", state)]);
+                }
+            })
+        })
+        .build();
+
+    let active = sessions.clone();
+    meter
+        .u64_observable_gauge("msprs.active_sessions")
+        .with_description("active stream sessions")
+        .with_callback(move |observer| {
+            active(&mut |service| {
+                let n = service.join_handlers.lock().map(|j| j.len()).unwrap_or(0);
+                observer.observe(n as u64, &[]);
+            })
+        })
+        .build();
+
+    let packets = sessions.clone();
+    meter
+        .u64_observable_counter("msprs.session.rtp_packets")
+        .with_description("rtp packets received")
+        .with_callback(move |observer| {
+            packets(&mut |service| {
+                for (attributes, stats) in session_attributes(service) {
+                    observer.observe(stats.rtp_packets, &attributes);
+                }
+            })
+        })
+        .build();
+
+    let bytes = sessions.clone();
+    meter
+        .u64_observable_counter("msprs.session.rtp_bytes")
+        .with_description("rtp bytes received")
+        .with_unit("By")
+        .with_callback(move |observer| {
+            bytes(&mut |service| {
+                for (attributes, stats) in session_attributes(service) {
+                    observer.observe(stats.rtp_bytes, &attributes);
+                }
+            })
+        })
+        .build();
+
+    let lost = sessions.clone();
+    meter
+        .i64_observable_gauge("msprs.session.packets_lost")
+        .with_description("cumulative rtp packets lost")
+        .with_callback(move |observer| {
+            lost(&mut |service| {
+                for (attributes, stats) in session_attributes(service) {
+                    observer.observe(stats.packets_lost, &attributes);
+                }
+            })
+        })
+        .build();
+
+    let kernel_drops = sessions.clone();
+    meter
+        .u64_observable_counter("msprs.session.kernel_drops")
+        .with_description("rtp packets dropped by a full socket receive buffer")
+        .with_callback(move |observer| {
+            kernel_drops(&mut |service| {
+                for (attributes, stats) in session_attributes(service) {
+                    observer.observe(stats.kernel_drops, &attributes);
+                }
+            })
+        })
+        .build();
+
+    let fps = sessions;
+    meter
+        .u64_observable_gauge("msprs.session.fps")
+        .with_description("frames per second")
+        .with_callback(move |observer| {
+            fps(&mut |service| {
+                for (attributes, stats) in session_attributes(service) {
+                    observer.observe(stats.fps as u64, &attributes);
+                }
+            })
+        })
+        .build();
+}
+
+// flushes pending spans and metrics
+pub fn shutdown() {
+    if let Some(providers) = PROVIDERS.get() {
+        if let Err(e) = providers.tracer.shutdown() {
+            eprintln!("otel tracer shutdown error, e: {:?}", e);
+        }
+        if let Err(e) = providers.meter.shutdown() {
+            eprintln!("otel meter shutdown error, e: {:?}", e);
+        }
+    }
+}
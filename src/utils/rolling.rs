@@ -0,0 +1,94 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// appends to <dir>/<name>, which is renamed to <name>.<time> once it would
+// exceed max_size, keeping at most max_files of those (0 keeps all)
+pub struct SizeRollingFile {
+    dir: PathBuf,
+    name: String,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl SizeRollingFile {
+    pub fn new(dir: PathBuf, name: String, max_size: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let file = Self::open(&dir, &name)?;
+        let size = file.metadata()?.len();
+        Ok(SizeRollingFile {
+            dir,
+            name,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn open(dir: &Path, name: &str) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(name))
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rolled = format!(
+            "{}.{}",
+            &self.name,
+            chrono::Local::now().format("%Y-%m-%d-%H-%M-%S%.3f")
+        );
+        fs::rename(self.dir.join(&self.name), self.dir.join(rolled))?;
+        self.file = Self::open(&self.dir, &self.name)?;
+        self.size = 0;
+        self.prune();
+        Ok(())
+    }
+
+    fn prune(&self) {
+        if self.max_files == 0 {
+            return;
+        }
+        let prefix = format!("{}.", &self.name);
+        let mut rolled = match fs::read_dir(&self.dir) {
+            Err(e) => {
+                eprintln!("SizeRollingFile read_dir error, e: {:?}", e);
+                return;
+            }
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with(&prefix))
+                .collect::<Vec<String>>(),
+        };
+        // the time suffix sorts oldest first
+        rolled.sort();
+        let excess = rolled.len().saturating_sub(self.max_files);
+        for name in rolled.iter().take(excess) {
+            if let Err(e) = fs::remove_file(self.dir.join(name)) {
+                eprintln!("SizeRollingFile remove_file({}) error, e: {:?}", name, e);
+            }
+        }
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            if let Err(e) = self.roll() {
+                eprintln!("SizeRollingFile roll error, e: {:?}", e);
+            }
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,29 @@
+// resolves on SIGINT or SIGTERM
+pub async fn wait_for_shutdown() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("tokio::signal::ctrl_c error, e: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Err(e) => {
+                tracing::error!("tokio::signal::unix::signal error, e: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+            Ok(mut s) => {
+                s.recv().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::warn!("SIGINT received"),
+        _ = terminate => tracing::warn!("SIGTERM received"),
+    }
+}
@@ -0,0 +1,476 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+use crate::gss::{StreamEvent, StreamEventType};
+use crate::utils::config::Config;
+
+// longest wait between two attempts, whatever retry_backoff and retries say
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub on_stream_arrive: String,
+    pub on_stream_timeout: String,
+    pub on_stream_freed: String,
+    pub on_record_segment_done: String,
+    pub on_no_reader: String,
+    pub on_send_rtp_stopped: String,
+    pub on_talk_stopped: String,
+    pub on_download_progress: String,
+    pub on_download_complete: String,
+    // seconds per attempt
+    pub timeout: u64,
+    pub retries: u32,
+    // milliseconds, doubled on every retry up to a minute
+    pub retry_backoff: u64,
+    pub queue_size: usize,
+    pub concurrency: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            on_stream_arrive: String::new(),
+            on_stream_timeout: String::new(),
+            on_stream_freed: String::new(),
+            on_record_segment_done: String::new(),
+            on_no_reader: String::new(),
+            on_send_rtp_stopped: String::new(),
+            on_talk_stopped: String::new(),
+            on_download_progress: String::new(),
+            on_download_complete: String::new(),
+            timeout: 5,
+            retries: 3,
+            retry_backoff: 500,
+            queue_size: 1024,
+            concurrency: 8,
+        }
+    }
+}
+
+impl WebhookConfig {
+    // (hook name, url) of an event, None when not hooked
+    pub fn hook(&self, event_type: StreamEventType) -> Option<(&'static str, &str)> {
+        let (name, url) = match event_type {
+            StreamEventType::StreamArrive => ("on_stream_arrive", &self.on_stream_arrive),
+            StreamEventType::StreamTimeout => ("on_stream_timeout", &self.on_stream_timeout),
+            StreamEventType::StreamFreed => ("on_stream_freed", &self.on_stream_freed),
+            StreamEventType::RecordSegmentDone => {
+                ("on_record_segment_done", &self.on_record_segment_done)
+            }
+            StreamEventType::NoReader => ("on_no_reader", &self.on_no_reader),
+            StreamEventType::SendRtpStopped => ("on_send_rtp_stopped", &self.on_send_rtp_stopped),
+            StreamEventType::TalkStopped => ("on_talk_stopped", &self.on_talk_stopped),
+            StreamEventType::DownloadProgress => {
+                ("on_download_progress", &self.on_download_progress)
+            }
+            StreamEventType::DownloadComplete => {
+                ("on_download_complete", &self.on_download_complete)
+            }
+            _ => return None,
+        };
+        if url.is_empty() {
+            None
+        } else {
+            Some((name, url.as_str()))
+        }
+    }
+}
+
+struct Delivery {
+    hook: &'static str,
+    url: String,
+    body: serde_json::Value,
+}
+
+fn make_body(hook: &str, event: &StreamEvent) -> serde_json::Value {
+    serde_json::json!({
+        "hook": hook,
+        "gb_code": event.gb_code,
+        "stream_id": event.stream_id,
+        "port": event.media_server_port,
+        "ssrc": event.ssrc,
+        "message": event.message,
+        "timestamp": event.timestamp,
+        "stats": event.stats,
+    })
+}
+
+// doubled, a large retry_backoff or many retries stay at the cap
+fn next_backoff(backoff: Duration) -> Duration {
+    backoff.saturating_mul(2).min(MAX_BACKOFF)
+}
+
+async fn deliver(client: &reqwest::Client, config: &WebhookConfig, delivery: &Delivery) {
+    let mut backoff = Duration::from_millis(config.retry_backoff).min(MAX_BACKOFF);
+    for attempt in 0..=config.retries {
+        if attempt > 0 {
+            tokio::time::sleep(backoff).await;
+            backoff = next_backoff(backoff);
+        }
+
+        match client
+            .post(&delivery.url)
+            .timeout(Duration::from_secs(config.timeout))
+            .json(&delivery.body)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => {
+                tracing::debug!(
+                    "webhook {} delivered, url: {}",
+                    delivery.hook,
+                    &delivery.url
+                );
+                return;
+            }
+            Ok(response) => {
+                tracing::warn!(
+                    "webhook {} failed, url: {}, attempt: {}, status: {}",
+                    delivery.hook,
+                    &delivery.url,
+                    attempt,
+                    response.status()
+                );
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "webhook {} failed, url: {}, attempt: {}, e: {:?}",
+                    delivery.hook,
+                    &delivery.url,
+                    attempt,
+                    e
+                );
+            }
+        }
+    }
+    tracing::error!(
+        "webhook {} dropped after {} retries, url: {}",
+        delivery.hook,
+        config.retries,
+        &delivery.url
+    );
+}
+
+// deliveries queued or being posted, flush waits for none left
+#[derive(Default)]
+struct Outstanding {
+    count: AtomicUsize,
+    done: tokio::sync::Notify,
+}
+
+impl Outstanding {
+    fn add(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn done(&self) {
+        if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.done.notify_waiters();
+        }
+    }
+}
+
+pub struct Webhook {
+    // the events emitted so far are queued when the reply comes
+    barrier_tx: tokio::sync::mpsc::Sender<tokio::sync::oneshot::Sender<()>>,
+    outstanding: Arc<Outstanding>,
+}
+
+impl Webhook {
+    // waits until events emitted before are delivered or dropped
+    pub async fn flush(&self) {
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        if self.barrier_tx.send(reply_tx).await.is_ok() {
+            let _ = reply_rx.await;
+        }
+        loop {
+            // registered before the check, a notify in between is not lost
+            let done = self.outstanding.done.notified();
+            if self.outstanding.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            done.await;
+        }
+    }
+}
+
+fn enqueue(
+    config: &Config,
+    queue_tx: &tokio::sync::mpsc::Sender<Delivery>,
+    outstanding: &Outstanding,
+    event: &StreamEvent,
+) {
+    let Some((hook, url)) = config.webhook.hook(event.event_type()) else {
+        return;
+    };
+    let delivery = Delivery {
+        hook,
+        url: url.to_string(),
+        body: make_body(hook, event),
+    };
+    outstanding.add();
+    if let Err(e) = queue_tx.try_send(delivery) {
+        tracing::error!("webhook queue full, drop {}, e: {}", hook, e);
+        outstanding.done();
+    }
+}
+
+// events -> bounded queue -> http posts, a full queue drops instead of blocking.
+// urls and retry settings follow config reloads, queue size and concurrency are fixed
+pub fn start(
+    config_rx: tokio::sync::watch::Receiver<Arc<Config>>,
+    event_tx: &tokio::sync::broadcast::Sender<StreamEvent>,
+) -> Webhook {
+    let config = config_rx.borrow().webhook.clone();
+    let mut event_rx = event_tx.subscribe();
+    let (queue_tx, mut queue_rx) = tokio::sync::mpsc::channel::<Delivery>(config.queue_size.max(1));
+    let (barrier_tx, mut barrier_rx) = tokio::sync::mpsc::channel(1);
+
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(config.concurrency.max(1)));
+    let outstanding = Arc::new(Outstanding::default());
+    let webhook = Webhook {
+        barrier_tx,
+        outstanding: outstanding.clone(),
+    };
+
+    let filter_config_rx = config_rx.clone();
+    let filter_outstanding = outstanding.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                result = event_rx.recv() => match result {
+                    Ok(event) => {
+                        let config = filter_config_rx.borrow().clone();
+                        enqueue(&config, &queue_tx, &filter_outstanding, &event);
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("webhook events lagged, skipped: {}", n);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                Some(reply_tx) = barrier_rx.recv() => {
+                    // what is already in the channel, before the reply
+                    let config = filter_config_rx.borrow().clone();
+                    loop {
+                        match event_rx.try_recv() {
+                            Ok(event) => enqueue(&config, &queue_tx, &filter_outstanding, &event),
+                            Err(TryRecvError::Lagged(n)) => {
+                                tracing::warn!("webhook events lagged, skipped: {}", n);
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    let _ = reply_tx.send(());
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        while let Some(delivery) = queue_rx.recv().await {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
+            };
+            let client = client.clone();
+            let config = config_rx.borrow().clone();
+            let outstanding = outstanding.clone();
+            tokio::spawn(async move {
+                deliver(&client, &config.webhook, &delivery).await;
+                drop(permit);
+                outstanding.done();
+            });
+        }
+    });
+
+    webhook
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // answers each request with the next status, the last one repeats, and
+    // hands over the request bodies
+    async fn stand_in(
+        statuses: Vec<u16>,
+    ) -> (
+        String,
+        tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>,
+    ) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (body_tx, body_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for n in 0.. {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let body = loop {
+                    let mut buff = [0u8; 4096];
+                    let amount = stream.read(&mut buff).await.unwrap();
+                    request.extend_from_slice(&buff[..amount]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some(end) = text.find("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap();
+                    if request.len() >= end + 4 + length {
+                        break request[end + 4..end + 4 + length].to_vec();
+                    }
+                };
+                body_tx
+                    .send(serde_json::from_slice(&body).unwrap())
+                    .unwrap();
+                let status = statuses[n.min(statuses.len() - 1)];
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, body_rx)
+    }
+
+    fn config(on_stream_timeout: &str, retries: u32) -> Arc<Config> {
+        let mut config: Config = serde_yaml::from_str("{}").unwrap();
+        config.webhook.on_stream_timeout = on_stream_timeout.to_string();
+        config.webhook.retries = retries;
+        config.webhook.retry_backoff = 10;
+        Arc::new(config)
+    }
+
+    fn event(event_type: StreamEventType) -> StreamEvent {
+        StreamEvent {
+            event_type: event_type.into(),
+            gb_code: "34020000001320000001".to_string(),
+            stream_id: 7,
+            media_server_port: 10002,
+            ssrc: "0200000001".to_string(),
+            message: "no data for 20s".to_string(),
+            timestamp: 1700000000,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_the_event_and_retries_until_success() {
+        let (url, mut body_rx) = stand_in(vec![500, 503, 200]).await;
+        let (_config_tx, config_rx) = tokio::sync::watch::channel(config(&url, 3));
+        let (event_tx, _) = tokio::sync::broadcast::channel(16);
+        let webhook = start(config_rx, &event_tx);
+
+        event_tx
+            .send(event(StreamEventType::StreamTimeout))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), webhook.flush())
+            .await
+            .unwrap();
+
+        let mut bodies = vec![];
+        while let Ok(body) = body_rx.try_recv() {
+            bodies.push(body);
+        }
+        assert_eq!(bodies.len(), 3);
+        assert!(bodies.iter().all(|body| body == &bodies[0]));
+        let body = &bodies[0];
+        assert_eq!(body["hook"], "on_stream_timeout");
+        assert_eq!(body["gb_code"], "34020000001320000001");
+        assert_eq!(body["stream_id"], 7);
+        assert_eq!(body["port"], 10002);
+        assert_eq!(body["ssrc"], "0200000001");
+        assert_eq!(body["message"], "no data for 20s");
+        assert_eq!(body["timestamp"], 1700000000);
+        assert!(body.get("stats").is_some());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        let (url, mut body_rx) = stand_in(vec![500]).await;
+        let (_config_tx, config_rx) = tokio::sync::watch::channel(config(&url, 2));
+        let (event_tx, _) = tokio::sync::broadcast::channel(16);
+        let webhook = start(config_rx, &event_tx);
+
+        event_tx
+            .send(event(StreamEventType::StreamTimeout))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), webhook.flush())
+            .await
+            .unwrap();
+
+        let mut attempts = 0;
+        while body_rx.try_recv().is_ok() {
+            attempts += 1;
+        }
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn posts_record_segment_done() {
+        let (url, mut body_rx) = stand_in(vec![200]).await;
+        let mut config = (*config("", 0)).clone();
+        config.webhook.on_record_segment_done = url;
+        let (_config_tx, config_rx) = tokio::sync::watch::channel(Arc::new(config));
+        let (event_tx, _) = tokio::sync::broadcast::channel(16);
+        let webhook = start(config_rx, &event_tx);
+
+        event_tx
+            .send(event(StreamEventType::RecordSegmentDone))
+            .unwrap();
+        event_tx
+            .send(event(StreamEventType::StreamTimeout))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), webhook.flush())
+            .await
+            .unwrap();
+
+        let body = body_rx.try_recv().unwrap();
+        assert_eq!(body["hook"], "on_record_segment_done");
+        assert_eq!(body["port"], 10002);
+        assert!(body_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(
+            next_backoff(Duration::from_millis(500)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(next_backoff(Duration::from_secs(40)), MAX_BACKOFF);
+        assert_eq!(next_backoff(Duration::MAX), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn skips_events_without_a_hook() {
+        let (url, mut body_rx) = stand_in(vec![200]).await;
+        let (_config_tx, config_rx) = tokio::sync::watch::channel(config(&url, 0));
+        let (event_tx, _) = tokio::sync::broadcast::channel(16);
+        let webhook = start(config_rx, &event_tx);
+
+        event_tx.send(event(StreamEventType::StreamArrive)).unwrap();
+        event_tx.send(event(StreamEventType::SsrcMismatch)).unwrap();
+        event_tx
+            .send(event(StreamEventType::StreamTimeout))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), webhook.flush())
+            .await
+            .unwrap();
+
+        assert_eq!(body_rx.try_recv().unwrap()["hook"], "on_stream_timeout");
+        assert!(body_rx.try_recv().is_err());
+    }
+}